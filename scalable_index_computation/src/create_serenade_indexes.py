@@ -14,7 +14,8 @@ from src.index_components.sessionizer import Sessionizer
 from src.index_components.deidentifycolumn import DeIdentifyColumn
 
 
-def create_serenade_indexes(catalog_input_dir, qty_lookback_days, end_date, base_input_dir, base_output_dir):
+def create_serenade_indexes(catalog_input_dir, qty_lookback_days, end_date, base_input_dir, base_output_dir,
+                            string_item_ids=False):
     if not end_date:
         print('error: {} is an invalid formatted date. use something like: 2020-06-22'.format(end_date))
 
@@ -54,6 +55,14 @@ def create_serenade_indexes(catalog_input_dir, qty_lookback_days, end_date, base
     events10 = sql_context.read.format("avro").load(context_path)
     events10 = events10.filter((fn.col('qty_detailpage') + fn.col('qty_purchased')) > fn.lit(0))
     events10 = events10.withColumnRenamed('item_id', 'ItemId')
+    if string_item_ids:
+        # Dense internal ids in the sorted order of the external ids, like the IdDictionary of serenade. The mapping is
+        # written as itemdictionary.txt next to the index, one external id per line in the order of the internal ids.
+        item_dictionary = events10.select(fn.col('ItemId').alias('ExternalItemId')).distinct()
+        item_dictionary = item_dictionary.withColumn(
+            'ItemId', (fn.row_number().over(Window.orderBy('ExternalItemId')) - 1).cast(LongType()))
+        events10 = events10.withColumnRenamed('ItemId', 'ExternalItemId')
+        events10 = events10.join(item_dictionary, on=['ExternalItemId']).drop('ExternalItemId')
     events10 = events10.withColumn('ItemId', fn.col('ItemId').cast(LongType()))
     events10 = events10.withColumn('Time', (fn.col('timestamp') / fn.lit(1000)).cast(IntegerType()))  # milliseconds to seconds conversion
     events10 = events10.drop(*['timestamp'])
@@ -85,7 +94,13 @@ def create_serenade_indexes(catalog_input_dir, qty_lookback_days, end_date, base
     # start index3: item_id to session_idf score on the _raw_ population data
     qty_unique_session_ids = full_df.select(['VisitId']).distinct().count()
     print("qty_unique_session_ids:" + str(qty_unique_session_ids))
-    itemid_to_idf_df = full_df.groupBy(['ItemId']).agg(fn.log((qty_unique_session_ids / fn.count(fn.col('VisitId')))).alias('idf'))
+    itemid_to_idf_df = full_df.groupBy(['ItemId']).agg(
+        fn.log((qty_unique_session_ids / fn.count(fn.col('VisitId')))).alias('idf'),
+        # raw counts, so that serenade can compute other item weightings
+        fn.countDistinct(fn.col('VisitId')).alias('qty_sessions'),
+        fn.count(fn.col('VisitId')).alias('qty_interactions')
+    )
+    qty_interactions = full_df.count()
     # end index3: item_id to session_idf score on the _raw_ population data
 
     # start index1: item to top-m most recent session_ids (but with the original session_ids)
@@ -139,6 +154,9 @@ def create_serenade_indexes(catalog_input_dir, qty_lookback_days, end_date, base
     catalog = sql_context.read.format("avro").load(catalog_input_dir)
     # Rename globalId to ItemId so we can easily join later on
     catalog = catalog.withColumnRenamed('globalId', 'ItemId')
+    if string_item_ids:
+        catalog = catalog.withColumnRenamed('ItemId', 'ExternalItemId')
+        catalog = catalog.join(item_dictionary, on=['ExternalItemId']).drop('ExternalItemId')
     # Greatly reduce the catalog size by filtering only the item_ids in the final output
     unique_item_ids = item_index.select(['ItemId'])
     catalog = catalog.join(unique_item_ids, on=['ItemId'])
@@ -163,8 +181,15 @@ def create_serenade_indexes(catalog_input_dir, qty_lookback_days, end_date, base
     item_index_joined = item_index_joined.na.fill(value=False, subset=['ForSale', 'IsAdult'])
 
     # write files. The ForSale and IsAdult fields can be null.
-    item_index_joined.select(['ItemId', 'session_indices_time_ordered', 'idf', 'ForSale', 'IsAdult']).repartition(25).write.mode("overwrite").format("avro").save(base_output_dir + "/avro/itemindex")
+    item_index_joined.select(['ItemId', 'session_indices_time_ordered', 'idf', 'ForSale', 'IsAdult', 'qty_sessions', 'qty_interactions']).repartition(25).write.mode("overwrite").format("avro").save(base_output_dir + "/avro/itemindex")
+    # item_weighting names the formula of the idf column for the serving, see ItemWeighting::IdfSpark
+    metadata_df = sql_context.createDataFrame([(qty_unique_session_ids, qty_interactions, 'idf_spark')], ['qty_sessions', 'qty_interactions', 'item_weighting'])
+    metadata_df.repartition(1).write.mode("overwrite").format("avro").save(base_output_dir + "/avro/metadata")
     sessionindex_to_items_and_time_df.select(['SessionIndex', 'item_ids_asc', 'Time']).repartition(25).write.mode("overwrite").format("avro").save(base_output_dir + "/avro/sessionindex")
+    if string_item_ids:
+        # coalesce keeps the order of the internal ids in a single part file
+        item_dictionary_text = item_dictionary.orderBy('ItemId').select(['ExternalItemId']).coalesce(1)
+        item_dictionary_text.write.mode("overwrite").text(base_output_dir + "/avro/itemdictionary.txt")
 
     # start: we also extract smaller indices from the production data for fast development
     # this global_id is also defined in the kubernetes readiness config for the rolling deployment
@@ -191,6 +216,8 @@ def create_serenade_indexes(catalog_input_dir, qty_lookback_days, end_date, base
         fn.max(fn.col('idf')).alias('idf'),
         fn.max(fn.col('ForSale')).alias('ForSale'),
         fn.max(fn.col('IsAdult')).alias('IsAdult'),
+        fn.max(fn.col('qty_sessions')).alias('qty_sessions'),
+        fn.max(fn.col('qty_interactions')).alias('qty_interactions'),
         # 'session_indices_time_ordered' might no longer be ordered but this is ok for this toy dataset.
         fn.collect_list(fn.col('SessionIndex')).alias('session_indices_time_ordered'),
     )
@@ -205,10 +232,13 @@ def create_serenade_indexes(catalog_input_dir, qty_lookback_days, end_date, base
         fn.sort_array(fn.collect_list(fn.col('ItemId'))).alias('item_ids_asc'),
     )
 
-    reduced_item_index_30.select(['ItemId', 'session_indices_time_ordered', 'idf', 'ForSale', 'IsAdult']).repartition(
+    reduced_item_index_30.select(['ItemId', 'session_indices_time_ordered', 'idf', 'ForSale', 'IsAdult', 'qty_sessions', 'qty_interactions']).repartition(
         2).write.mode("overwrite").format("avro").save(base_output_dir + "/sampled-avro/itemindex")
+    metadata_df.repartition(1).write.mode("overwrite").format("avro").save(base_output_dir + "/sampled-avro/metadata")
     reduced_session_index_30.select(['SessionIndex', 'item_ids_asc', 'Time']).repartition(2).write.mode(
         "overwrite").format("avro").save(base_output_dir + "/sampled-avro/sessionindex")
+    if string_item_ids:
+        item_dictionary_text.write.mode("overwrite").text(base_output_dir + "/sampled-avro/itemdictionary.txt")
     # end: we also extract smaller indices from the production data for fast development
 
 if __name__ == "__main__":
@@ -218,6 +248,8 @@ if __name__ == "__main__":
     parser.add_argument("end_date")
     parser.add_argument("base_input_dir")   # base_input_dir='gs://my-google-cloud-data/fct/clicks/'
     parser.add_argument("base_output_dir")
+    # for string item ids such as SKUs, which are encoded and written to itemdictionary.txt
+    parser.add_argument("--string_item_ids", action="store_true")
     args = parser.parse_args()
     print(args)
     create_serenade_indexes(**vars(args))
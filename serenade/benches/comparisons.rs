@@ -4,19 +4,26 @@ extern crate rand;
 extern crate serenade_optimized;
 
 use bencher::Bencher;
-use hashbrown::{HashMap, HashSet as HashSetBrown, HashSet};
+use hashbrown::hash_map::Entry;
+use hashbrown::{HashMap, HashSet};
 use itertools::Itertools;
-use rand::Rng;
-use std::collections::BTreeSet as BTreeSetStd;
-use std::collections::HashSet as HashSetStd;
+use rand::rngs::StdRng;
+use rand::{Rng, SeedableRng};
+use std::collections::BinaryHeap;
+use std::path::Path;
+use std::sync::OnceLock;
 
 use rayon::prelude::*;
 
+use serenade_optimized::dataframeutils::TrainingDataStats;
 use serenade_optimized::io::{read_test_data_evolving, read_training_data};
-use serenade_optimized::io::{ItemId, Time, TrainingSessionId};
-use serenade_optimized::vmisknn::SessionTime;
+use serenade_optimized::io::{InputSchema, ItemId, Time, TrainingSessionId};
+use serenade_optimized::vmisknn;
+use serenade_optimized::vmisknn::offline_index::OfflineIndex;
+use serenade_optimized::vmisknn::similarity_indexed::SimilarityComputationNew;
+use serenade_optimized::vmisknn::{ItemScore, SessionTime};
 
-benchmark_group!(benches, zipper);
+benchmark_group!(benches, hashmap_scoring, dense_scoring);
 benchmark_main!(benches);
 
 const TRAIN_DATA_FILE: &str = "../serenade/datasets/private-clicks-100k_train.txt";
@@ -24,12 +31,212 @@ const TEST_DATA_FILE: &str = "../serenade/datasets/private-clicks-100k_test.txt"
 const SAMPLE_SIZE_M: usize = 5000;
 const MAX_SESSION_ITEMS: usize = 27;
 
-fn zipper(bench: &mut Bencher) {
-    let training_data: Vec<(TrainingSessionId, ItemId, Time)> = read_training_data(TRAIN_DATA_FILE);
+// Synthetic data, used when the private dataset is not available.
+const NUM_HISTORY_SESSIONS: usize = 200_000;
+const NUM_ITEMS: u64 = 100_000;
+const NUM_EVOLVING_SESSIONS: usize = 100;
+
+const M_MOST_RECENT_SESSIONS: usize = 500;
+const NEIGHBORHOOD_SIZE_K: usize = 500;
+const NUM_ITEMS_TO_RECOMMEND: usize = 21;
+
+/// The index, the evolving sessions and a copy of the index in the previous layout, keyed by external item id.
+struct Fixture {
+    index: OfflineIndex,
+    evolving_sessions: Vec<Vec<ItemId>>,
+    session_to_items_sorted: Vec<Vec<ItemId>>,
+    item_to_idf_score: HashMap<ItemId, f64>,
+}
+
+// bencher calls the benchmark functions repeatedly, so the fixture is only built once.
+static FIXTURE: OnceLock<Fixture> = OnceLock::new();
+
+fn fixture() -> &'static Fixture {
+    FIXTURE.get_or_init(|| {
+        let (historical_sessions, max_time_stamps, evolving_sessions) =
+            if Path::new(TRAIN_DATA_FILE).is_file() && Path::new(TEST_DATA_FILE).is_file() {
+                read_dataset()
+            } else {
+                println!("{} not found, using synthetic data", TRAIN_DATA_FILE);
+                create_synthetic_dataset()
+            };
+        let qty_sessions = historical_sessions.len() as u32;
+        let training_data_stats = TrainingDataStats {
+            qty_events_p99_5: MAX_SESSION_ITEMS as u64,
+            ..TrainingDataStats::default()
+        };
+        let index = OfflineIndex::new_from_sessions(
+            historical_sessions,
+            max_time_stamps,
+            training_data_stats,
+            M_MOST_RECENT_SESSIONS,
+        );
+
+        let session_to_items_sorted = (0..qty_sessions)
+            .map(|session_id| {
+                index
+                    .items_for_session(&session_id)
+                    .map(|item| index.external_item_id(&item))
+                    .collect()
+            })
+            .collect();
+        let item_to_idf_score = (0..index.qty_items() as u32)
+            .filter(|item| !index.idf(item).is_nan())
+            .map(|item| (index.external_item_id(&item), index.idf(&item)))
+            .collect();
+
+        Fixture {
+            index,
+            evolving_sessions,
+            session_to_items_sorted,
+            item_to_idf_score,
+        }
+    })
+}
+
+/// The scoring before the dense internal item ids: an idf lookup per item and neighbor session in a hash map,
+/// and a hash map to accumulate the item scores.
+fn hashmap_scoring(bench: &mut Bencher) {
+    let fixture = fixture();
+
+    bench.iter(|| {
+        for evolving_session in fixture.evolving_sessions.iter() {
+            bencher::black_box(predict_with_hashmaps(fixture, evolving_session));
+        }
+    });
+}
+
+fn dense_scoring(bench: &mut Bencher) {
+    let fixture = fixture();
+
+    bench.iter(|| {
+        for evolving_session in fixture.evolving_sessions.iter() {
+            bencher::black_box(vmisknn::predict(
+                &fixture.index,
+                evolving_session,
+                NEIGHBORHOOD_SIZE_K,
+                M_MOST_RECENT_SESSIONS,
+                NUM_ITEMS_TO_RECOMMEND,
+                false,
+            ));
+        }
+    });
+}
+
+fn predict_with_hashmaps(fixture: &Fixture, evolving_session: &[ItemId]) -> BinaryHeap<ItemScore> {
+    let neighbors =
+        fixture
+            .index
+            .find_neighbors(evolving_session, NEIGHBORHOOD_SIZE_K, M_MOST_RECENT_SESSIONS);
+
+    let mut item_scores: HashMap<ItemId, f64> = HashMap::with_capacity(1000);
+
+    for scored_session in neighbors.into_iter() {
+        let training_item_ids = &fixture.session_to_items_sorted[scored_session.id as usize];
+
+        let (first_match_index, _) = evolving_session
+            .iter()
+            .rev()
+            .enumerate()
+            .find(|(_, item_id)| training_item_ids.contains(*item_id))
+            .unwrap();
+
+        let session_weight = linear_score(first_match_index + 1);
+
+        for item_id in training_item_ids.iter() {
+            let item_idf = fixture.item_to_idf_score[item_id];
+            *item_scores.entry(*item_id).or_insert(0.0) +=
+                session_weight * item_idf * scored_session.score;
+        }
+    }
+
+    let most_recent_item = *evolving_session.last().unwrap();
+    if let Entry::Occupied(entry) = item_scores.entry(most_recent_item) {
+        entry.remove_entry();
+    }
+
+    let mut top_items: BinaryHeap<ItemScore> = BinaryHeap::with_capacity(NUM_ITEMS_TO_RECOMMEND);
+    for (id, score) in item_scores.into_iter() {
+        if top_items.len() < NUM_ITEMS_TO_RECOMMEND {
+            top_items.push(ItemScore { id, score });
+        } else {
+            let mut bottom = top_items.peek_mut().unwrap();
+            if score > bottom.score {
+                *bottom = ItemScore { id, score };
+            }
+        }
+    }
+    top_items
+}
+
+fn linear_score(pos: usize) -> f64 {
+    if pos < 100 {
+        1.0 - (0.1 * pos as f64)
+    } else {
+        0.0
+    }
+}
+
+type Dataset = (Vec<Vec<ItemId>>, Vec<u32>, Vec<Vec<ItemId>>);
+
+fn read_dataset() -> Dataset {
+    let training_data = read_training_data(TRAIN_DATA_FILE, &InputSchema::default());
     let training_data = sanitize_training_data(training_data, SAMPLE_SIZE_M, MAX_SESSION_ITEMS);
-    println!("{}", training_data.len());
-    let test_data: HashMap<TrainingSessionId, Vec<ItemId>> =
-        read_test_data_evolving(TEST_DATA_FILE);
+    println!("{} training events", training_data.len());
+
+    let (historical_sessions, max_time_stamps): (Vec<Vec<ItemId>>, Vec<u32>) = training_data
+        .into_iter()
+        .map(|(session_id, item_id, time)| (session_id, (item_id, time)))
+        .into_group_map()
+        .into_iter()
+        .sorted_by_key(|(session_id, _)| *session_id)
+        .map(|(_session_id, items_with_time)| {
+            let max_time_stamp = items_with_time.iter().map(|(_, time)| *time).max().unwrap();
+            let items = items_with_time
+                .into_iter()
+                .map(|(item_id, _)| item_id)
+                .sorted()
+                .dedup()
+                .collect();
+            (items, max_time_stamp as u32)
+        })
+        .unzip();
+
+    let evolving_sessions = read_test_data_evolving(TEST_DATA_FILE, &InputSchema::default())
+        .into_iter()
+        .sorted_by_key(|(session_id, _)| *session_id)
+        .map(|(_session_id, items)| items)
+        .collect();
+
+    (historical_sessions, max_time_stamps, evolving_sessions)
+}
+
+// Item popularity is skewed towards low item ids, like in click data.
+fn random_item(rng: &mut StdRng) -> ItemId {
+    (rng.gen::<f64>().powi(3) * NUM_ITEMS as f64) as ItemId
+}
+
+fn create_synthetic_dataset() -> Dataset {
+    let mut rng = StdRng::seed_from_u64(42);
+    let mut historical_sessions = Vec::with_capacity(NUM_HISTORY_SESSIONS);
+    let mut max_time_stamps = Vec::with_capacity(NUM_HISTORY_SESSIONS);
+    for session_id in 0..NUM_HISTORY_SESSIONS {
+        let qty_items = rng.gen_range(2..=MAX_SESSION_ITEMS);
+        let items: Vec<ItemId> = (0..qty_items)
+            .map(|_| random_item(&mut rng))
+            .sorted()
+            .dedup()
+            .collect();
+        historical_sessions.push(items);
+        max_time_stamps.push(1_600_000_000 + session_id as u32 * 5);
+    }
+    let evolving_sessions = (0..NUM_EVOLVING_SESSIONS)
+        .map(|_| {
+            let qty_items = rng.gen_range(1..=3);
+            (0..qty_items).map(|_| random_item(&mut rng)).collect()
+        })
+        .collect();
+    (historical_sessions, max_time_stamps, evolving_sessions)
 }
 
 fn sanitize_training_data(
@@ -50,14 +257,12 @@ fn sanitize_training_data(
     let filtered_training_data: Vec<(TrainingSessionId, ItemId, Time)> = training_data
         .par_iter()
         .filter(|(session_id, _item_id, _time)| session_ids.contains(session_id))
-        .map(|(session_id, item_id, time)| (session_id.clone(), item_id.clone(), time.clone()))
+        .cloned()
         .collect();
 
     let valid_session_ids: HashSet<u32> = filtered_training_data
         .iter()
-        .map(|(session_id, item_id, time)| {
-            (item_id, SessionTime::new(session_id.clone(), time.clone()))
-        })
+        .map(|(session_id, item_id, time)| (item_id, SessionTime::new(*session_id, *time as u32)))
         .into_group_map()
         .into_iter()
         .flat_map(|(_item_id, mut session_id_with_time)| {
@@ -83,6 +288,5 @@ fn sanitize_training_data(
     training_data
         .into_iter()
         .filter(|training_event| valid_session_ids.contains(&training_event.0))
-        .cloned()
         .collect()
 }
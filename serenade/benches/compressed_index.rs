@@ -0,0 +1,119 @@
+#[macro_use]
+extern crate bencher;
+extern crate rand;
+extern crate serenade_optimized;
+
+use bencher::Bencher;
+use rand::rngs::StdRng;
+use rand::{Rng, SeedableRng};
+use std::sync::OnceLock;
+
+use serenade_optimized::dataframeutils::TrainingDataStats;
+use serenade_optimized::vmisknn;
+use serenade_optimized::vmisknn::compressed_index::CompressedOfflineIndex;
+use serenade_optimized::vmisknn::offline_index::OfflineIndex;
+
+const NUM_HISTORY_SESSIONS: usize = 200_000;
+const NUM_ITEMS: u64 = 100_000;
+const MAX_NUM_ITEMS_IN_HISTORY_SESSION: usize = 20;
+const NUM_EVOLVING_SESSIONS: usize = 20;
+const M_MOST_RECENT_SESSIONS: usize = 500;
+const NEIGHBORHOOD_SIZE_K: usize = 500;
+const NUM_ITEMS_TO_RECOMMEND: usize = 21;
+
+benchmark_group!(benches, bench_uncompressed, bench_compressed);
+benchmark_main!(benches);
+
+// bencher calls the benchmark functions repeatedly, so the indexes are only built once.
+static UNCOMPRESSED_INDEX: OnceLock<OfflineIndex> = OnceLock::new();
+static COMPRESSED_INDEX: OnceLock<CompressedOfflineIndex> = OnceLock::new();
+
+fn bench_uncompressed(bench: &mut Bencher) {
+    let index = UNCOMPRESSED_INDEX.get_or_init(|| {
+        let index = create_index();
+        println!(
+            "uncompressed posting lists and session lists: {} MB",
+            index.lists_size_in_bytes() / 1_000_000
+        );
+        index
+    });
+    let evolving_sessions = create_evolving_sessions();
+
+    bench.iter(|| {
+        for evolving_session in evolving_sessions.iter() {
+            bencher::black_box(predict(index, evolving_session));
+        }
+    });
+}
+
+fn bench_compressed(bench: &mut Bencher) {
+    let index = COMPRESSED_INDEX.get_or_init(|| {
+        let index = CompressedOfflineIndex::new(create_index());
+        println!(
+            "compressed posting lists and session lists: {} MB",
+            index.lists_size_in_bytes() / 1_000_000
+        );
+        index
+    });
+    let evolving_sessions = create_evolving_sessions();
+
+    bench.iter(|| {
+        for evolving_session in evolving_sessions.iter() {
+            bencher::black_box(predict(index, evolving_session));
+        }
+    });
+}
+
+fn predict<I>(index: &I, evolving_session: &[u64]) -> usize
+where
+    I: vmisknn::similarity_indexed::SimilarityComputationNew + Send + Sync,
+{
+    vmisknn::predict(
+        index,
+        evolving_session,
+        NEIGHBORHOOD_SIZE_K,
+        M_MOST_RECENT_SESSIONS,
+        NUM_ITEMS_TO_RECOMMEND,
+        false,
+    )
+    .len()
+}
+
+// Item popularity is skewed towards low item ids, like in click data.
+fn random_item(rng: &mut StdRng) -> u64 {
+    (rng.gen::<f64>().powi(3) * NUM_ITEMS as f64) as u64
+}
+
+fn create_index() -> OfflineIndex {
+    let mut rng = StdRng::seed_from_u64(42);
+    let mut historical_sessions = Vec::with_capacity(NUM_HISTORY_SESSIONS);
+    let mut max_time_stamps = Vec::with_capacity(NUM_HISTORY_SESSIONS);
+    for session_id in 0..NUM_HISTORY_SESSIONS {
+        let qty_items = rng.gen_range(2..=MAX_NUM_ITEMS_IN_HISTORY_SESSION);
+        let mut items: Vec<u64> = (0..qty_items).map(|_| random_item(&mut rng)).collect();
+        items.sort_unstable();
+        items.dedup();
+        historical_sessions.push(items);
+        max_time_stamps.push(1_600_000_000 + session_id as u32 * 5);
+    }
+    let training_data_stats = TrainingDataStats {
+        qty_events_p99_5: MAX_NUM_ITEMS_IN_HISTORY_SESSION as u64,
+        ..TrainingDataStats::default()
+    };
+    OfflineIndex::new_from_sessions(
+        historical_sessions,
+        max_time_stamps,
+        training_data_stats,
+        M_MOST_RECENT_SESSIONS,
+    )
+}
+
+fn create_evolving_sessions() -> Vec<Vec<u64>> {
+    let mut rng = StdRng::seed_from_u64(7);
+    (0..NUM_EVOLVING_SESSIONS)
+        .map(|_| {
+            let qty_items = rng.gen_range(1..=3);
+            (0..qty_items).map(|_| random_item(&mut rng)).collect()
+        })
+        .collect()
+}
@@ -0,0 +1,50 @@
+extern crate serenade_optimized;
+
+use std::path::Path;
+
+use serenade_optimized::config::AppConfig;
+use serenade_optimized::recommender::RecommendParams;
+use serenade_optimized::vmisknn::offline_index::OfflineIndex;
+use serenade_optimized::vmisknn::single_item_table::{SingleItemTable, SINGLE_ITEM_TABLE_FILE_NAME};
+
+fn main() {
+    // Precomputes the recommendations of the single-item sessions for the index directory of the config, so that
+    // the serving instances read the table instead of building it at start.
+    let config_path = std::env::args()
+        .nth(1)
+        .expect("Config file not specified!");
+    let config = AppConfig::new(config_path);
+    let index_path = Path::new(&config.data.training_data_path);
+    assert!(
+        index_path.is_dir(),
+        "The training data is not an index directory: {}",
+        &config.data.training_data_path
+    );
+
+    let mut index = OfflineIndex::new(&config.data.training_data_path);
+    index.set_weighting(config.model.weighting);
+    if let Some(item_weighting) = config.model.item_weighting {
+        index.set_item_weighting(item_weighting);
+    }
+    index.set_event_type_weights(&config.model.event_type_weights);
+    index.set_product_groups(config.model.product_groups);
+    // The product groups of the item attributes change the recommendations, like in the serving instances.
+    if let Some(item_attributes_path) = &config.data.item_attributes_path {
+        index
+            .read_item_attributes(item_attributes_path)
+            .unwrap_or_else(|error| panic!("Reading the item attributes failed: {}", error));
+    }
+
+    let params = RecommendParams {
+        neighborhood_size_k: config.model.neighborhood_size_k,
+        m_most_recent_sessions: config.model.m_most_recent_sessions,
+        how_many: config.model.num_items_to_recommend,
+        enable_business_logic: config.logic.enable_business_logic,
+    };
+    let table = SingleItemTable::build(&index, &params);
+    let table_path = index_path.join(SINGLE_ITEM_TABLE_FILE_NAME);
+    table
+        .write_to_file(&table_path.display().to_string())
+        .expect("Writing the single item table failed");
+    println!("wrote {} items to {}", table.len(), table_path.display());
+}
@@ -0,0 +1,119 @@
+use std::sync::Arc;
+
+use serenade_optimized::config::{read_input_schema, read_item_attributes_path, read_model_config};
+use serenade_optimized::io;
+use serenade_optimized::io::read_training_data;
+use serenade_optimized::metrics::evaluation_reporter::EvaluationReporter;
+use serenade_optimized::metrics::intra_list_diversity::IntraListDiversity;
+use serenade_optimized::recommender::{create_recommender, RecommendParams, RecommenderKind};
+use serenade_optimized::vmisknn::offline_index::OfflineIndex;
+
+/// Evaluates every recommender with the hyperparameters of the `[model]` section on the same test sessions.
+fn main() {
+    let qty_max_reco_results = 20;
+    let enable_business_logic = false;
+
+    let path_to_training = std::env::args()
+        .nth(1)
+        .expect("Training data file not specified!");
+    println!("result:training_data_file:{}", path_to_training);
+
+    let test_data_file = std::env::args()
+        .nth(2)
+        .expect("Test data file not specified!");
+    println!("result:test_data_file:{}", test_data_file);
+
+    // Optional: a config file with the layout of the data files in its `[data]` section
+    // and the hyperparameters of the recommenders in its `[model]` section.
+    let config_path = std::env::args().nth(3);
+    let input_schema = config_path
+        .as_deref()
+        .map(read_input_schema)
+        .unwrap_or_default();
+    let mut model_config = config_path
+        .as_deref()
+        .map(read_model_config)
+        .unwrap_or_default();
+    let max_items_in_session = model_config.max_items_in_session;
+
+    let mut offline_index = OfflineIndex::new_from_csv(
+        &path_to_training,
+        &input_schema,
+        model_config.m_most_recent_sessions,
+    );
+    offline_index.set_weighting(model_config.weighting);
+    if let Some(item_weighting) = model_config.item_weighting {
+        offline_index.set_item_weighting(item_weighting);
+    }
+    offline_index.set_product_groups(model_config.product_groups);
+    // With the categories and brands of the items, the diversity of the recommendations is reported as well.
+    let item_attributes_path = config_path.as_deref().and_then(read_item_attributes_path);
+    if let Some(item_attributes_path) = &item_attributes_path {
+        offline_index
+            .read_item_attributes(item_attributes_path)
+            .unwrap_or_else(|error| panic!("Reading the item attributes failed: {}", error));
+    }
+    let offline_index = Arc::new(offline_index);
+    let training_df = read_training_data(&path_to_training, &input_schema);
+    let create_evaluation_reporter = || {
+        let mut evaluation_reporter = EvaluationReporter::new(&training_df, qty_max_reco_results);
+        if item_attributes_path.is_some() {
+            evaluation_reporter.set_intra_list_diversity(IntraListDiversity::new(&offline_index, qty_max_reco_results));
+        }
+        evaluation_reporter
+    };
+
+    let ordered_test_sessions = match offline_index.item_dictionary() {
+        Some(item_dictionary) => io::read_test_data_evolving_with_dictionary(
+            &test_data_file,
+            &input_schema,
+            item_dictionary,
+        ),
+        None => io::read_test_data_evolving(&test_data_file, &input_schema),
+    };
+    let params = RecommendParams {
+        neighborhood_size_k: model_config.neighborhood_size_k,
+        m_most_recent_sessions: model_config.m_most_recent_sessions,
+        how_many: qty_max_reco_results,
+        enable_business_logic,
+    };
+
+    println!(
+        "result:recommender,{}",
+        create_evaluation_reporter().get_name()
+    );
+    // The ensemble of the config file is compared with its members.
+    let mut recommender_kinds = RecommenderKind::ALL.to_vec();
+    if !model_config.ensemble.members.is_empty() {
+        recommender_kinds.push(RecommenderKind::Ensemble);
+    }
+    for recommender_kind in recommender_kinds.iter() {
+        model_config.recommender = *recommender_kind;
+        let recommender = create_recommender(
+            &model_config,
+            offline_index.clone(),
+            &path_to_training,
+            &input_schema,
+            enable_business_logic,
+        );
+        let mut evaluation_reporter = create_evaluation_reporter();
+        ordered_test_sessions
+            .iter()
+            .for_each(|(_session_id, evolving_session_items)| {
+                for session_state in 1..evolving_session_items.len() {
+                    // use last x items of evolving session
+                    let start_index = session_state.saturating_sub(max_items_in_session);
+                    let session: &[u64] = &evolving_session_items[start_index..session_state];
+                    let recommended_items = recommender
+                        .recommend(session, &params)
+                        .iter()
+                        .map(|scored| scored.id)
+                        .collect::<Vec<u64>>();
+
+                    let actual_next_items = Vec::from(&evolving_session_items[session_state..]);
+                    evaluation_reporter.add(&recommended_items, &actual_next_items);
+                }
+            });
+        println!("result:{},{}", recommender_kind, evaluation_reporter.result());
+    }
+}
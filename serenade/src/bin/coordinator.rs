@@ -0,0 +1,102 @@
+extern crate serenade_optimized;
+
+use sessions::RocksDBSessionStore;
+
+use actix_web::client::Client;
+use actix_web::{http::ContentEncoding, middleware, App, HttpServer};
+use actix_web_prom::PrometheusMetrics;
+
+use std::path::Path;
+use std::sync::Arc;
+use std::time::Duration;
+
+use serenade_optimized::config::AppConfig;
+use serenade_optimized::dataframeutils::CoordinatorHandlesAndConfig;
+use serenade_optimized::endpoints::shard_resource::v1_recommend_sharded;
+use serenade_optimized::io::read_interactions;
+use serenade_optimized::sessions;
+use serenade_optimized::vmisknn::offline_index::read_item_dictionary;
+
+// Serves `/v1/recommend` for an index that is partitioned over several `serving` processes,
+// each started with a `shard_index` and `qty_shards`. The coordinator itself does not load the index.
+#[actix_web::main]
+async fn main() -> std::io::Result<()> {
+    let config_path = std::env::args().nth(1).unwrap_or_default();
+    let config = AppConfig::new(config_path);
+
+    let bind_address = format!("{}:{}", config.server.host, config.server.port);
+    let m_most_recent_sessions = config.model.m_most_recent_sessions;
+    let neighborhood_size_k = config.model.neighborhood_size_k;
+    let num_items_to_recommend = config.model.num_items_to_recommend;
+    let max_items_in_session = config.model.max_items_in_session;
+    let enable_business_logic = config.logic.enable_business_logic;
+    let weighting = config.model.weighting;
+    let product_groups = config.model.product_groups;
+    let event_type_weights = config.model.event_type_weights;
+
+    let shard_urls: Vec<String> = config
+        .sharding
+        .shard_addresses
+        .iter()
+        .map(|address| format!("http://{}", address))
+        .collect();
+    if shard_urls.is_empty() {
+        panic!("The coordinator requires shard_addresses in the [sharding] section");
+    }
+    println!("coordinating shards: {:?}", &shard_urls);
+
+    // The shards encode the string ids of the whole training data, so the coordinator encodes them the same way.
+    // An index directory ships the dictionary of its build.
+    let item_dictionary = if Path::new(&config.data.training_data_path).is_dir() {
+        read_item_dictionary(&config.data.training_data_path).map(Arc::new)
+    } else if config.data.input_schema.string_ids {
+        let training_data_path = &config.data.training_data_path;
+        let interactions = read_interactions(training_data_path, &config.data.input_schema)
+            .unwrap_or_else(|err| panic!("Unable to read {}: {}", training_data_path, err));
+        interactions.item_dictionary.map(Arc::new)
+    } else {
+        None
+    };
+
+    println!("start db");
+    let session_ttl = Duration::from_secs(30 * 60);
+    let db = Arc::new(RocksDBSessionStore::new("./sessions.db", session_ttl));
+
+    println!("start metrics");
+    let prometheus = PrometheusMetrics::new("api", Some("/internal/prometheus"), None);
+
+    println!("Done. start httpd at http://{}", &bind_address);
+    HttpServer::new(move || {
+        let handles_and_config = CoordinatorHandlesAndConfig {
+            session_store: db.clone(),
+            shard_client: Client::default(),
+            shard_urls: shard_urls.clone(),
+            m_most_recent_sessions,
+            neighborhood_size_k,
+            num_items_to_recommend,
+            max_items_in_session,
+            enable_business_logic,
+            weighting,
+            product_groups,
+            event_type_weights: event_type_weights.clone(),
+            item_dictionary: item_dictionary.clone(),
+        };
+
+        App::new()
+            .wrap(middleware::Compress::new(ContentEncoding::Identity))
+            .wrap(prometheus.clone())
+            .wrap(
+                middleware::DefaultHeaders::new()
+                    .header("Cache-Control", "no-cache, no-store, must-revalidate")
+                    .header("Pragma", "no-cache")
+                    .header("Expires", "0"),
+            )
+            .data(handles_and_config)
+            .service(v1_recommend_sharded)
+    })
+    .workers(config.server.num_workers)
+    .bind(&bind_address)
+    .unwrap_or_else(|_| panic!("Could not bind server to address {}", &bind_address))
+    .run()
+    .await
+}
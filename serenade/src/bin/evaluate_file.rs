@@ -1,5 +1,5 @@
 use itertools::Itertools;
-use serenade_optimized::io::read_training_data;
+use serenade_optimized::io::{read_training_data, InputSchema};
 use serenade_optimized::metrics::coverage::Coverage;
 use serenade_optimized::metrics::hitrate::HitRate;
 use serenade_optimized::metrics::mrr::Mrr;
@@ -16,7 +16,7 @@ fn main() {
     let training_data_path = "../datasets/private-clicks-1m_train.txt";
     let predictions_file = "../java_impl/java_vsknn_predictions.txt";
 
-    let training_df = read_training_data(&*training_data_path);
+    let training_df = read_training_data(training_data_path, &InputSchema::default());
 
     let mut ndcg = Ndcg::new(20);
     let mut mrr = Mrr::new(20);
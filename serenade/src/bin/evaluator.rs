@@ -1,4 +1,8 @@
-use serenade_optimized::{io, vmisknn};
+use std::sync::Arc;
+
+use serenade_optimized::config::{read_input_schema, read_model_config, ModelConfig};
+use serenade_optimized::io;
+use serenade_optimized::recommender::{create_recommender, RecommendParams};
 
 use serenade_optimized::metrics::mrr::Mrr;
 use serenade_optimized::metrics::SessionMetric;
@@ -22,11 +26,48 @@ fn main() {
         .expect("Test data file not specified!");
     println!("test_data_file:{}", test_data_file);
 
-    let offline_index = OfflineIndex::new_from_csv(&*path_to_training, n_most_recent_sessions);
+    // Optional: a config file with the layout of the data files in its `[data]` section
+    // and the recommender and its weighting in its `[model]` section.
+    let config_path = std::env::args().nth(3);
+    let input_schema = config_path
+        .as_deref()
+        .map(read_input_schema)
+        .unwrap_or_default();
+    let model_config = ModelConfig {
+        m_most_recent_sessions: n_most_recent_sessions,
+        neighborhood_size_k,
+        ..config_path.as_deref().map(read_model_config).unwrap_or_default()
+    };
+    println!("recommender:{}", model_config.recommender);
+
+    let mut offline_index =
+        OfflineIndex::new_from_csv(&path_to_training, &input_schema, n_most_recent_sessions);
+    offline_index.set_weighting(model_config.weighting);
+    let offline_index = Arc::new(offline_index);
+    let recommender = create_recommender(
+        &model_config,
+        offline_index.clone(),
+        &path_to_training,
+        &input_schema,
+        enable_business_logic,
+    );
 
-    let ordered_test_sessions = io::read_test_data_evolving(&*test_data_file);
+    let ordered_test_sessions = match offline_index.item_dictionary() {
+        Some(item_dictionary) => io::read_test_data_evolving_with_dictionary(
+            &test_data_file,
+            &input_schema,
+            item_dictionary,
+        ),
+        None => io::read_test_data_evolving(&test_data_file, &input_schema),
+    };
 
     let qty_max_reco_results = 20;
+    let params = RecommendParams {
+        neighborhood_size_k,
+        m_most_recent_sessions: n_most_recent_sessions,
+        how_many: qty_max_reco_results,
+        enable_business_logic,
+    };
     let mut mymetric = Mrr::new(qty_max_reco_results);
 
     ordered_test_sessions
@@ -40,17 +81,9 @@ fn main() {
                     0
                 };
                 let session: &[u64] = &evolving_session_items[start_index..session_state];
-                let recommendations = vmisknn::predict(
-                    &offline_index,
-                    &session,
-                    neighborhood_size_k,
-                    n_most_recent_sessions,
-                    qty_max_reco_results,
-                    enable_business_logic,
-                );
+                let recommendations = recommender.recommend(session, &params);
 
                 let recommended_items = recommendations
-                    .into_sorted_vec()
                     .iter()
                     .map(|scored| scored.id)
                     .collect::<Vec<u64>>();
@@ -1,9 +1,25 @@
+use serenade_optimized::config::{read_input_schema, read_model_config, ModelConfig};
+use serenade_optimized::dictionary::IdDictionary;
+use serenade_optimized::ensemble::{EnsembleRecommender, Fusion};
 use serenade_optimized::hyperparameter::hyperparamgrid::HyperParamGrid;
+use serenade_optimized::io;
+use serenade_optimized::io::InputSchema;
 use serenade_optimized::metrics::mrr::Mrr;
 use serenade_optimized::metrics::SessionMetric;
+use serenade_optimized::io::read_training_data;
+use serenade_optimized::recommender::{create_recommender_of_kind, RecommendParams, Recommender, RecommenderKind};
+use serenade_optimized::rules::association_rules::AssociationRules;
+use serenade_optimized::rules::sequential_rules::SequentialRules;
 use serenade_optimized::vmisknn::offline_index::OfflineIndex;
-use serenade_optimized::{io, vmisknn};
+use serenade_optimized::vmisknn::stan_index::{StanIndex, StanParameters};
+use serenade_optimized::vmisknn::tree_index::TreeIndex;
+use serenade_optimized::vmisknn::vmisknn_index::VMISSkNNIndex;
+use serenade_optimized::vmisknn::vmisknn_index_noopt::VMISSkNNIndexNoOpt;
+use serenade_optimized::vmisknn::vmisknn_index_smallopt::VMISSkNNIndexSmallOpt;
+use serenade_optimized::vmisknn::vsknn_index::VSkNNIndex;
+use serenade_optimized::vmisknn::weighting::{ItemWeighting, Weighting, WeightingFunction};
 use std::collections::HashMap;
+use std::sync::Arc;
 
 fn main() {
     let mut param_grid = HashMap::new();
@@ -16,6 +32,19 @@ fn main() {
         vec![50, 100, 500, 1000, 1500],
     );
     param_grid.insert("max_items_in_session".to_string(), vec![1, 2, 3, 5, 7, 15, 100]);
+    // Positions in `WeightingFunction::ALL`, because the grid only contains numbers.
+    let weighting_functions: Vec<usize> = (0..WeightingFunction::ALL.len()).collect();
+    param_grid.insert("position_decay".to_string(), weighting_functions.clone());
+    param_grid.insert("match_weight".to_string(), weighting_functions);
+    param_grid.insert(
+        "item_weighting".to_string(),
+        (0..ItemWeighting::ALL.len()).collect(),
+    );
+    // 0 disables the recency weighting, the other values are an hour, a day, a week and four weeks.
+    param_grid.insert(
+        "recency_half_life_secs".to_string(),
+        vec![0, 3_600, 86_400, 604_800, 2_419_200],
+    );
 
     let qty_max_reco_results = 21;
 
@@ -29,10 +58,61 @@ fn main() {
         .expect("Test data file not specified!");
     println!("test_data_file:{}", test_data_file);
 
+    // Optional: a config file with the layout of the data files in its `[data]` section
+    // and the recommender to tune in its `[model]` section.
+    let config_path = std::env::args().nth(3);
+    let input_schema = config_path
+        .as_deref()
+        .map(read_input_schema)
+        .unwrap_or_default();
+    let model_config = config_path
+        .as_deref()
+        .map(read_model_config)
+        .unwrap_or_default();
+    let recommender_kind = model_config.recommender;
+    match recommender_kind {
+        RecommenderKind::VmisKnn => {}
+        RecommenderKind::Ensemble => {
+            tune_ensemble(
+                &model_config,
+                &path_to_training,
+                &test_data_file,
+                &input_schema,
+                qty_max_reco_results,
+            );
+            return;
+        }
+        RecommenderKind::Stan => {
+            tune_stan(&path_to_training, &test_data_file, &input_schema, qty_max_reco_results);
+            return;
+        }
+        RecommenderKind::SequentialRules | RecommenderKind::AssociationRules => {
+            tune_rules(
+                recommender_kind,
+                &path_to_training,
+                &test_data_file,
+                &input_schema,
+                qty_max_reco_results,
+            );
+            return;
+        }
+        _ => {
+            tune_neighborhood(
+                recommender_kind,
+                &path_to_training,
+                &test_data_file,
+                &input_schema,
+                qty_max_reco_results,
+            );
+            return;
+        }
+    }
+
     let hyper_parametergrid = HyperParamGrid { param_grid };
 
     let mut best_score = 0.0;
     let mut best_params = HashMap::new();
+    let mut best_weighting = Weighting::default();
     let main_metric_name = Mrr::new(20).get_name();
 
     let chosen_hyperparameters = hyper_parametergrid.get_n_random_combinations(150);
@@ -40,57 +120,436 @@ fn main() {
         let max_items_in_session = *hyperparams.get("max_items_in_session").unwrap();
         let neighborhood_size_k = *hyperparams.get("neighborhood_size_k").unwrap();
         let m_most_recent_sessions = *hyperparams.get("m_most_recent_sessions").unwrap();
+        let weighting = Weighting {
+            position_decay: WeightingFunction::ALL[*hyperparams.get("position_decay").unwrap()],
+            match_weight: WeightingFunction::ALL[*hyperparams.get("match_weight").unwrap()],
+            recency_half_life_secs: match *hyperparams.get("recency_half_life_secs").unwrap() {
+                0 => None,
+                half_life_secs => Some(half_life_secs as u32),
+            },
+        };
+        let item_weighting = ItemWeighting::ALL[*hyperparams.get("item_weighting").unwrap()];
         let enable_business_logic = false;
 
         if neighborhood_size_k <= m_most_recent_sessions {
-            let vsknn_index = OfflineIndex::new_from_csv(&*path_to_training, m_most_recent_sessions);
-            let ordered_test_sessions = io::read_test_data_evolving(&*test_data_file);
-            let mut mymetric = Mrr::new(20);
-            ordered_test_sessions
-                .iter()
-                .for_each(|(_session_id, evolving_session_items)| {
-                    for session_state in 1..evolving_session_items.len() {
-                        // use last x items of evolving session
-                        let start_index = if session_state > max_items_in_session {
-                            session_state - max_items_in_session
-                        } else {
-                            0
-                        };
-                        let session: &[u64] = &evolving_session_items[start_index..session_state];
-                        let recommendations = vmisknn::predict(
-                            &vsknn_index,
-                            &session,
-                            neighborhood_size_k,
-                            m_most_recent_sessions,
-                            qty_max_reco_results,
-                            enable_business_logic,
-                        );
-
-                        let recommended_items = recommendations
-                            .into_sorted_vec()
-                            .iter()
-                            .map(|scored| scored.id)
-                            .collect::<Vec<u64>>();
-
-                        let actual_next_items = Vec::from(&evolving_session_items[session_state..]);
-                        mymetric.add(&recommended_items, &actual_next_items);
-                    }
-                });
+            let mut vsknn_index =
+                OfflineIndex::new_from_csv(&path_to_training, &input_schema, m_most_recent_sessions);
+            vsknn_index.set_weighting(weighting);
+            vsknn_index.set_item_weighting(item_weighting);
+            let ordered_test_sessions = match vsknn_index.item_dictionary() {
+                Some(item_dictionary) => io::read_test_data_evolving_with_dictionary(
+                    &test_data_file,
+                    &input_schema,
+                    item_dictionary,
+                ),
+                None => io::read_test_data_evolving(&test_data_file, &input_schema),
+            };
+            let params = RecommendParams {
+                neighborhood_size_k,
+                m_most_recent_sessions,
+                how_many: qty_max_reco_results,
+                enable_business_logic,
+            };
+            let mymetric = evaluate(
+                &vsknn_index,
+                &ordered_test_sessions,
+                max_items_in_session,
+                &params,
+            );
             if mymetric.result() > best_score {
                 best_score = mymetric.result();
                 best_params = hyperparams.clone();
+                best_weighting = weighting;
             }
             println!(
-                "HPO,{},{},{},{}",
+                "HPO,{},{},{},{},{},{},{},{}",
                 m_most_recent_sessions,
                 neighborhood_size_k,
                 max_items_in_session,
+                weighting.position_decay,
+                weighting.match_weight,
+                weighting.recency_half_life_secs.unwrap_or(0),
+                item_weighting,
                 mymetric.result()
             );
         }
     }
+    println!(
+        "Best hyperparameter values found:,{:?} position_decay={} match_weight={} with {}:{}",
+        best_params,
+        best_weighting.position_decay,
+        best_weighting.match_weight,
+        main_metric_name,
+        best_score
+    );
+}
+
+/// Tunes the neighborhood and the three decay parameters of STAN.
+fn tune_stan(path_to_training: &str, test_data_file: &str, input_schema: &InputSchema, qty_max_reco_results: usize) {
+    // The grid only contains numbers, so the decay parameters are positions in these lists.
+    let lambdas = [0.25, 0.5, 1.0, 2.0, 4.0, 8.0];
+    // An hour, a day, a week and four weeks.
+    let lambdas_snh_secs = [3_600.0, 86_400.0, 604_800.0, 2_419_200.0];
+
+    let mut param_grid = HashMap::new();
+    param_grid.insert(
+        "m_most_recent_sessions".to_string(),
+        vec![100, 250, 500, 750, 1000, 2500],
+    );
+    param_grid.insert(
+        "neighborhood_size_k".to_string(),
+        vec![50, 100, 500, 1000, 1500],
+    );
+    param_grid.insert("max_items_in_session".to_string(), vec![1, 2, 3, 5, 7, 15, 100]);
+    param_grid.insert("lambda_spw".to_string(), (0..lambdas.len()).collect());
+    param_grid.insert("lambda_snh_secs".to_string(), (0..lambdas_snh_secs.len()).collect());
+    param_grid.insert("lambda_inh".to_string(), (0..lambdas.len()).collect());
+    let hyper_parametergrid = HyperParamGrid { param_grid };
+
+    let mut best_score = 0.0;
+    let mut best_params = HashMap::new();
+    let main_metric_name = Mrr::new(20).get_name();
+    for hyperparams in hyper_parametergrid.get_n_random_combinations(150) {
+        let max_items_in_session = *hyperparams.get("max_items_in_session").unwrap();
+        let neighborhood_size_k = *hyperparams.get("neighborhood_size_k").unwrap();
+        let m_most_recent_sessions = *hyperparams.get("m_most_recent_sessions").unwrap();
+        let parameters = StanParameters {
+            lambda_spw: lambdas[*hyperparams.get("lambda_spw").unwrap()],
+            lambda_snh_secs: lambdas_snh_secs[*hyperparams.get("lambda_snh_secs").unwrap()],
+            lambda_inh: lambdas[*hyperparams.get("lambda_inh").unwrap()],
+        };
+        if neighborhood_size_k > m_most_recent_sessions {
+            continue;
+        }
+        let index = OfflineIndex::new_from_csv(path_to_training, input_schema, m_most_recent_sessions);
+        let stan_index = StanIndex::new_from_csv(path_to_training, input_schema, Arc::new(index), parameters);
+        let ordered_test_sessions = match stan_index.index().item_dictionary() {
+            Some(item_dictionary) => {
+                io::read_test_data_evolving_with_dictionary(test_data_file, input_schema, item_dictionary)
+            }
+            None => io::read_test_data_evolving(test_data_file, input_schema),
+        };
+        let params = RecommendParams {
+            neighborhood_size_k,
+            m_most_recent_sessions,
+            how_many: qty_max_reco_results,
+            enable_business_logic: false,
+        };
+        let mymetric = evaluate(
+            &stan_index,
+            &ordered_test_sessions,
+            max_items_in_session,
+            &params,
+        );
+        if mymetric.result() > best_score {
+            best_score = mymetric.result();
+            best_params = hyperparams.clone();
+        }
+        println!(
+            "HPO,{},{},{},{},{},{},{}",
+            m_most_recent_sessions,
+            neighborhood_size_k,
+            max_items_in_session,
+            parameters.lambda_spw,
+            parameters.lambda_snh_secs,
+            parameters.lambda_inh,
+            mymetric.result()
+        );
+    }
     println!(
         "Best hyperparameter values found:,{:?} with {}:{}",
         best_params, main_metric_name, best_score
     );
 }
+
+/// Tunes the weights of the `ensemble_members` and the fusion. The members are trained once, with the
+/// hyperparameters of the `[model]` section.
+fn tune_ensemble(
+    model_config: &ModelConfig,
+    path_to_training: &str,
+    test_data_file: &str,
+    input_schema: &InputSchema,
+    qty_max_reco_results: usize,
+) {
+    let member_kinds: Vec<RecommenderKind> = model_config
+        .ensemble
+        .members
+        .iter()
+        .map(|(member_kind, _weight)| *member_kind)
+        .collect();
+    if member_kinds.is_empty() {
+        panic!("The ensemble recommender requires ensemble_members");
+    }
+
+    let mut param_grid = HashMap::new();
+    // The grid only contains numbers, so the weights are in tenths and the fusion is a position in `Fusion::ALL`.
+    for member_kind in member_kinds.iter() {
+        param_grid.insert(format!("weight_{}", member_kind), (0..=10).collect());
+    }
+    param_grid.insert("fusion".to_string(), (0..Fusion::ALL.len()).collect());
+    let hyper_parametergrid = HyperParamGrid { param_grid };
+
+    let mut offline_index = OfflineIndex::new_from_csv(
+        path_to_training,
+        input_schema,
+        model_config.m_most_recent_sessions,
+    );
+    offline_index.set_weighting(model_config.weighting);
+    if let Some(item_weighting) = model_config.item_weighting {
+        offline_index.set_item_weighting(item_weighting);
+    }
+    let offline_index = Arc::new(offline_index);
+    let ordered_test_sessions = match offline_index.item_dictionary() {
+        Some(item_dictionary) => {
+            io::read_test_data_evolving_with_dictionary(test_data_file, input_schema, item_dictionary)
+        }
+        None => io::read_test_data_evolving(test_data_file, input_schema),
+    };
+    let members = member_kinds
+        .iter()
+        .map(|member_kind| {
+            create_recommender_of_kind(
+                *member_kind,
+                model_config,
+                offline_index.clone(),
+                path_to_training,
+                input_schema,
+            )
+        })
+        .collect();
+    let mut ensemble = EnsembleRecommender::new(
+        members,
+        vec![1.0; member_kinds.len()],
+        model_config.ensemble.fusion,
+        model_config.ensemble.rrf_k,
+    );
+    let params = RecommendParams {
+        neighborhood_size_k: model_config.neighborhood_size_k,
+        m_most_recent_sessions: model_config.m_most_recent_sessions,
+        how_many: qty_max_reco_results,
+        enable_business_logic: false,
+    };
+
+    let mut best_score = 0.0;
+    let mut best_params = HashMap::new();
+    let main_metric_name = Mrr::new(20).get_name();
+    for hyperparams in hyper_parametergrid.get_n_random_combinations(150) {
+        let weights: Vec<f64> = member_kinds
+            .iter()
+            .map(|member_kind| *hyperparams.get(&format!("weight_{}", member_kind)).unwrap() as f64 / 10.0)
+            .collect();
+        if weights.iter().all(|weight| *weight == 0.0) {
+            continue;
+        }
+        let fusion = Fusion::ALL[*hyperparams.get("fusion").unwrap()];
+        ensemble.set_weights(weights);
+        ensemble.set_fusion(fusion);
+        let mymetric = evaluate(
+            &ensemble,
+            &ordered_test_sessions,
+            model_config.max_items_in_session,
+            &params,
+        );
+        if mymetric.result() > best_score {
+            best_score = mymetric.result();
+            best_params = hyperparams.clone();
+        }
+        let weights: Vec<String> = ensemble.weights().iter().map(|weight| weight.to_string()).collect();
+        println!("HPO,{},{},{}", fusion, weights.join(","), mymetric.result());
+    }
+    println!(
+        "Best hyperparameter values found:,{:?} with {}:{}",
+        best_params, main_metric_name, best_score
+    );
+}
+
+/// Tunes the number of rules per item of the rules models and the steps of the sequential rules.
+/// The rules don't depend on the session length, so the last item of the evolving session is all they use.
+fn tune_rules(
+    recommender_kind: RecommenderKind,
+    path_to_training: &str,
+    test_data_file: &str,
+    input_schema: &InputSchema,
+    qty_max_reco_results: usize,
+) {
+    let mut param_grid = HashMap::new();
+    // 0 keeps all rules.
+    param_grid.insert("rules_pruning".to_string(), vec![0, 10, 20, 50, 100]);
+    if recommender_kind == RecommenderKind::SequentialRules {
+        param_grid.insert("sr_steps".to_string(), vec![1, 2, 3, 5, 10, 15]);
+    }
+    let hyper_parametergrid = HyperParamGrid { param_grid };
+
+    // Reads the training data once for the item dictionary, which the test sessions are encoded with.
+    let item_dictionary: Option<IdDictionary> = io::read_interactions(path_to_training, input_schema)
+        .unwrap_or_else(|err| panic!("Unable to read {}: {}", path_to_training, err))
+        .item_dictionary;
+    let training_sessions =
+        io::read_training_sessions_ordered(path_to_training, input_schema, item_dictionary.as_ref());
+    let ordered_test_sessions = match item_dictionary.as_ref() {
+        Some(item_dictionary) => {
+            io::read_test_data_evolving_with_dictionary(test_data_file, input_schema, item_dictionary)
+        }
+        None => io::read_test_data_evolving(test_data_file, input_schema),
+    };
+
+    let mut best_score = 0.0;
+    let mut best_params = HashMap::new();
+    let main_metric_name = Mrr::new(20).get_name();
+    for hyperparams in hyper_parametergrid.get_all_combinations() {
+        let rules_pruning = *hyperparams.get("rules_pruning").unwrap();
+        let sr_steps = hyperparams.get("sr_steps").copied().unwrap_or(0);
+        let recommender: Box<dyn Recommender> = match recommender_kind {
+            RecommenderKind::SequentialRules => {
+                Box::new(SequentialRules::new(&training_sessions, sr_steps, rules_pruning))
+            }
+            _ => Box::new(AssociationRules::new(&training_sessions, rules_pruning)),
+        };
+        let params = RecommendParams {
+            neighborhood_size_k: 0,
+            m_most_recent_sessions: 0,
+            how_many: qty_max_reco_results,
+            enable_business_logic: false,
+        };
+        let mymetric = evaluate(recommender.as_ref(), &ordered_test_sessions, 1, &params);
+        if mymetric.result() > best_score {
+            best_score = mymetric.result();
+            best_params = hyperparams.clone();
+        }
+        println!(
+            "HPO,{},{},{},{}",
+            recommender_kind,
+            rules_pruning,
+            sr_steps,
+            mymetric.result()
+        );
+    }
+    println!(
+        "Best hyperparameter values found:,{:?} with {}:{}",
+        best_params, main_metric_name, best_score
+    );
+}
+
+/// Tunes the neighborhood of the indexes of the runtime comparisons, which all compute VMIS-kNN with its default
+/// weighting, except for VS-kNN. They are keyed by the item ids of the training data.
+fn tune_neighborhood(
+    recommender_kind: RecommenderKind,
+    path_to_training: &str,
+    test_data_file: &str,
+    input_schema: &InputSchema,
+    qty_max_reco_results: usize,
+) {
+    let mut param_grid = HashMap::new();
+    param_grid.insert(
+        "m_most_recent_sessions".to_string(),
+        vec![100, 250, 500, 750, 1000, 2500],
+    );
+    param_grid.insert(
+        "neighborhood_size_k".to_string(),
+        vec![50, 100, 500, 1000, 1500],
+    );
+    param_grid.insert("max_items_in_session".to_string(), vec![1, 2, 3, 5, 7, 15, 100]);
+    let hyper_parametergrid = HyperParamGrid { param_grid };
+
+    let ordered_test_sessions = io::read_test_data_evolving(test_data_file, input_schema);
+
+    let mut best_score = 0.0;
+    let mut best_params = HashMap::new();
+    let main_metric_name = Mrr::new(20).get_name();
+    for hyperparams in hyper_parametergrid.get_n_random_combinations(150) {
+        let max_items_in_session = *hyperparams.get("max_items_in_session").unwrap();
+        let neighborhood_size_k = *hyperparams.get("neighborhood_size_k").unwrap();
+        let m_most_recent_sessions = *hyperparams.get("m_most_recent_sessions").unwrap();
+        if neighborhood_size_k > m_most_recent_sessions {
+            continue;
+        }
+        let recommender: Box<dyn Recommender> = match recommender_kind {
+            RecommenderKind::VmisKnnHashMap => Box::new(VMISSkNNIndex::new(
+                path_to_training,
+                input_schema,
+                m_most_recent_sessions,
+            )),
+            RecommenderKind::VmisKnnNoOpt => Box::new(VMISSkNNIndexNoOpt::new(
+                path_to_training,
+                input_schema,
+                m_most_recent_sessions,
+            )),
+            RecommenderKind::VmisKnnSmallOpt => Box::new(VMISSkNNIndexSmallOpt::new(
+                path_to_training,
+                input_schema,
+                m_most_recent_sessions,
+            )),
+            RecommenderKind::VmisKnnTree => Box::new(TreeIndex::new(
+                path_to_training,
+                input_schema,
+                m_most_recent_sessions,
+            )),
+            RecommenderKind::VsKnn => Box::new(VSkNNIndex::new(
+                read_training_data(path_to_training, input_schema),
+                m_most_recent_sessions,
+                usize::MAX,
+            )),
+            _ => panic!("The {} recommender is not tuned by its neighborhood", recommender_kind),
+        };
+        let params = RecommendParams {
+            neighborhood_size_k,
+            m_most_recent_sessions,
+            how_many: qty_max_reco_results,
+            enable_business_logic: false,
+        };
+        let mymetric = evaluate(
+            recommender.as_ref(),
+            &ordered_test_sessions,
+            max_items_in_session,
+            &params,
+        );
+        if mymetric.result() > best_score {
+            best_score = mymetric.result();
+            best_params = hyperparams.clone();
+        }
+        println!(
+            "HPO,{},{},{},{},{}",
+            recommender_kind,
+            m_most_recent_sessions,
+            neighborhood_size_k,
+            max_items_in_session,
+            mymetric.result()
+        );
+    }
+    println!(
+        "Best hyperparameter values found:,{:?} with {}:{}",
+        best_params, main_metric_name, best_score
+    );
+}
+
+fn evaluate(
+    recommender: &dyn Recommender,
+    ordered_test_sessions: &hashbrown::HashMap<u32, Vec<u64>>,
+    max_items_in_session: usize,
+    params: &RecommendParams,
+) -> Mrr {
+    let mut mymetric = Mrr::new(20);
+    ordered_test_sessions
+        .iter()
+        .for_each(|(_session_id, evolving_session_items)| {
+            for session_state in 1..evolving_session_items.len() {
+                // use last x items of evolving session
+                let start_index = if session_state > max_items_in_session {
+                    session_state - max_items_in_session
+                } else {
+                    0
+                };
+                let session: &[u64] = &evolving_session_items[start_index..session_state];
+                let recommendations = recommender.recommend(session, params);
+
+                let recommended_items = recommendations
+                    .iter()
+                    .map(|scored| scored.id)
+                    .collect::<Vec<u64>>();
+
+                let actual_next_items = Vec::from(&evolving_session_items[session_state..]);
+                mymetric.add(&recommended_items, &actual_next_items);
+            }
+        });
+    mymetric
+}
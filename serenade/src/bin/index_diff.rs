@@ -0,0 +1,56 @@
+use serenade_optimized::config::read_input_schema;
+use serenade_optimized::io;
+use serenade_optimized::vmisknn::index_diff::{diff_indexes, recommendation_overlap};
+use serenade_optimized::vmisknn::offline_index::OfflineIndex;
+
+fn main() {
+    // hyper-parameters used for the recommendation overlap
+    let m_most_recent_sessions = 500;
+    let neighborhood_size_k = 500;
+    let max_items_in_session = 2;
+    let qty_max_reco_results = 20;
+    let enable_business_logic = false;
+    // amount of items reported for the largest idf shifts and session list churn
+    let top_n = 25;
+
+    let index_path_before = std::env::args()
+        .nth(1)
+        .expect("Index directory of the previous build not specified!");
+    println!("index_directory_before:{}", index_path_before);
+
+    let index_path_after = std::env::args()
+        .nth(2)
+        .expect("Index directory of the new build not specified!");
+    println!("index_directory_after:{}", index_path_after);
+
+    // Optional: evolving sessions to compare the recommendations of both builds on.
+    let test_data_file = std::env::args().nth(3);
+
+    // Optional: a config file with the layout of the data files in its `[data]` section.
+    let input_schema = std::env::args()
+        .nth(4)
+        .map(|config_path| read_input_schema(&config_path))
+        .unwrap_or_default();
+
+    let index_before = OfflineIndex::new(&index_path_before);
+    let index_after = OfflineIndex::new(&index_path_after);
+
+    let diff = diff_indexes(&index_before, &index_after, top_n);
+    println!("{}", diff);
+
+    if let Some(test_data_file) = test_data_file {
+        println!("test_data_file:{}", test_data_file);
+        let ordered_test_sessions = io::read_test_data_evolving(&test_data_file, &input_schema);
+        let overlap = recommendation_overlap(
+            &index_before,
+            &index_after,
+            &ordered_test_sessions,
+            neighborhood_size_k,
+            m_most_recent_sessions,
+            max_items_in_session,
+            qty_max_reco_results,
+            enable_business_logic,
+        );
+        println!("Overlap@{}: {:.4}", qty_max_reco_results, overlap);
+    }
+}
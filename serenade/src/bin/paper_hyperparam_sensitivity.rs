@@ -1,3 +1,4 @@
+use serenade_optimized::config::{read_input_schema, read_weighting};
 use serenade_optimized::hyperparameter::hyperparamgrid::HyperParamGrid;
 use serenade_optimized::io::read_training_data;
 use serenade_optimized::metrics::evaluation_reporter::EvaluationReporter;
@@ -27,9 +28,21 @@ fn main() {
         .expect("Test data file not specified!");
     println!("result:test_data_file:{}", test_data_file);
 
+    // Optional: a config file with the layout of the data files in its `[data]` section
+    // and the weighting in its `[model]` section.
+    let config_path = std::env::args().nth(3);
+    let input_schema = config_path
+        .as_deref()
+        .map(read_input_schema)
+        .unwrap_or_default();
+    let weighting = config_path
+        .as_deref()
+        .map(read_weighting)
+        .unwrap_or_default();
+
     let hyper_parametergrid = HyperParamGrid { param_grid };
 
-    let training_df = read_training_data(&*path_to_training);
+    let training_df = read_training_data(&path_to_training, &input_schema);
 
     let evaluation_reporter = EvaluationReporter::new(&training_df, 20);
     println!(
@@ -44,8 +57,11 @@ fn main() {
         let n_most_recent_sessions = *hyperparams.get("sample_size").unwrap();
 
         if neighborhood_size_k <= n_most_recent_sessions {
-            let vsknn_index = OfflineIndex::new_from_csv(&*path_to_training, n_most_recent_sessions);
-            let ordered_test_sessions = io::read_test_data_evolving(&*test_data_file);
+            let mut vsknn_index =
+                OfflineIndex::new_from_csv(&path_to_training, &input_schema, n_most_recent_sessions);
+            vsknn_index.set_weighting(weighting);
+            let ordered_test_sessions =
+                io::read_test_data_evolving(&test_data_file, &input_schema);
             let mut evaluation_reporter = EvaluationReporter::new(&training_df, 20);
 
             ordered_test_sessions
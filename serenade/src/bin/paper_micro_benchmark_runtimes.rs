@@ -22,6 +22,7 @@ use rayon::prelude::*;
 use tdigest::TDigest;
 
 use serenade_optimized::io;
+use serenade_optimized::io::InputSchema;
 use serenade_optimized::vmisknn::similarity_hashed::SimilarityComputationHash;
 use serenade_optimized::vmisknn::similarity_indexed::SimilarityComputationNew;
 use serenade_optimized::vmisknn::tree_index::TreeIndex;
@@ -51,22 +52,23 @@ fn main() {
         .unwrap();
 
     let algorithms = vec!["vmis_noopt", "vmis", "vsknn"];
+    let input_schema = InputSchema::default();
 
     for dataset in &["1m"] {
         for m in &[100, 250, 500, 1000] {
             let path_train = format!("data/private-clicks-{}_train.txt", dataset);
             let path_test = format!("data/private-clicks-{}_test.txt", dataset);
 
-            let historical_sessions = io::read_training_data(&path_train);
+            let historical_sessions = io::read_training_data(&path_train, &input_schema);
 
-            let vmis_noopt_index = VMISSkNNIndexNoOpt::new(&path_train, *m);
-            let vmis_index = VMISSkNNIndex::new(&path_train, *m);
+            let vmis_noopt_index = VMISSkNNIndexNoOpt::new(&path_train, &input_schema, *m);
+            let vmis_index = VMISSkNNIndex::new(&path_train, &input_schema, *m);
             let vsknn_index = VSkNNIndex::new(historical_sessions, *m, 1_000_000);
-            // let tree_index = TreeIndex::new(&path_train, *m);
+            // let tree_index = TreeIndex::new(&path_train, &input_schema, *m);
 
             let mut test_sessions: HashMap<u32, Vec<u64>> = HashMap::new();
 
-            for (session, item, _) in io::read_training_data(&path_test).iter() {
+            for (session, item, _) in io::read_training_data(&path_test, &input_schema).iter() {
                 if !test_sessions.contains_key(session) {
                     test_sessions.insert(*session, Vec::new());
                 }
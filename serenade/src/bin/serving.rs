@@ -1,6 +1,6 @@
 extern crate serenade_optimized;
 
-use sessions::RocksDBSessionStore;
+use sessions::{RocksDBSessionStore, RocksDBUserHistoryStore};
 
 use actix_web::{
     http::ContentEncoding, middleware, web, App, HttpRequest, HttpResponse, HttpServer,
@@ -14,10 +14,23 @@ use std::time::Duration;
 
 use serenade_optimized::config::AppConfig;
 use serenade_optimized::dataframeutils::SharedHandlesAndConfig;
+use serenade_optimized::diversity::Diversification;
+use serenade_optimized::endpoints::basket_resource::v1_basket;
 use serenade_optimized::endpoints::index_resource::internal;
 use serenade_optimized::endpoints::recommend_resource::v1_recommend;
+use serenade_optimized::endpoints::session_resource::v1_merge_sessions;
+use serenade_optimized::endpoints::shard_resource::shard_neighbors;
+use serenade_optimized::endpoints::similar_resource::v1_similar;
+use serenade_optimized::recommendation_cache::CachedRecommender;
+use serenade_optimized::recommender::{
+    create_compressed_recommender, create_recommender, RecommendParams, RecommenderKind,
+};
 use serenade_optimized::sessions;
+use serenade_optimized::trending::TrendingCounter;
+use serenade_optimized::vmisknn::compressed_index::CompressedOfflineIndex;
 use serenade_optimized::vmisknn::offline_index::OfflineIndex;
+use serenade_optimized::vmisknn::similar_items::precompute_similar_items;
+use serenade_optimized::vmisknn::single_item_table::{SingleItemTable, SINGLE_ITEM_TABLE_FILE_NAME};
 
 #[actix_web::main]
 async fn main() -> std::io::Result<()> {
@@ -31,36 +44,171 @@ async fn main() -> std::io::Result<()> {
     let max_items_in_session = config.model.max_items_in_session;
     let qty_workers = config.server.num_workers;
     let enable_business_logic = config.logic.enable_business_logic;
+    let event_type_weights = config.model.event_type_weights.clone();
 
+    let shard = config.sharding.shard.as_ref();
     let training_data_path = Path::new(&config.data.training_data_path);
-    let vsknn = if training_data_path.is_dir() {
+    let mut index = if training_data_path.is_dir() {
         // By default we use an index that is computed offline on billions of user-item interactions.
-        Arc::new(OfflineIndex::new(&config.data.training_data_path))
+        match shard {
+            Some(shard) => OfflineIndex::new_shard(&config.data.training_data_path, shard),
+            None => OfflineIndex::new(&config.data.training_data_path),
+        }
     } else if training_data_path.is_file() {
         // The following line creates an index directly from a csv file as input.
-        Arc::new(OfflineIndex::new_from_csv(
+        let mut index = OfflineIndex::new_from_csv(
             &config.data.training_data_path,
+            &config.data.input_schema,
             config.model.m_most_recent_sessions,
-        ))
+        );
+        if let Some(shard) = shard {
+            index.retain_shard(shard);
+        }
+        index
     } else {
         panic!(
             "Training data file does not exist: {}",
             &config.data.training_data_path
         )
     };
+    index.set_weighting(config.model.weighting);
+    if let Some(item_weighting) = config.model.item_weighting {
+        index.set_item_weighting(item_weighting);
+    }
+    index.set_event_type_weights(&config.model.event_type_weights);
+    index.set_product_groups(config.model.product_groups);
+    if let Some(item_attributes_path) = &config.data.item_attributes_path {
+        let qty_items = index
+            .read_item_attributes(item_attributes_path)
+            .unwrap_or_else(|error| panic!("Reading the item attributes failed: {}", error));
+        println!("item attributes: {} items", qty_items);
+    }
+    // The table holds the recommendations of the index, without the diversification.
+    let single_item_table = if config.model.precompute_single_items
+        && config.model.recommender == RecommenderKind::VmisKnn
+        && config.model.diversity.diversification == Diversification::None
+    {
+        let params = RecommendParams {
+            neighborhood_size_k,
+            m_most_recent_sessions,
+            how_many: num_items_to_recommend,
+            enable_business_logic,
+        };
+        // Only the table of an unsharded index directory is stored alongside the index.
+        let table = if training_data_path.is_dir() && shard.is_none() {
+            let table_path = training_data_path.join(SINGLE_ITEM_TABLE_FILE_NAME);
+            SingleItemTable::read_or_build(&index, &params, &table_path.display().to_string())
+        } else {
+            SingleItemTable::build(&index, &params)
+        };
+        Some(Arc::new(table))
+    } else {
+        None
+    };
+
+    let similar_items = if config.model.precompute_similar_items {
+        Some(Arc::new(precompute_similar_items(
+            &index,
+            m_most_recent_sessions,
+            num_items_to_recommend,
+            enable_business_logic,
+        )))
+    } else {
+        None
+    };
+
+    // The precomputed recommendations are computed before the compression, from the same sessions.
+    let (vsknn, compressed_index) = if config.model.compressed_index {
+        if shard.is_some() {
+            panic!("The compressed index can't serve a shard, shards search their neighbors in the uncompressed index");
+        }
+        let compressed_index = Arc::new(CompressedOfflineIndex::new(index));
+        println!(
+            "compressed posting lists and session lists: {} MB",
+            compressed_index.lists_size_in_bytes() / 1_000_000
+        );
+        (compressed_index.index().clone(), Some(compressed_index))
+    } else {
+        (Arc::new(index), None)
+    };
+    let mut recommender = match &compressed_index {
+        Some(compressed_index) => create_compressed_recommender(&config.model, compressed_index.clone()),
+        None => create_recommender(
+            &config.model,
+            vsknn.clone(),
+            &config.data.training_data_path,
+            &config.data.input_schema,
+            enable_business_logic,
+        ),
+    };
+
+    if let Some(shard) = shard {
+        println!("serving shard {} of {}", shard.shard_index, shard.qty_shards);
+    }
 
     println!("start db");
     let session_ttl = Duration::from_secs(30 * 60);
-    let db = Arc::new(RocksDBSessionStore::new("./sessions.db", session_ttl));
+    // Shards on the same machine need their own session database.
+    let session_db_path = match shard {
+        Some(shard) => format!("./sessions-shard{}.db", shard.shard_index),
+        None => "./sessions.db".to_string(),
+    };
+    let db = Arc::new(RocksDBSessionStore::new(&session_db_path, session_ttl));
+
+    let user_history_store = if config.user_history.max_items > 0 {
+        // Like the sessions, shards on the same machine need their own user database.
+        let user_history_db_path = match shard {
+            Some(shard) => format!("./users-shard{}.db", shard.shard_index),
+            None => "./users.db".to_string(),
+        };
+        Some(Arc::new(RocksDBUserHistoryStore::new(
+            &user_history_db_path,
+            config.user_history.ttl,
+            config.user_history.max_items,
+        )))
+    } else {
+        None
+    };
+    let user_history_weight = config.user_history.weight;
+
+    let trending_counter = if config.trending.weight > 0.0 {
+        println!(
+            "boost the items trending in the last {} mins",
+            config.trending.window.as_secs() / 60
+        );
+        Some(Arc::new(TrendingCounter::new(&config.trending)))
+    } else {
+        None
+    };
 
     println!("start metrics");
     let prometheus = PrometheusMetrics::new("api", Some("/internal/prometheus"), None);
 
+    if config.cache.capacity > 0 {
+        println!(
+            "cache recommendations of {} session states for {} secs",
+            config.cache.capacity,
+            config.cache.ttl.as_secs()
+        );
+        let cached_recommender = CachedRecommender::new(recommender, &config.cache);
+        cached_recommender
+            .register_metrics(&prometheus.registry)
+            .expect("Registering the cache metrics failed");
+        recommender = Arc::new(cached_recommender);
+    }
+
     println!("Done. start httpd at http://{}", &bind_address);
     HttpServer::new(move || {
         let handles_and_config = SharedHandlesAndConfig {
             session_store: db.clone(),
+            user_history_store: user_history_store.clone(),
+            user_history_weight,
+            trending_counter: trending_counter.clone(),
             vsknn_index: vsknn.clone(),
+            compressed_index: compressed_index.clone(),
+            recommender: recommender.clone(),
+            single_item_table: single_item_table.clone(),
+            similar_items: similar_items.clone(),
             m_most_recent_sessions,
             neighborhood_size_k,
             num_items_to_recommend,
@@ -68,6 +216,7 @@ async fn main() -> std::io::Result<()> {
             qty_workers,
             db_compaction_ttl_in_secs: session_ttl.as_secs() as usize,
             enable_business_logic,
+            event_type_weights: event_type_weights.clone(),
         };
 
         App::new()
@@ -81,6 +230,10 @@ async fn main() -> std::io::Result<()> {
             )
             .data(handles_and_config)
             .service(v1_recommend)
+            .service(v1_similar)
+            .service(v1_basket)
+            .service(v1_merge_sessions)
+            .service(shard_neighbors)
             .service(internal)
             .service(web::resource("/").route(web::get().to(|_req: HttpRequest| {
                 HttpResponse::Found()
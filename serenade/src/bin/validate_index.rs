@@ -0,0 +1,19 @@
+use serenade_optimized::vmisknn::index_validation::validate_index;
+use serenade_optimized::vmisknn::offline_index::OfflineIndex;
+
+fn main() {
+    // Checks an index directory computed offline (`itemindex/` and `sessionindex/` avro files)
+    // before it is deployed to the serving instances.
+    let index_path = std::env::args()
+        .nth(1)
+        .expect("Index directory not specified!");
+    println!("index_directory:{}", index_path);
+
+    let offline_index = OfflineIndex::new(&index_path);
+    let report = validate_index(&offline_index);
+    println!("{}", report);
+
+    if !report.is_valid() {
+        std::process::exit(1);
+    }
+}
@@ -1,6 +1,7 @@
 use std::convert::TryInto;
 use std::ffi::OsStr;
 use std::fs::File;
+use std::time::Duration;
 
 use justconfig::item::ValueExtractor;
 use justconfig::processors::Trim;
@@ -10,12 +11,30 @@ use justconfig::ConfPath;
 use justconfig::Config;
 
 use crate::config_processors::Unquote;
+use crate::diversity::DiversityConfig;
+use crate::ensemble::{parse_ensemble_members, EnsembleConfig};
+use crate::io::InputSchema;
+use crate::recommendation_cache::CacheConfig;
+use crate::trending::TrendingConfig;
+use crate::recommender::RecommenderKind;
+use crate::vmisknn::product_groups::ProductGroups;
+use crate::vmisknn::sharded_index::ShardAssignment;
+use crate::vmisknn::stan_index::StanParameters;
+use crate::vmisknn::weighting::{parse_event_type_weights, EventTypeWeights, ItemWeighting, Weighting};
 
 // Set some default values
 const DEFAULT_MOST_RECENT_SESSIONS_M: usize = 500;
 const DEFAULT_NEIGHBORHOOD_SIZE_K: usize = 500;
 const DEFAULT_NUM_ITEMS_TO_RECOMMEND: usize = 21;
 const DEFAULT_MAX_ITEMS_IN_SESSION: usize = 2;
+const DEFAULT_SR_STEPS: usize = 10;
+const DEFAULT_RULES_PRUNING: usize = 20;
+const DEFAULT_CACHE_TTL_SECS: u64 = 300;
+const DEFAULT_CACHE_SHARDS: usize = 16;
+const DEFAULT_USER_HISTORY_WEIGHT: f64 = 0.5;
+const DEFAULT_USER_HISTORY_TTL_DAYS: u64 = 30;
+const DEFAULT_TRENDING_WINDOW_MINS: u64 = 60;
+const DEFAULT_TRENDING_HALF_LIFE_MINS: u64 = 15;
 
 pub struct AppConfig {
     pub server: ServerConfig,
@@ -23,6 +42,10 @@ pub struct AppConfig {
     pub data: DataConfig,
     pub model: ModelConfig,
     pub logic: LogicConfig,
+    pub sharding: ShardingConfig,
+    pub cache: CacheConfig,
+    pub user_history: UserHistoryConfig,
+    pub trending: TrendingConfig,
 }
 
 pub struct ServerConfig {
@@ -37,6 +60,10 @@ pub struct LogConfig {
 
 pub struct DataConfig {
     pub training_data_path: String,
+    pub input_schema: InputSchema,
+    /// A csv file with the `item_id,category_id,brand_id,group_id` of the items, for the diversification and the
+    /// product groups.
+    pub item_attributes_path: Option<String>,
 }
 
 pub struct ModelConfig {
@@ -44,12 +71,48 @@ pub struct ModelConfig {
     pub neighborhood_size_k: usize,
     pub num_items_to_recommend: usize,
     pub max_items_in_session: usize,
+    pub weighting: Weighting,
+    /// `None` keeps the item weights of the index build.
+    pub item_weighting: Option<ItemWeighting>,
+    pub event_type_weights: EventTypeWeights,
+    pub recommender: RecommenderKind,
+    /// The maximum distance between the items of a sequential rule.
+    pub sr_steps: usize,
+    /// The number of rules kept per item by the rules models, 0 keeps all of them.
+    pub rules_pruning: usize,
+    pub stan: StanParameters,
+    pub ensemble: EnsembleConfig,
+    /// Whether the server serves sessions with a single item from a precomputed table of the VMIS-kNN index.
+    pub precompute_single_items: bool,
+    /// Whether the similar items of every item are computed at start instead of for every request.
+    pub precompute_similar_items: bool,
+    /// Whether the server keeps the posting lists and session items of the index in the `CompressedOfflineIndex`.
+    pub compressed_index: bool,
+    pub diversity: DiversityConfig,
+    pub product_groups: ProductGroups,
 }
 
 pub struct LogicConfig {
     pub enable_business_logic: bool,
 }
 
+/// The recent items of logged-in users from past sessions, which extend their evolving sessions.
+pub struct UserHistoryConfig {
+    /// The number of recent items kept per user, 0 disables the user history.
+    pub max_items: usize,
+    /// The weight of the user's recent items in the neighbor similarity, relative to the event type weights of the
+    /// session items.
+    pub weight: f64,
+    pub ttl: Duration,
+}
+
+pub struct ShardingConfig {
+    /// The sessions that a shard process serves, absent when the process serves the whole index.
+    pub shard: Option<ShardAssignment>,
+    /// The `host:port` of every shard, used by the coordinator.
+    pub shard_addresses: Vec<String>,
+}
+
 impl AppConfig {
     pub fn new(config_path: String) -> AppConfig {
         // Initialize config object
@@ -72,6 +135,19 @@ impl AppConfig {
                 ConfPath::from(&["server", "num_workers"]),
                 OsStr::new("NUM_WORKERS"),
             ),
+            (ConfPath::from(&["server", "port"]), OsStr::new("PORT")),
+            (
+                ConfPath::from(&["sharding", "shard_index"]),
+                OsStr::new("SHARD_INDEX"),
+            ),
+            (
+                ConfPath::from(&["sharding", "qty_shards"]),
+                OsStr::new("QTY_SHARDS"),
+            ),
+            (
+                ConfPath::from(&["sharding", "shard_addresses"]),
+                OsStr::new("SHARD_ADDRESSES"),
+            ),
         ]);
         conf.add_source(config_env);
 
@@ -85,7 +161,11 @@ impl AppConfig {
             log: LogConfig::parse(&conf, ConfPath::from(&["log"])),
             data: DataConfig::parse(&conf, ConfPath::from(&["data"])),
             model: ModelConfig::parse(&conf, ConfPath::from(&["model"])),
-            logic: LogicConfig::parse(&conf, ConfPath::from(&["logic"]))
+            logic: LogicConfig::parse(&conf, ConfPath::from(&["logic"])),
+            sharding: ShardingConfig::parse(&conf, ConfPath::from(&["sharding"])),
+            cache: parse_cache_config(&conf, ConfPath::from(&["cache"])),
+            user_history: UserHistoryConfig::parse(&conf, ConfPath::from(&["user_history"])),
+            trending: parse_trending_config(&conf, ConfPath::from(&["trending"])),
         }
     }
 }
@@ -129,10 +209,266 @@ impl DataConfig {
                 .unquote()
                 .value()
                 .unwrap(),
+            input_schema: parse_input_schema(conf, path.clone()),
+            item_attributes_path: parse_item_attributes_path(conf, path),
         }
     }
 }
 
+/// Reads the layout of the training and test data from the `[data]` section.
+/// Missing parameters fall back to the `InputSchema` defaults, invalid values are rejected.
+fn parse_input_schema(conf: &Config, path: ConfPath) -> InputSchema {
+    let defaults = InputSchema::default();
+    InputSchema {
+        delimiter: conf
+            .get(path.push("delimiter"))
+            .unquote()
+            .try_value()
+            .unwrap()
+            .unwrap_or(defaults.delimiter),
+        has_header: conf
+            .get(path.push("has_header"))
+            .unquote()
+            .try_value()
+            .unwrap()
+            .unwrap_or(defaults.has_header),
+        session_id_column: conf
+            .get(path.push("session_id_column"))
+            .unquote()
+            .try_value()
+            .unwrap()
+            .unwrap_or(defaults.session_id_column),
+        item_id_column: conf
+            .get(path.push("item_id_column"))
+            .unquote()
+            .try_value()
+            .unwrap()
+            .unwrap_or(defaults.item_id_column),
+        time_column: conf
+            .get(path.push("time_column"))
+            .unquote()
+            .try_value()
+            .unwrap()
+            .unwrap_or(defaults.time_column),
+        event_type_column: conf
+            .get(path.push("event_type_column"))
+            .unquote()
+            .try_value()
+            .unwrap(),
+        timestamp_format: conf
+            .get(path.push("timestamp_format"))
+            .unquote()
+            .try_value()
+            .unwrap()
+            .unwrap_or(defaults.timestamp_format),
+        compression: conf
+            .get(path.push("compression"))
+            .unquote()
+            .try_value()
+            .unwrap()
+            .unwrap_or(defaults.compression),
+        string_ids: conf
+            .get(path.push("string_ids"))
+            .unquote()
+            .try_value()
+            .unwrap()
+            .unwrap_or(defaults.string_ids),
+    }
+}
+
+/// Reads only the input schema from a config file, for the command line tools that get their data paths as arguments.
+pub fn read_input_schema(config_path: &str) -> InputSchema {
+    let conf = read_config_file(config_path);
+    parse_input_schema(&conf, ConfPath::from(&["data"]))
+}
+
+/// Reads only the `item_attributes_path` of the `[data]` section, for the command line tools that get their data
+/// paths as arguments.
+pub fn read_item_attributes_path(config_path: &str) -> Option<String> {
+    let conf = read_config_file(config_path);
+    parse_item_attributes_path(&conf, ConfPath::from(&["data"]))
+}
+
+fn parse_item_attributes_path(conf: &Config, path: ConfPath) -> Option<String> {
+    conf.get(path.push("item_attributes_path"))
+        .unquote()
+        .try_value()
+        .unwrap()
+}
+
+/// Reads the `[model]` section, for the command line tools that get their data paths as arguments.
+pub fn read_model_config(config_path: &str) -> ModelConfig {
+    let conf = read_config_file(config_path);
+    ModelConfig::parse(&conf, ConfPath::from(&["model"]))
+}
+
+/// Reads only the weighting of the `[model]` section, for the command line tools that tune the other parameters.
+pub fn read_weighting(config_path: &str) -> Weighting {
+    let conf = read_config_file(config_path);
+    parse_weighting(&conf, ConfPath::from(&["model"]))
+}
+
+fn read_config_file(config_path: &str) -> Config {
+    let mut conf = Config::default();
+    let config_file = File::open(config_path)
+        .unwrap_or_else(|_| panic!("Config file does not exist: {}", config_path));
+    let config_text =
+        ConfigText::new(config_file, config_path).expect("Loading configuration file failed.");
+    conf.add_source(config_text);
+    conf
+}
+
+/// Reads `event_type_weights` such as `view:1,cart:3,purchase:5` and `excluded_event_types` such as `purchase`.
+fn parse_event_type_weights_config(conf: &Config, path: &ConfPath) -> EventTypeWeights {
+    let weights: String = conf
+        .get(path.push("event_type_weights"))
+        .unquote()
+        .try_value()
+        .unwrap()
+        .unwrap_or_default();
+    let excluded_event_types: String = conf
+        .get(path.push("excluded_event_types"))
+        .unquote()
+        .try_value()
+        .unwrap()
+        .unwrap_or_default();
+    EventTypeWeights::new(
+        parse_event_type_weights(&weights).unwrap(),
+        excluded_event_types
+            .split(',')
+            .map(|event_type| event_type.trim().to_string())
+            .filter(|event_type| !event_type.is_empty())
+            .collect(),
+    )
+}
+
+fn parse_stan_parameters(conf: &Config, path: &ConfPath) -> StanParameters {
+    let defaults = StanParameters::default();
+    let parameters = StanParameters {
+        lambda_spw: conf
+            .get(path.push("stan_lambda_spw"))
+            .trim()
+            .try_value()
+            .unwrap()
+            .unwrap_or(defaults.lambda_spw),
+        lambda_snh_secs: conf
+            .get(path.push("stan_lambda_snh_secs"))
+            .trim()
+            .try_value()
+            .unwrap()
+            .unwrap_or(defaults.lambda_snh_secs),
+        lambda_inh: conf
+            .get(path.push("stan_lambda_inh"))
+            .trim()
+            .try_value()
+            .unwrap()
+            .unwrap_or(defaults.lambda_inh),
+    };
+    // The lambdas divide the distances and the ages of the decays.
+    for (key, lambda) in [
+        ("stan_lambda_spw", parameters.lambda_spw),
+        ("stan_lambda_snh_secs", parameters.lambda_snh_secs),
+        ("stan_lambda_inh", parameters.lambda_inh),
+    ] {
+        if lambda.is_nan() || lambda <= 0.0 {
+            panic!("{} must be positive: {}", key, lambda);
+        }
+    }
+    parameters
+}
+
+/// Reads the `diversification` and the `diversity_lambda` of MMR.
+fn parse_diversity_config(conf: &Config, path: &ConfPath) -> DiversityConfig {
+    let defaults = DiversityConfig::default();
+    DiversityConfig {
+        diversification: conf
+            .get(path.push("diversification"))
+            .unquote()
+            .try_value()
+            .unwrap()
+            .unwrap_or(defaults.diversification),
+        lambda: conf
+            .get(path.push("diversity_lambda"))
+            .trim()
+            .try_value()
+            .unwrap()
+            .unwrap_or(defaults.lambda),
+    }
+}
+
+/// Reads the `max_items_per_group` and whether to `collapse_groups`. Without them, the product groups are ignored.
+fn parse_product_groups(conf: &Config, path: &ConfPath) -> ProductGroups {
+    ProductGroups {
+        max_items_per_group: conf
+            .get(path.push("max_items_per_group"))
+            .trim()
+            .value()
+            .unwrap_or(0),
+        collapse: conf
+            .get(path.push("collapse_groups"))
+            .trim()
+            .value()
+            .unwrap_or(false),
+    }
+}
+
+/// Reads `ensemble_members` such as `vmisknn:0.7,sequential_rules:0.3`, `ensemble_fusion` and `ensemble_rrf_k`.
+fn parse_ensemble_config(conf: &Config, path: &ConfPath) -> EnsembleConfig {
+    let defaults = EnsembleConfig::default();
+    let members: String = conf
+        .get(path.push("ensemble_members"))
+        .unquote()
+        .try_value()
+        .unwrap()
+        .unwrap_or_default();
+    EnsembleConfig {
+        members: parse_ensemble_members(&members).unwrap(),
+        fusion: conf
+            .get(path.push("ensemble_fusion"))
+            .unquote()
+            .try_value()
+            .unwrap()
+            .unwrap_or(defaults.fusion),
+        rrf_k: conf
+            .get(path.push("ensemble_rrf_k"))
+            .trim()
+            .try_value()
+            .unwrap()
+            .unwrap_or(defaults.rrf_k),
+    }
+}
+
+fn parse_weighting(conf: &Config, path: ConfPath) -> Weighting {
+    Weighting {
+        position_decay: conf
+            .get(path.push("position_decay"))
+            .unquote()
+            .try_value()
+            .unwrap()
+            .unwrap_or_default(),
+        match_weight: conf
+            .get(path.push("match_weight"))
+            .unquote()
+            .try_value()
+            .unwrap()
+            .unwrap_or_default(),
+        // 0 disables the recency weighting, like in the hyperparameter search.
+        recency_half_life_secs: conf
+            .get(path.push("recency_half_life_secs"))
+            .trim()
+            .try_value()
+            .unwrap()
+            .filter(|half_life_secs| *half_life_secs > 0),
+    }
+}
+
+/// The defaults of all the parameters of the `[model]` section.
+impl Default for ModelConfig {
+    fn default() -> Self {
+        ModelConfig::parse(&Config::default(), ConfPath::from(&["model"]))
+    }
+}
+
 impl ModelConfig {
     fn parse(conf: &Config, path: ConfPath) -> ModelConfig {
         ModelConfig {
@@ -156,6 +492,48 @@ impl ModelConfig {
                 .trim()
                 .value()
                 .unwrap_or(DEFAULT_MAX_ITEMS_IN_SESSION),
+            item_weighting: conf
+                .get(path.push("item_weighting"))
+                .unquote()
+                .try_value()
+                .unwrap(),
+            event_type_weights: parse_event_type_weights_config(conf, &path),
+            recommender: conf
+                .get(path.push("recommender"))
+                .unquote()
+                .try_value()
+                .unwrap()
+                .unwrap_or_default(),
+            sr_steps: conf
+                .get(path.push("sr_steps"))
+                .trim()
+                .value()
+                .unwrap_or(DEFAULT_SR_STEPS),
+            rules_pruning: conf
+                .get(path.push("rules_pruning"))
+                .trim()
+                .value()
+                .unwrap_or(DEFAULT_RULES_PRUNING),
+            stan: parse_stan_parameters(conf, &path),
+            ensemble: parse_ensemble_config(conf, &path),
+            precompute_single_items: conf
+                .get(path.push("precompute_single_items"))
+                .trim()
+                .value()
+                .unwrap_or(false),
+            precompute_similar_items: conf
+                .get(path.push("precompute_similar_items"))
+                .trim()
+                .value()
+                .unwrap_or(false),
+            compressed_index: conf
+                .get(path.push("compressed_index"))
+                .trim()
+                .value()
+                .unwrap_or(false),
+            diversity: parse_diversity_config(conf, &path),
+            product_groups: parse_product_groups(conf, &path),
+            weighting: parse_weighting(conf, path),
         }
     }
 }
@@ -171,3 +549,109 @@ impl LogicConfig {
         }
     }
 }
+
+/// Reads the `[cache]` section. Without a `capacity`, recommendations are not cached.
+fn parse_cache_config(conf: &Config, path: ConfPath) -> CacheConfig {
+    CacheConfig {
+        capacity: conf
+            .get(path.push("capacity"))
+            .trim()
+            .value()
+            .unwrap_or(0),
+        ttl: Duration::from_secs(
+            conf.get(path.push("ttl_secs"))
+                .trim()
+                .value()
+                .unwrap_or(DEFAULT_CACHE_TTL_SECS),
+        ),
+        qty_shards: conf
+            .get(path.push("qty_shards"))
+            .trim()
+            .value()
+            .unwrap_or(DEFAULT_CACHE_SHARDS),
+    }
+}
+
+/// Reads the `[trending]` section. Without a `weight`, the recommendations are not boosted.
+fn parse_trending_config(conf: &Config, path: ConfPath) -> TrendingConfig {
+    TrendingConfig {
+        weight: conf
+            .get(path.push("weight"))
+            .trim()
+            .value()
+            .unwrap_or(0.0),
+        window: Duration::from_secs(
+            60 * conf
+                .get(path.push("window_mins"))
+                .trim()
+                .value()
+                .unwrap_or(DEFAULT_TRENDING_WINDOW_MINS),
+        ),
+        half_life: Duration::from_secs(
+            60 * conf
+                .get(path.push("half_life_mins"))
+                .trim()
+                .value()
+                .unwrap_or(DEFAULT_TRENDING_HALF_LIFE_MINS),
+        ),
+    }
+}
+
+impl UserHistoryConfig {
+    fn parse(conf: &Config, path: ConfPath) -> UserHistoryConfig {
+        UserHistoryConfig {
+            max_items: conf
+                .get(path.push("max_items"))
+                .trim()
+                .value()
+                .unwrap_or(0),
+            weight: conf
+                .get(path.push("weight"))
+                .trim()
+                .value()
+                .unwrap_or(DEFAULT_USER_HISTORY_WEIGHT),
+            ttl: Duration::from_secs(
+                24 * 60 * 60
+                    * conf
+                        .get(path.push("ttl_days"))
+                        .trim()
+                        .value()
+                        .unwrap_or(DEFAULT_USER_HISTORY_TTL_DAYS),
+            ),
+        }
+    }
+}
+
+impl ShardingConfig {
+    fn parse(conf: &Config, path: ConfPath) -> ShardingConfig {
+        let shard_index: Option<usize> = conf
+            .get(path.push("shard_index"))
+            .trim()
+            .try_value()
+            .unwrap();
+        let qty_shards: Option<usize> = conf
+            .get(path.push("qty_shards"))
+            .trim()
+            .try_value()
+            .unwrap();
+        let shard = match (shard_index, qty_shards) {
+            (Some(shard_index), Some(qty_shards)) => Some(ShardAssignment::new(shard_index, qty_shards)),
+            (None, None) => None,
+            _ => panic!("Both shard_index and qty_shards are required for a shard"),
+        };
+        let shard_addresses: String = conf
+            .get(path.push("shard_addresses"))
+            .unquote()
+            .try_value()
+            .unwrap()
+            .unwrap_or_default();
+        ShardingConfig {
+            shard,
+            shard_addresses: shard_addresses
+                .split(',')
+                .map(|address| address.trim().to_string())
+                .filter(|address| !address.is_empty())
+                .collect(),
+        }
+    }
+}
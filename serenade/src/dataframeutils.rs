@@ -2,15 +2,40 @@
 // use itertools::Itertools;
 use chrono::NaiveDateTime;
 // use tdigest::TDigest;
+use hashbrown::HashMap;
 use rayon::prelude::*;
 use std::sync::Arc;
 
-use crate::sessions::RocksDBSessionStore;
+use actix_web::client::Client;
+
+use crate::dictionary::IdDictionary;
+use crate::recommender::Recommender;
+use crate::sessions::{RocksDBSessionStore, RocksDBUserHistoryStore};
+use crate::trending::TrendingCounter;
+use crate::vmisknn::compressed_index::CompressedOfflineIndex;
 use crate::vmisknn::offline_index::OfflineIndex;
+use crate::vmisknn::product_groups::ProductGroups;
+use crate::vmisknn::single_item_table::SingleItemTable;
+use crate::vmisknn::weighting::{EventTypeWeights, Weighting};
+use crate::vmisknn::ItemScore;
 
 pub struct SharedHandlesAndConfig {
     pub session_store: Arc<RocksDBSessionStore>,
+    /// The recent items of the users from past sessions, absent unless the `[user_history]` is enabled.
+    pub user_history_store: Option<Arc<RocksDBUserHistoryStore>>,
+    pub user_history_weight: f64,
+    /// Counts the requested items and boosts the trending ones, absent unless the `[trending]` boost has a weight.
+    pub trending_counter: Option<Arc<TrendingCounter>>,
     pub vsknn_index: Arc<OfflineIndex>,
+    /// The posting lists and session items of `vsknn_index` in the compressed layout, absent unless
+    /// `compressed_index` is set. The `vsknn_index` then only holds the item data.
+    pub compressed_index: Option<Arc<CompressedOfflineIndex>>,
+    /// The model that recommends the items, which may use `vsknn_index` or a model of its own.
+    pub recommender: Arc<dyn Recommender>,
+    /// The recommendations of the sessions with a single item, absent unless `precompute_single_items` is set.
+    pub single_item_table: Option<Arc<SingleItemTable>>,
+    /// The similar items of every item, absent unless `precompute_similar_items` is set.
+    pub similar_items: Option<Arc<HashMap<u64, Vec<ItemScore>>>>,
     pub m_most_recent_sessions: usize,
     pub neighborhood_size_k: usize,
     pub num_items_to_recommend: usize,
@@ -18,8 +43,29 @@ pub struct SharedHandlesAndConfig {
     pub qty_workers: usize,
     pub db_compaction_ttl_in_secs: usize,
     pub enable_business_logic: bool,
+    pub event_type_weights: EventTypeWeights,
+}
+
+/// The state of a coordinator that serves recommendations from the neighbors of several shards.
+pub struct CoordinatorHandlesAndConfig {
+    pub session_store: Arc<RocksDBSessionStore>,
+    pub shard_client: Client,
+    /// The base url of every shard, e.g. `http://localhost:8081`.
+    pub shard_urls: Vec<String>,
+    pub m_most_recent_sessions: usize,
+    pub neighborhood_size_k: usize,
+    pub num_items_to_recommend: usize,
+    pub max_items_in_session: usize,
+    pub enable_business_logic: bool,
+    pub weighting: Weighting,
+    /// The canonical items of the product groups are assigned by the shards, all from the same global item counts.
+    pub product_groups: ProductGroups,
+    pub event_type_weights: EventTypeWeights,
+    /// The item dictionary of the training data with `string_ids`, the same as the dictionary of every shard.
+    pub item_dictionary: Option<Arc<IdDictionary>>,
 }
 
+#[derive(Default)]
 pub struct TrainingDataStats {
     pub descriptive_name: String,
     pub qty_records: usize,
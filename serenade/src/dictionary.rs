@@ -0,0 +1,134 @@
+use hashbrown::HashMap;
+use std::error::Error;
+use std::fs;
+use std::fs::File;
+use std::io::{BufRead, BufReader, BufWriter, Write};
+use std::path::{Path, PathBuf};
+
+/// Maps external string identifiers (e.g. SKUs like `B07XJ8C8F5`) to dense internal ids starting at zero
+/// and back. The internal id of an external id is its position in `internal_to_external`.
+#[derive(Clone, Debug, Default, PartialEq)]
+pub struct IdDictionary {
+    external_to_internal: HashMap<String, u64>,
+    internal_to_external: Vec<String>,
+}
+
+impl IdDictionary {
+    /// Returns the internal id of `external_id`, assigning the next free internal id if it is unknown.
+    pub fn encode(&mut self, external_id: &str) -> u64 {
+        if let Some(internal_id) = self.external_to_internal.get(external_id) {
+            return *internal_id;
+        }
+        let internal_id = self.internal_to_external.len() as u64;
+        self.external_to_internal
+            .insert(external_id.to_string(), internal_id);
+        self.internal_to_external.push(external_id.to_string());
+        internal_id
+    }
+
+    pub fn to_internal(&self, external_id: &str) -> Option<u64> {
+        self.external_to_internal.get(external_id).copied()
+    }
+
+    pub fn to_external(&self, internal_id: u64) -> Option<&str> {
+        self.internal_to_external
+            .get(internal_id as usize)
+            .map(|external_id| external_id.as_str())
+    }
+
+    pub fn len(&self) -> usize {
+        self.internal_to_external.len()
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.internal_to_external.is_empty()
+    }
+
+    /// Writes one external id per line, ordered by internal id.
+    pub fn write_to_file(&self, path: &str) -> Result<(), Box<dyn Error>> {
+        let mut writer = BufWriter::new(File::create(path)?);
+        for external_id in self.internal_to_external.iter() {
+            writeln!(writer, "{}", external_id)?;
+        }
+        writer.flush()?;
+        Ok(())
+    }
+
+    /// Reads a dictionary written by `write_to_file`, the line number of an external id is its internal id.
+    /// The path can also be a directory like Spark writes it, whose `part-*` files are read in the order of their names.
+    pub fn read_from_file(path: &str) -> Result<Self, Box<dyn Error>> {
+        let part_paths = if Path::new(path).is_dir() {
+            let mut part_paths = Vec::new();
+            for entry in fs::read_dir(path)? {
+                let part_path = entry?.path();
+                let is_part = part_path
+                    .file_name()
+                    .and_then(|file_name| file_name.to_str())
+                    .is_some_and(|file_name| file_name.starts_with("part-"));
+                if is_part {
+                    part_paths.push(part_path);
+                }
+            }
+            part_paths.sort();
+            part_paths
+        } else {
+            vec![PathBuf::from(path)]
+        };
+
+        let mut dictionary = IdDictionary::default();
+        for part_path in part_paths.iter() {
+            for line in BufReader::new(File::open(part_path)?).lines() {
+                let external_id = line?;
+                if dictionary.to_internal(&external_id).is_some() {
+                    return Err(format!("Duplicate id {} in dictionary {}", external_id, path).into());
+                }
+                dictionary.encode(&external_id);
+            }
+        }
+        Ok(dictionary)
+    }
+}
+
+#[cfg(test)]
+mod dictionary_test {
+    use super::*;
+
+    #[test]
+    fn should_assign_dense_ids_and_translate_both_directions() {
+        let mut dictionary = IdDictionary::default();
+        assert_eq!(0, dictionary.encode("B07XJ8C8F5"));
+        assert_eq!(1, dictionary.encode("9200000012345678"));
+        assert_eq!(0, dictionary.encode("B07XJ8C8F5"));
+
+        assert_eq!(2, dictionary.len());
+        assert_eq!(Some(1), dictionary.to_internal("9200000012345678"));
+        assert_eq!(None, dictionary.to_internal("unknown"));
+        assert_eq!(Some("B07XJ8C8F5"), dictionary.to_external(0));
+        assert_eq!(None, dictionary.to_external(2));
+    }
+
+    #[test]
+    fn should_write_and_read_dictionary() {
+        let mut dictionary = IdDictionary::default();
+        dictionary.encode("sku-a");
+        dictionary.encode("sku-b");
+        let dir = tempfile::tempdir().unwrap();
+        let path = dir.path().join("itemdictionary.txt").display().to_string();
+
+        dictionary.write_to_file(&path).unwrap();
+        assert_eq!(dictionary, IdDictionary::read_from_file(&path).unwrap());
+    }
+
+    #[test]
+    fn should_read_the_part_files_of_a_dictionary_directory() {
+        let dir = tempfile::tempdir().unwrap();
+        std::fs::write(dir.path().join("part-00001.txt"), "sku-c\n").unwrap();
+        std::fs::write(dir.path().join("part-00000.txt"), "sku-a\nsku-b\n").unwrap();
+        std::fs::write(dir.path().join("_SUCCESS"), "").unwrap();
+
+        let dictionary = IdDictionary::read_from_file(&dir.path().display().to_string()).unwrap();
+        assert_eq!(Some("sku-a"), dictionary.to_external(0));
+        assert_eq!(Some(2), dictionary.to_internal("sku-c"));
+        assert_eq!(3, dictionary.len());
+    }
+}
@@ -0,0 +1,279 @@
+use std::error::Error;
+use std::fmt;
+use std::str::FromStr;
+use std::sync::Arc;
+
+use crate::recommender::{RecommendParams, Recommender, RecommenderKind};
+use crate::vmisknn::offline_index::{OfflineIndex, ProductAttributes};
+use crate::vmisknn::similarity_indexed::SimilarityComputationNew;
+use crate::vmisknn::weighting::SessionEvents;
+use crate::vmisknn::ItemScore;
+
+/// The number of candidates per recommended item that the model recommends for the re-ranking.
+const CANDIDATES_PER_ITEM: usize = 3;
+
+pub const DEFAULT_DIVERSITY_LAMBDA: f64 = 0.7;
+
+#[derive(Debug)]
+pub struct DiversityError(String);
+
+impl fmt::Display for DiversityError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "{}", self.0)
+    }
+}
+
+impl Error for DiversityError {}
+
+/// How the recommendations of a model are re-ranked to cover more categories and brands.
+#[derive(Clone, Copy, Debug, Default, PartialEq, Eq)]
+pub enum Diversification {
+    /// The recommendations of the model.
+    #[default]
+    None,
+    /// Maximal marginal relevance: every next item maximizes `lambda * relevance - (1 - lambda) * similarity` to the
+    /// most similar item that was already selected, with the relevance divided by the best score.
+    Mmr,
+    /// The best remaining item of every category in turn, the categories ordered by their best item.
+    RoundRobin,
+}
+
+impl FromStr for Diversification {
+    type Err = DiversityError;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        match s {
+            "none" => Ok(Diversification::None),
+            "mmr" => Ok(Diversification::Mmr),
+            "round_robin" => Ok(Diversification::RoundRobin),
+            _ => Err(DiversityError(format!("Invalid diversification: {}", s))),
+        }
+    }
+}
+
+impl fmt::Display for Diversification {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        let name = match self {
+            Diversification::None => "none",
+            Diversification::Mmr => "mmr",
+            Diversification::RoundRobin => "round_robin",
+        };
+        write!(f, "{}", name)
+    }
+}
+
+/// The re-ranking of the `[model]` section.
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub struct DiversityConfig {
+    pub diversification: Diversification,
+    /// The trade-off of MMR between relevance (1) and diversity (0).
+    pub lambda: f64,
+}
+
+impl Default for DiversityConfig {
+    fn default() -> Self {
+        DiversityConfig {
+            diversification: Diversification::default(),
+            lambda: DEFAULT_DIVERSITY_LAMBDA,
+        }
+    }
+}
+
+/// Re-ranks more candidates of a model by the categories and brands of the `ProductAttributes` of the index. The
+/// scores of the re-ranked items are `1 / rank`, so that later stages keep their order.
+pub struct DiversifiedRecommender {
+    recommender: Arc<dyn Recommender>,
+    index: Arc<OfflineIndex>,
+    config: DiversityConfig,
+}
+
+impl DiversifiedRecommender {
+    pub fn new(recommender: Arc<dyn Recommender>, index: Arc<OfflineIndex>, config: &DiversityConfig) -> Self {
+        DiversifiedRecommender {
+            recommender,
+            index,
+            config: *config,
+        }
+    }
+
+    fn attributes(&self, item_id: &u64) -> Option<&ProductAttributes> {
+        self.index
+            .internal_item_id(item_id)
+            .and_then(|item| self.index.find_attributes(&item))
+    }
+}
+
+impl Recommender for DiversifiedRecommender {
+    fn recommend_with_events(
+        &self,
+        evolving_session: &[u64],
+        session_events: &SessionEvents,
+        params: &RecommendParams,
+    ) -> Vec<ItemScore> {
+        let candidate_params = RecommendParams {
+            how_many: params.how_many * CANDIDATES_PER_ITEM,
+            ..*params
+        };
+        let candidates = self
+            .recommender
+            .recommend_with_events(evolving_session, session_events, &candidate_params);
+        let attributes = |item_id: &u64| self.attributes(item_id);
+        let reranked = match self.config.diversification {
+            Diversification::None => return candidates.into_iter().take(params.how_many).collect(),
+            Diversification::Mmr => {
+                maximal_marginal_relevance(&candidates, attributes, self.config.lambda, params.how_many)
+            }
+            Diversification::RoundRobin => category_round_robin(&candidates, attributes, params.how_many),
+        };
+        reranked
+            .into_iter()
+            .enumerate()
+            .map(|(rank, item_id)| ItemScore::new(item_id, 1.0 / (rank + 1) as f64))
+            .collect()
+    }
+
+    fn kind(&self) -> RecommenderKind {
+        self.recommender.kind()
+    }
+}
+
+/// Greedily selects `how_many` of the candidates, the best first, by their relevance minus their similarity to the
+/// items that were already selected. Items without attributes are not similar to any item.
+pub fn maximal_marginal_relevance<'a>(
+    candidates: &[ItemScore],
+    attributes: impl Fn(&u64) -> Option<&'a ProductAttributes>,
+    lambda: f64,
+    how_many: usize,
+) -> Vec<u64> {
+    let best_score = candidates
+        .first()
+        .map_or(0.0, |scored| scored.score)
+        .max(f64::MIN_POSITIVE);
+    let candidate_attributes: Vec<Option<&ProductAttributes>> =
+        candidates.iter().map(|scored| attributes(&scored.id)).collect();
+    let mut max_similarities = vec![0.0_f64; candidates.len()];
+    let mut is_selected = vec![false; candidates.len()];
+    let mut selected = Vec::with_capacity(how_many.min(candidates.len()));
+
+    while selected.len() < how_many.min(candidates.len()) {
+        // The first of equally good candidates is the one that the model ranks higher.
+        let (next, _) = candidates
+            .iter()
+            .enumerate()
+            .filter(|(position, _)| !is_selected[*position])
+            .map(|(position, scored)| {
+                let relevance = scored.score / best_score;
+                (position, lambda * relevance - (1.0 - lambda) * max_similarities[position])
+            })
+            .fold((usize::MAX, f64::NEG_INFINITY), |best, candidate| {
+                if candidate.1 > best.1 {
+                    candidate
+                } else {
+                    best
+                }
+            });
+        is_selected[next] = true;
+        selected.push(candidates[next].id);
+        if let Some(next_attributes) = candidate_attributes[next] {
+            for (position, other_attributes) in candidate_attributes.iter().enumerate() {
+                if let Some(other_attributes) = other_attributes {
+                    max_similarities[position] =
+                        max_similarities[position].max(next_attributes.similarity(other_attributes));
+                }
+            }
+        }
+    }
+    selected
+}
+
+/// Takes the best remaining candidate of every category in turn, the categories in the order of their best
+/// candidate. Items without a category form one category.
+pub fn category_round_robin<'a>(
+    candidates: &[ItemScore],
+    attributes: impl Fn(&u64) -> Option<&'a ProductAttributes>,
+    how_many: usize,
+) -> Vec<u64> {
+    let mut categories: Vec<(Option<u64>, Vec<u64>)> = Vec::new();
+    for scored in candidates.iter() {
+        let category = attributes(&scored.id).and_then(|attributes| attributes.category);
+        match categories.iter_mut().find(|(other_category, _)| *other_category == category) {
+            Some((_, items)) => items.push(scored.id),
+            None => categories.push((category, vec![scored.id])),
+        }
+    }
+
+    let mut selected = Vec::with_capacity(how_many.min(candidates.len()));
+    let mut round = 0;
+    while selected.len() < how_many.min(candidates.len()) {
+        for (_, items) in categories.iter() {
+            if let Some(item_id) = items.get(round) {
+                if selected.len() < how_many {
+                    selected.push(*item_id);
+                }
+            }
+        }
+        round += 1;
+    }
+    selected
+}
+
+#[cfg(test)]
+mod diversity_test {
+    use hashbrown::HashMap;
+
+    use super::*;
+
+    fn attributes(category: u64, brand: u64) -> ProductAttributes {
+        ProductAttributes {
+            is_adult: false,
+            is_for_sale: true,
+            category: Some(category),
+            brand: Some(brand),
+            group: None,
+            canonical_item_id: None,
+        }
+    }
+
+    /// Items 1, 2 and 3 are shoes of the same brand, 4 is a shirt and 5 has no attributes.
+    fn catalog() -> HashMap<u64, ProductAttributes> {
+        vec![(1, attributes(10, 100)), (2, attributes(10, 100)), (3, attributes(10, 101)), (4, attributes(20, 102))]
+            .into_iter()
+            .collect()
+    }
+
+    fn candidates() -> Vec<ItemScore> {
+        vec![
+            ItemScore::new(1, 1.0),
+            ItemScore::new(2, 0.9),
+            ItemScore::new(3, 0.8),
+            ItemScore::new(4, 0.5),
+            ItemScore::new(5, 0.4),
+        ]
+    }
+
+    #[test]
+    fn should_parse_the_diversification() {
+        assert_eq!(Diversification::Mmr, "mmr".parse().unwrap());
+        assert_eq!(Diversification::RoundRobin, "round_robin".parse().unwrap());
+        assert_eq!("round_robin", Diversification::RoundRobin.to_string());
+        assert!("random".parse::<Diversification>().is_err());
+    }
+
+    #[test]
+    fn should_trade_relevance_for_diversity() {
+        let catalog = catalog();
+        let lookup = |item_id: &u64| catalog.get(item_id);
+        assert_eq!(vec![1, 2, 3, 4, 5], maximal_marginal_relevance(&candidates(), lookup, 1.0, 5));
+        assert_eq!(vec![1, 4, 5, 3, 2], maximal_marginal_relevance(&candidates(), lookup, 0.5, 5));
+        assert_eq!(vec![1, 4], maximal_marginal_relevance(&candidates(), lookup, 0.5, 2));
+        assert!(maximal_marginal_relevance(&[], lookup, 0.5, 2).is_empty());
+    }
+
+    #[test]
+    fn should_alternate_the_categories() {
+        let catalog = catalog();
+        let lookup = |item_id: &u64| catalog.get(item_id);
+        assert_eq!(vec![1, 4, 5, 2, 3], category_round_robin(&candidates(), lookup, 5));
+        assert_eq!(vec![1, 4, 5, 2], category_round_robin(&candidates(), lookup, 4));
+    }
+}
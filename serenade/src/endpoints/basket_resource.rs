@@ -0,0 +1,86 @@
+use actix_web::{post, web, HttpResponse};
+use serde::Deserialize;
+
+use crate::dataframeutils::SharedHandlesAndConfig;
+use crate::endpoints::recommend_resource::{parse_item_id, recommended_items_response};
+use crate::vmisknn::basket::predict_basket;
+use crate::vmisknn::similarity_indexed::{PostingLists, SimilarityComputationNew};
+use crate::vmisknn::ItemScore;
+
+/// The maximum number of items of a basket, larger baskets are rejected.
+const MAX_BASKET_ITEMS: usize = 100;
+
+#[derive(Debug, Deserialize)]
+pub struct V1BasketRequest {
+    /// Numeric item ids, or external string ids when the index has an item dictionary.
+    pub(crate) item_ids: Vec<String>,
+    /// The weight of every item, 1 for all items if absent.
+    pub(crate) weights: Option<Vec<f64>>,
+}
+
+// Recommendations for a set of items without an order, e.g. for cart pages.
+// Independent of any session, so nothing is read from or stored in the session store.
+#[post("/v1/basket")]
+pub async fn v1_basket(
+    data: web::Data<SharedHandlesAndConfig>,
+    request: web::Json<V1BasketRequest>,
+) -> HttpResponse {
+    let vsknn_index = data.vsknn_index.as_ref();
+    let item_dictionary = vsknn_index.item_dictionary();
+
+    if request.item_ids.len() > MAX_BASKET_ITEMS {
+        return HttpResponse::BadRequest()
+            .body(format!("A basket must not contain more than {} items", MAX_BASKET_ITEMS));
+    }
+    let weights = match &request.weights {
+        Some(weights) if weights.len() != request.item_ids.len() => {
+            return HttpResponse::BadRequest().body("The basket requires a weight for each of its items")
+        }
+        Some(weights) => weights.clone(),
+        None => vec![1.0; request.item_ids.len()],
+    };
+    if weights.iter().any(|weight| !weight.is_finite() || *weight < 0.0) {
+        return HttpResponse::BadRequest().body("The weights of the basket must not be negative");
+    }
+
+    let mut basket_items = Vec::with_capacity(request.item_ids.len());
+    let mut item_weights = Vec::with_capacity(request.item_ids.len());
+    for (item_id, weight) in request.item_ids.iter().zip(weights.into_iter()) {
+        match parse_item_id(item_dictionary, item_id) {
+            Ok(Some(item_id)) => {
+                basket_items.push(item_id);
+                item_weights.push(weight);
+            }
+            // Items that are not part of the index don't contribute to the recommendations.
+            Ok(None) => {}
+            Err(response) => return response,
+        }
+    }
+
+    let recommendations = match &data.compressed_index {
+        Some(compressed_index) => {
+            basket_recommendations(compressed_index.as_ref(), &data, &basket_items, &item_weights)
+        }
+        None => basket_recommendations(vsknn_index, &data, &basket_items, &item_weights),
+    };
+
+    recommended_items_response(item_dictionary, &recommendations)
+}
+
+fn basket_recommendations<I: SimilarityComputationNew + PostingLists>(
+    index: &I,
+    data: &SharedHandlesAndConfig,
+    basket_items: &[u64],
+    item_weights: &[f64],
+) -> Vec<ItemScore> {
+    predict_basket(
+        index,
+        basket_items,
+        item_weights,
+        data.neighborhood_size_k,
+        data.m_most_recent_sessions,
+        data.num_items_to_recommend,
+        data.enable_business_logic,
+    )
+    .into_sorted_vec()
+}
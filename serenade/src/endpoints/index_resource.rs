@@ -70,6 +70,9 @@ pub async fn internal(config: Data<SharedHandlesAndConfig>) -> HttpResponse {
     html.push_str(&data_stats.qty_events_p100.to_string());
 
     html.push_str("<h3>Models</h3>");
+    html.push_str("recommender: ");
+    html.push_str(&config.recommender.kind().to_string());
+    html.push_str("<br />");
     html.push_str("hyperparameters");
     html.push_str("<br />m : ");
     html.push_str(&config.m_most_recent_sessions.to_string());
@@ -81,6 +84,11 @@ pub async fn internal(config: Data<SharedHandlesAndConfig>) -> HttpResponse {
     html.push_str(&config.max_items_in_session.to_string());
     html.push_str("<br />Qty items to recommend: ");
     html.push_str(&config.num_items_to_recommend.to_string());
+    html.push_str("<br />Item weighting: ");
+    match config.vsknn_index.item_weighting() {
+        Some(item_weighting) => html.push_str(&item_weighting.to_string()),
+        None => html.push_str("idf of the index build"),
+    }
     html.push_str("<br /><a href=\"/v1/recommend?session_id=144&user_consent=true&item_id=1001004010971015\">v1 endpoint of our model</a>");
     html.push_str("<h3>Machine instance</h3>");
     html.push_str("<br />Qty CPU's detected: ");
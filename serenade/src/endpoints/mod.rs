@@ -1,2 +1,6 @@
+pub mod basket_resource;
 pub mod index_resource;
 pub mod recommend_resource;
+pub mod session_resource;
+pub mod shard_resource;
+pub mod similar_resource;
@@ -4,13 +4,23 @@ use serde::Deserialize;
 use uuid::Builder;
 
 use crate::dataframeutils::SharedHandlesAndConfig;
-use crate::vmisknn;
+use crate::dictionary::IdDictionary;
+use crate::recommender::RecommendParams;
+use crate::sessions::RocksDBSessionStore;
+use crate::vmisknn::passes_business_rules_of_trending_items;
+use crate::vmisknn::weighting::{blend_user_history, EventTypeWeights};
+use crate::vmisknn::ItemScore;
 
 #[derive(Debug, Deserialize)]
 pub struct V1QueryParams {
-    item_id: u64,
-    session_id: String,
-    user_consent: bool,
+    /// A numeric item id, or an external string id when the index has an item dictionary.
+    pub(crate) item_id: String,
+    pub(crate) session_id: String,
+    pub(crate) user_consent: bool,
+    /// The type of the interaction with the item, e.g. `view` or `purchase`.
+    pub(crate) event_type: Option<String>,
+    /// The id of a logged-in user, whose recent items from past sessions extend the session if the user consents.
+    pub(crate) user_id: Option<String>,
 }
 
 // Serenade's main endpoint.
@@ -22,44 +32,160 @@ pub async fn v1_recommend(
     data: web::Data<SharedHandlesAndConfig>,
     query: web::Query<V1QueryParams>,
 ) -> HttpResponse {
-    let most_recent_item = query.item_id;
-    let user_consent = query.user_consent;
-    let session_id_digest = md5::compute(&query.session_id);
-    let evolving_session_id = Builder::from_bytes(session_id_digest.0).build().as_u128();
-
     let vsknn_index = data.vsknn_index.as_ref();
+    let item_dictionary = vsknn_index.item_dictionary();
+
+    let most_recent_item = match parse_item_id(item_dictionary, &query.item_id) {
+        Ok(Some(item_id)) => item_id,
+        // The item is not part of the index, so there is nothing to recommend. It is still counted, so that it can
+        // trend before it is part of the index.
+        Ok(None) => {
+            if let (Some(trending_counter), Some(item_dictionary)) = (&data.trending_counter, item_dictionary) {
+                trending_counter.record(trending_counter.item_id(item_dictionary, &query.item_id));
+            }
+            return HttpResponse::Ok().json(Vec::<String>::new());
+        }
+        Err(response) => return response,
+    };
     let session_store = data.session_store.as_ref();
 
-    let k = data.neighborhood_size_k;
-    let m = data.m_most_recent_sessions;
     let how_many = data.num_items_to_recommend;
     let max_items_in_session = data.max_items_in_session;
-    let enable_business_logic = data.enable_business_logic;
-
-    let session_items = if user_consent {
-        let mut session_items = session_store.get_session_items(&evolving_session_id);
-        if session_items.is_empty() {
-            session_items.push(most_recent_item);
-        } else if session_items.last().unwrap() != &most_recent_item {
-            session_items.push(most_recent_item);
-            if session_items.len() > max_items_in_session {
-                // Reduce the amount of session_items to max_items_in_session.
-                session_items.drain(0..1);
-            }
+
+    let event_type_weights = &data.event_type_weights;
+    let (session_items, session_event_types) = update_evolving_session(
+        session_store,
+        &query,
+        most_recent_item,
+        max_items_in_session,
+        event_type_weights,
+    );
+    let session_events = event_type_weights.session_events(&session_items, &session_event_types);
+    let (session_items, session_events) = match (&data.user_history_store, &query.user_id) {
+        (Some(user_history_store), Some(user_id)) if query.user_consent => {
+            let hashed_user_id = hash_id(user_id);
+            let evolving_session_id = hash_id(&query.session_id);
+            let user_items = user_history_store.get_recent_items(&hashed_user_id, &evolving_session_id);
+            user_history_store.add_item(&hashed_user_id, &evolving_session_id, most_recent_item);
+            blend_user_history(&session_items, &session_events, &user_items, data.user_history_weight)
         }
-        session_store.update_session_items(&evolving_session_id, &session_items);
-        session_items
-    } else {
-        vec![most_recent_item]
+        _ => (session_items, session_events),
     };
 
-    let recommendations = vmisknn::predict(vsknn_index, &session_items, k, m, how_many, enable_business_logic);
+    let params = RecommendParams {
+        neighborhood_size_k: data.neighborhood_size_k,
+        m_most_recent_sessions: data.m_most_recent_sessions,
+        how_many,
+        enable_business_logic: data.enable_business_logic,
+    };
+    // A single item with a positive weight ranks like an unweighted one, unless its event type excludes it.
+    let precomputed_recommendations = match (&data.single_item_table, session_items.as_slice()) {
+        (Some(single_item_table), [item_id])
+            if session_events.excluded_items.is_empty() && session_events.item_weights[0] > 0.0 =>
+        {
+            single_item_table.recommend(item_id)
+        }
+        _ => None,
+    };
+    let recommendations = precomputed_recommendations.unwrap_or_else(|| {
+        data.recommender
+            .recommend_with_events(&session_items, &session_events, &params)
+    });
 
+    let recommendations = match &data.trending_counter {
+        Some(trending_counter) => {
+            trending_counter.record(most_recent_item);
+            trending_counter.boost(recommendations, how_many, |item_id| {
+                !session_items.contains(&item_id)
+                    && !session_events.excluded_items.contains(&item_id)
+                    && (!data.enable_business_logic
+                        || passes_business_rules_of_trending_items(vsknn_index, &most_recent_item, &item_id))
+            })
+        }
+        None => recommendations,
+    };
+
+    match (&data.trending_counter, item_dictionary) {
+        // The trending items may be new items that only the trending counter knows.
+        (Some(trending_counter), Some(item_dictionary)) => {
+            HttpResponse::Ok().json(trending_counter.external_item_ids(item_dictionary, &recommendations))
+        }
+        _ => recommended_items_response(item_dictionary, &recommendations),
+    }
+}
+
+/// The numeric item id of the `item_id` query parameter, `None` if the item dictionary doesn't know the item.
+pub(crate) fn parse_item_id(
+    item_dictionary: Option<&IdDictionary>,
+    item_id: &str,
+) -> Result<Option<u64>, HttpResponse> {
+    match item_dictionary {
+        Some(item_dictionary) => Ok(item_dictionary.to_internal(item_id)),
+        None => match item_id.parse::<u64>() {
+            Ok(item_id) => Ok(Some(item_id)),
+            Err(_) => Err(HttpResponse::BadRequest().body(format!("Invalid item_id: {}", item_id))),
+        },
+    }
+}
+
+/// The ids of the recommended items, translated back to the external string ids if the index has an item dictionary.
+pub(crate) fn recommended_items_response(
+    item_dictionary: Option<&IdDictionary>,
+    recommendations: &[ItemScore],
+) -> HttpResponse {
     let recommended_items: Vec<u64> = recommendations
-        .into_sorted_vec()
         .iter()
         .map(|scored| scored.id)
         .collect();
 
-    HttpResponse::Ok().json(recommended_items)
+    match item_dictionary {
+        Some(item_dictionary) => {
+            let recommended_external_items: Vec<&str> = recommended_items
+                .iter()
+                .filter_map(|item_id| item_dictionary.to_external(*item_id))
+                .collect();
+            HttpResponse::Ok().json(recommended_external_items)
+        }
+        None => HttpResponse::Ok().json(recommended_items),
+    }
+}
+
+/// The md5 digest of a session or user id, so that the stores don't contain the original ids.
+pub(crate) fn hash_id(id: &str) -> u128 {
+    let digest = md5::compute(id);
+    Builder::from_bytes(digest.0).build().as_u128()
+}
+
+/// Appends the most recent item and its event type to the stored evolving session, if the user consents to storing
+/// it. Returns the session items with the event type of every item.
+pub(crate) fn update_evolving_session(
+    session_store: &RocksDBSessionStore,
+    query: &V1QueryParams,
+    most_recent_item: u64,
+    max_items_in_session: usize,
+    event_type_weights: &EventTypeWeights,
+) -> (Vec<u64>, Vec<Option<String>>) {
+    if !query.user_consent {
+        return (vec![most_recent_item], vec![query.event_type.clone()]);
+    }
+    let evolving_session_id = hash_id(&query.session_id);
+
+    let (mut session_items, mut session_event_types) = session_store.get_session(&evolving_session_id);
+    if session_items.is_empty() || session_items.last().unwrap() != &most_recent_item {
+        session_items.push(most_recent_item);
+        session_event_types.push(query.event_type.clone());
+        if session_items.len() > max_items_in_session {
+            // Reduce the amount of session_items to max_items_in_session.
+            session_items.drain(0..1);
+            session_event_types.drain(0..1);
+        }
+    } else {
+        // A repeated interaction with the most recent item, such as a purchase after a view.
+        let last_event_type = session_event_types.last_mut().unwrap();
+        if event_type_weights.is_stronger(query.event_type.as_deref(), last_event_type.as_deref()) {
+            *last_event_type = query.event_type.clone();
+        }
+    }
+    session_store.update_session(&evolving_session_id, &session_items, &session_event_types);
+    (session_items, session_event_types)
 }
@@ -0,0 +1,29 @@
+use actix_web::{post, web, HttpResponse};
+use serde::Deserialize;
+
+use crate::dataframeutils::SharedHandlesAndConfig;
+use crate::endpoints::recommend_resource::hash_id;
+
+#[derive(Debug, Deserialize)]
+pub struct V1SessionMergeRequest {
+    /// The session id before the login, e.g. of the anonymous visitor.
+    pub(crate) from_session_id: String,
+    /// The session id after the login.
+    pub(crate) into_session_id: String,
+}
+
+// Called when a visitor logs in and the session id changes, so that the items of the anonymous session are not lost.
+// The session `from_session_id` is deleted afterwards.
+#[post("/v1/session/merge")]
+pub async fn v1_merge_sessions(
+    data: web::Data<SharedHandlesAndConfig>,
+    request: web::Json<V1SessionMergeRequest>,
+) -> HttpResponse {
+    data.session_store.merge_sessions(
+        &hash_id(&request.from_session_id),
+        &hash_id(&request.into_session_id),
+        data.max_items_in_session,
+        &data.event_type_weights,
+    );
+    HttpResponse::Ok().finish()
+}
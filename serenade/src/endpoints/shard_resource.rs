@@ -0,0 +1,108 @@
+use actix_web::client::Client;
+use actix_web::{get, post, web, HttpResponse};
+use futures::future::join_all;
+
+use crate::dataframeutils::{CoordinatorHandlesAndConfig, SharedHandlesAndConfig};
+use crate::endpoints::recommend_resource::{
+    parse_item_id, recommended_items_response, update_evolving_session, V1QueryParams,
+};
+use crate::recommender::{RecommendParams, Recommender};
+use crate::vmisknn::sharded_index::{
+    find_shard_neighbors, MergedNeighborhood, ShardNeighbors, ShardNeighborsRequest,
+};
+
+// The neighbors of a shard contain the items of up to `m` sessions, which exceeds the default payload limit.
+const MAX_SHARD_RESPONSE_BYTES: usize = 64 * 1024 * 1024;
+
+// Called by the coordinator with the evolving session, see `find_shard_neighbors`.
+#[post("/internal/shard/neighbors")]
+pub async fn shard_neighbors(
+    data: web::Data<SharedHandlesAndConfig>,
+    request: web::Json<ShardNeighborsRequest>,
+) -> HttpResponse {
+    let neighbors = find_shard_neighbors(
+        data.vsknn_index.as_ref(),
+        &request.session_items,
+        &request.item_weights,
+        request.m,
+    );
+    HttpResponse::Ok().json(neighbors)
+}
+
+// The main endpoint of the coordinator, with the same parameters and session handling as `v1_recommend`.
+// The neighbors are gathered from all shards, so a single failing shard fails the request.
+#[get("/v1/recommend")]
+pub async fn v1_recommend_sharded(
+    data: web::Data<CoordinatorHandlesAndConfig>,
+    query: web::Query<V1QueryParams>,
+) -> HttpResponse {
+    let item_dictionary = data.item_dictionary.as_deref();
+    let most_recent_item = match parse_item_id(item_dictionary, &query.item_id) {
+        Ok(Some(item_id)) => item_id,
+        Ok(None) => return HttpResponse::Ok().json(Vec::<String>::new()),
+        Err(response) => return response,
+    };
+
+    let k = data.neighborhood_size_k;
+    let m = data.m_most_recent_sessions;
+    let (session_items, session_event_types) = update_evolving_session(
+        data.session_store.as_ref(),
+        &query,
+        most_recent_item,
+        data.max_items_in_session,
+        &data.event_type_weights,
+    );
+    let session_events = data
+        .event_type_weights
+        .session_events(&session_items, &session_event_types);
+
+    let request = ShardNeighborsRequest {
+        session_items,
+        item_weights: session_events.item_weights.clone(),
+        m,
+    };
+    let neighbors_per_shard = match scatter(&data.shard_client, &data.shard_urls, &request).await {
+        Ok(neighbors_per_shard) => neighbors_per_shard,
+        Err(err) => {
+            eprintln!("{}", err);
+            return HttpResponse::BadGateway().body(err);
+        }
+    };
+
+    let mut neighborhood = MergedNeighborhood::new(neighbors_per_shard, k, m, data.weighting);
+    neighborhood.set_product_groups(data.product_groups);
+    let params = RecommendParams {
+        neighborhood_size_k: k,
+        m_most_recent_sessions: m,
+        how_many: data.num_items_to_recommend,
+        enable_business_logic: data.enable_business_logic,
+    };
+    let recommendations = neighborhood.recommend_with_events(&request.session_items, &session_events, &params);
+
+    recommended_items_response(item_dictionary, &recommendations)
+}
+
+/// Requests the neighbors from all shards concurrently.
+async fn scatter(
+    client: &Client,
+    shard_urls: &[String],
+    request: &ShardNeighborsRequest,
+) -> Result<Vec<ShardNeighbors>, String> {
+    let responses = shard_urls.iter().map(|shard_url| async move {
+        let url = format!("{}/internal/shard/neighbors", shard_url);
+        let mut response = client
+            .post(&url)
+            .send_json(request)
+            .await
+            .map_err(|err| format!("Shard {} is unavailable: {}", shard_url, err))?;
+        if !response.status().is_success() {
+            return Err(format!("Shard {} responded with {}", shard_url, response.status()));
+        }
+        response
+            .json::<ShardNeighbors>()
+            .limit(MAX_SHARD_RESPONSE_BYTES)
+            .await
+            .map_err(|err| format!("Invalid response of shard {}: {}", shard_url, err))
+    });
+    join_all(responses).await.into_iter().collect()
+}
@@ -0,0 +1,57 @@
+use actix_web::{get, web, HttpResponse};
+use serde::Deserialize;
+
+use crate::dataframeutils::SharedHandlesAndConfig;
+use crate::endpoints::recommend_resource::{parse_item_id, recommended_items_response};
+use crate::vmisknn::similar_items::similar_items;
+use crate::vmisknn::similarity_indexed::{PostingLists, SimilarityComputationNew};
+use crate::vmisknn::ItemScore;
+
+#[derive(Debug, Deserialize)]
+pub struct V1SimilarQueryParams {
+    /// A numeric item id, or an external string id when the index has an item dictionary.
+    pub(crate) item_id: String,
+}
+
+// The items that co-occur with an item in the training sessions, e.g. for product detail pages.
+// Independent of any session, so nothing is stored.
+#[get("/v1/similar")]
+pub async fn v1_similar(
+    data: web::Data<SharedHandlesAndConfig>,
+    query: web::Query<V1SimilarQueryParams>,
+) -> HttpResponse {
+    let vsknn_index = data.vsknn_index.as_ref();
+    let item_dictionary = vsknn_index.item_dictionary();
+
+    let item_id = match parse_item_id(item_dictionary, &query.item_id) {
+        Ok(Some(item_id)) => item_id,
+        Ok(None) => return HttpResponse::Ok().json(Vec::<String>::new()),
+        Err(response) => return response,
+    };
+
+    let precomputed_similar_items = data
+        .similar_items
+        .as_ref()
+        .and_then(|item_to_similar_items| item_to_similar_items.get(&item_id).cloned());
+    let recommendations = precomputed_similar_items.unwrap_or_else(|| match &data.compressed_index {
+        Some(compressed_index) => similar_item_recommendations(compressed_index.as_ref(), &data, &item_id),
+        None => similar_item_recommendations(vsknn_index, &data, &item_id),
+    });
+
+    recommended_items_response(item_dictionary, &recommendations)
+}
+
+fn similar_item_recommendations<I: SimilarityComputationNew + PostingLists>(
+    index: &I,
+    data: &SharedHandlesAndConfig,
+    item_id: &u64,
+) -> Vec<ItemScore> {
+    similar_items(
+        index,
+        item_id,
+        data.m_most_recent_sessions,
+        data.num_items_to_recommend,
+        data.enable_business_logic,
+    )
+    .into_sorted_vec()
+}
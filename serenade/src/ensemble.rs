@@ -0,0 +1,249 @@
+use std::cmp::Ordering;
+use std::error::Error;
+use std::fmt;
+use std::str::FromStr;
+use std::sync::Arc;
+
+use hashbrown::HashMap;
+
+use crate::recommender::{RecommendParams, Recommender, RecommenderKind};
+use crate::vmisknn::weighting::SessionEvents;
+use crate::vmisknn::ItemScore;
+
+/// The constant of reciprocal rank fusion from the literature, which dampens the influence of the top ranks.
+pub const DEFAULT_RRF_K: f64 = 60.0;
+
+#[derive(Debug)]
+pub struct EnsembleError(String);
+
+impl fmt::Display for EnsembleError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "{}", self.0)
+    }
+}
+
+impl Error for EnsembleError {}
+
+/// How an ensemble combines the recommendations of its members.
+#[derive(Clone, Copy, Debug, Default, PartialEq, Eq)]
+pub enum Fusion {
+    /// The weighted sum of the scores of every member, divided by the score of its best item.
+    #[default]
+    Weighted,
+    /// The weighted sum of `1 / (rrf_k + rank)` of every member, which only uses the ranks of the items.
+    ReciprocalRank,
+}
+
+impl Fusion {
+    pub const ALL: [Fusion; 2] = [Fusion::Weighted, Fusion::ReciprocalRank];
+}
+
+impl FromStr for Fusion {
+    type Err = EnsembleError;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        match s {
+            "weighted" => Ok(Fusion::Weighted),
+            "rrf" => Ok(Fusion::ReciprocalRank),
+            _ => Err(EnsembleError(format!("Invalid fusion: {}", s))),
+        }
+    }
+}
+
+impl fmt::Display for Fusion {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        let name = match self {
+            Fusion::Weighted => "weighted",
+            Fusion::ReciprocalRank => "rrf",
+        };
+        write!(f, "{}", name)
+    }
+}
+
+/// The ensemble of the `[model]` section: its members with their weights and how they are combined.
+#[derive(Clone, Debug, PartialEq)]
+pub struct EnsembleConfig {
+    pub members: Vec<(RecommenderKind, f64)>,
+    pub fusion: Fusion,
+    pub rrf_k: f64,
+}
+
+impl Default for EnsembleConfig {
+    fn default() -> Self {
+        EnsembleConfig {
+            members: Vec::new(),
+            fusion: Fusion::default(),
+            rrf_k: DEFAULT_RRF_K,
+        }
+    }
+}
+
+/// Parses members in the format `vmisknn:0.7,sequential_rules:0.3`.
+pub fn parse_ensemble_members(members: &str) -> Result<Vec<(RecommenderKind, f64)>, EnsembleError> {
+    members
+        .split(',')
+        .map(|member| member.trim())
+        .filter(|member| !member.is_empty())
+        .map(|member| {
+            let invalid = || EnsembleError(format!("Invalid ensemble member: {}", member));
+            let (recommender, weight) = member.split_once(':').ok_or_else(invalid)?;
+            let recommender: RecommenderKind = recommender.trim().parse().map_err(|_| invalid())?;
+            let weight: f64 = weight.trim().parse().map_err(|_| invalid())?;
+            if recommender == RecommenderKind::Ensemble || !weight.is_finite() || weight < 0.0 {
+                return Err(invalid());
+            }
+            Ok((recommender, weight))
+        })
+        .collect()
+}
+
+/// Combines the recommendations of several recommenders into one list. Every member recommends the same number of
+/// items as the ensemble, and an item that a member doesn't recommend gets nothing from that member.
+pub struct EnsembleRecommender {
+    members: Vec<Arc<dyn Recommender>>,
+    weights: Vec<f64>,
+    fusion: Fusion,
+    rrf_k: f64,
+}
+
+impl EnsembleRecommender {
+    pub fn new(members: Vec<Arc<dyn Recommender>>, weights: Vec<f64>, fusion: Fusion, rrf_k: f64) -> Self {
+        assert_eq!(
+            members.len(),
+            weights.len(),
+            "An ensemble requires a weight for each of its members"
+        );
+        EnsembleRecommender {
+            members,
+            weights,
+            fusion,
+            rrf_k,
+        }
+    }
+
+    pub fn weights(&self) -> &[f64] {
+        &self.weights
+    }
+
+    /// Changes the weights without retraining the members, for the hyperparameter search.
+    pub fn set_weights(&mut self, weights: Vec<f64>) {
+        assert_eq!(
+            self.members.len(),
+            weights.len(),
+            "An ensemble requires a weight for each of its members"
+        );
+        self.weights = weights;
+    }
+
+    pub fn set_fusion(&mut self, fusion: Fusion) {
+        self.fusion = fusion;
+    }
+}
+
+impl Recommender for EnsembleRecommender {
+    fn recommend_with_events(
+        &self,
+        evolving_session: &[u64],
+        session_events: &SessionEvents,
+        params: &RecommendParams,
+    ) -> Vec<ItemScore> {
+        let mut item_scores: HashMap<u64, f64> = HashMap::new();
+        for (member, weight) in self.members.iter().zip(self.weights.iter()) {
+            if *weight == 0.0 {
+                continue;
+            }
+            let recommendations = member.recommend_with_events(evolving_session, session_events, params);
+            let best_score = recommendations.first().map_or(0.0, |scored| scored.score);
+            for (rank, scored) in recommendations.iter().enumerate() {
+                let fused_score = match self.fusion {
+                    Fusion::Weighted if best_score > 0.0 => scored.score / best_score,
+                    Fusion::Weighted => 0.0,
+                    Fusion::ReciprocalRank => 1.0 / (self.rrf_k + (rank + 1) as f64),
+                };
+                *item_scores.entry(scored.id).or_default() += weight * fused_score;
+            }
+        }
+
+        let mut recommendations: Vec<ItemScore> = item_scores
+            .into_iter()
+            .filter(|(_id, score)| *score > 0.0)
+            .map(|(id, score)| ItemScore::new(id, score))
+            .collect();
+        recommendations.sort_unstable_by(|a, b| {
+            b.score
+                .partial_cmp(&a.score)
+                .unwrap_or(Ordering::Equal)
+                .then(a.id.cmp(&b.id))
+        });
+        recommendations.truncate(params.how_many);
+        recommendations
+    }
+
+    fn kind(&self) -> RecommenderKind {
+        RecommenderKind::Ensemble
+    }
+}
+
+#[cfg(test)]
+mod ensemble_test {
+    use super::*;
+    use crate::rules::association_rules::AssociationRules;
+    use crate::rules::sequential_rules::SequentialRules;
+
+    fn params() -> RecommendParams {
+        RecommendParams {
+            neighborhood_size_k: 10,
+            m_most_recent_sessions: 10,
+            how_many: 10,
+            enable_business_logic: false,
+        }
+    }
+
+    fn recommended_items(recommender: &dyn Recommender, evolving_session: &[u64]) -> Vec<u64> {
+        recommender
+            .recommend(evolving_session, &params())
+            .iter()
+            .map(|scored| scored.id)
+            .collect()
+    }
+
+    #[test]
+    fn should_parse_members() {
+        let members = parse_ensemble_members("vmisknn:0.7, sequential_rules:0.3").unwrap();
+        assert_eq!(
+            vec![(RecommenderKind::VmisKnn, 0.7), (RecommenderKind::SequentialRules, 0.3)],
+            members
+        );
+        assert!(parse_ensemble_members("").unwrap().is_empty());
+        assert!(parse_ensemble_members("vmisknn").is_err());
+        assert!(parse_ensemble_members("vmisknn:-1").is_err());
+        assert!(parse_ensemble_members("ensemble:1").is_err());
+        assert_eq!(Fusion::ReciprocalRank, "rrf".parse().unwrap());
+    }
+
+    #[test]
+    fn should_fuse_the_members_by_their_weights() {
+        // Sequential rules of item 1 rank 2 before 3, association rules rank 3 before 2.
+        let sessions = vec![vec![1, 2], vec![1, 2], vec![3, 1], vec![3, 1], vec![3, 1]];
+        let sequential_rules: Arc<dyn Recommender> = Arc::new(SequentialRules::new(&sessions, 10, 0));
+        let association_rules: Arc<dyn Recommender> = Arc::new(AssociationRules::new(&sessions, 0));
+        assert_eq!(vec![2], recommended_items(sequential_rules.as_ref(), &[1]));
+        assert_eq!(vec![3, 2], recommended_items(association_rules.as_ref(), &[1]));
+
+        for fusion in Fusion::ALL.iter() {
+            let mut ensemble = EnsembleRecommender::new(
+                vec![sequential_rules.clone(), association_rules.clone()],
+                vec![1.0, 0.0],
+                *fusion,
+                DEFAULT_RRF_K,
+            );
+            assert_eq!(vec![2], recommended_items(&ensemble, &[1]));
+
+            ensemble.set_weights(vec![0.0, 1.0]);
+            assert_eq!(vec![3, 2], recommended_items(&ensemble, &[1]));
+
+            ensemble.set_weights(vec![1.0, 1.0]);
+            assert_eq!(vec![2, 3], recommended_items(&ensemble, &[1]));
+        }
+    }
+}
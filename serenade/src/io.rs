@@ -1,45 +1,490 @@
+use chrono::{DateTime, NaiveDateTime};
+use flate2::read::MultiGzDecoder;
 use hashbrown::HashMap;
 use itertools::Itertools;
 use rayon::iter::ParallelBridge;
 use rayon::prelude::ParallelIterator;
+use std::error::Error;
+use std::fmt;
 use std::fs::File;
-use std::io::{self, BufRead};
+use std::io::{self, BufRead, BufReader, Read};
 use std::path::Path;
+use std::str::FromStr;
+use std::sync::atomic::{AtomicUsize, Ordering};
+
+use crate::dictionary::IdDictionary;
 
 pub type TrainingSessionId = u32;
 pub type ItemId = u64;
 pub type Time = usize;
 
-pub fn read_training_data(training_data_path: &str) -> Vec<(TrainingSessionId, ItemId, Time)> {
-    let mut line_iterator = create_buffered_line_reader(training_data_path).unwrap();
-    line_iterator.next(); // skip header
-    let training_data = line_iterator.par_bridge().filter_map(move |result| {
-        if let Ok(rawline) = result {
-            let parts = rawline.split_whitespace().take(3).collect::<Vec<_>>();
-            let (session_id, item_id, time) = (
-                parts.get(0).unwrap().parse::<TrainingSessionId>().unwrap(),
-                parts.get(1).unwrap().parse::<ItemId>().unwrap(),
-                parts.get(2).unwrap().parse::<f64>().unwrap(),
-            );
-            Some((session_id, item_id, time.round() as Time))
+/// The deduplicated items of each training session in ascending order and the max timestamp of each session.
+pub type TrainingSessions = (Vec<Vec<ItemId>>, Vec<u32>);
+
+#[derive(Debug)]
+pub struct InputSchemaError(String);
+
+impl fmt::Display for InputSchemaError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "{}", self.0)
+    }
+}
+
+impl Error for InputSchemaError {}
+
+/// How the fields in a line of an input file are separated.
+#[derive(Clone, Copy, Debug, Default, PartialEq)]
+pub enum Delimiter {
+    /// Any amount of spaces and tabs.
+    #[default]
+    Whitespace,
+    Char(char),
+}
+
+impl FromStr for Delimiter {
+    type Err = InputSchemaError;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        match s {
+            "whitespace" => Ok(Delimiter::Whitespace),
+            "tab" | "\\t" | "\t" => Ok(Delimiter::Char('\t')),
+            "comma" => Ok(Delimiter::Char(',')),
+            "semicolon" => Ok(Delimiter::Char(';')),
+            _ => {
+                let mut chars = s.chars();
+                match (chars.next(), chars.next()) {
+                    (Some(delimiter), None) => Ok(Delimiter::Char(delimiter)),
+                    _ => Err(InputSchemaError(format!("Invalid delimiter: {}", s))),
+                }
+            }
+        }
+    }
+}
+
+/// A column in an input file, either by its name in the header line or by its zero-based position.
+#[derive(Clone, Debug, PartialEq)]
+pub enum Column {
+    Name(String),
+    Position(usize),
+}
+
+impl FromStr for Column {
+    type Err = InputSchemaError;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        if s.is_empty() {
+            Err(InputSchemaError("Empty column name".to_string()))
+        } else if let Ok(position) = s.parse::<usize>() {
+            Ok(Column::Position(position))
         } else {
-            None
+            Ok(Column::Name(s.to_string()))
         }
-    });
-    training_data.collect()
+    }
+}
+
+#[derive(Clone, Copy, Debug, Default, PartialEq)]
+pub enum TimestampFormat {
+    /// Seconds since epoch, fractional values are rounded.
+    #[default]
+    EpochSeconds,
+    /// Milliseconds since epoch.
+    EpochMillis,
+    /// For example `2021-06-16T19:21:58Z` or `2021-06-16T21:21:58.123+02:00`.
+    /// Timestamps without an offset are interpreted as UTC.
+    Iso8601,
+}
+
+impl FromStr for TimestampFormat {
+    type Err = InputSchemaError;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        match s {
+            "epoch_seconds" => Ok(TimestampFormat::EpochSeconds),
+            "epoch_millis" => Ok(TimestampFormat::EpochMillis),
+            "iso8601" => Ok(TimestampFormat::Iso8601),
+            _ => Err(InputSchemaError(format!("Invalid timestamp format: {}", s))),
+        }
+    }
+}
+
+#[derive(Clone, Copy, Debug, Default, PartialEq)]
+pub enum Compression {
+    /// Determined by the file extension: `.gz` for gzip, `.zst` for zstd and uncompressed otherwise.
+    #[default]
+    Auto,
+    None,
+    Gzip,
+    Zstd,
+}
+
+impl FromStr for Compression {
+    type Err = InputSchemaError;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        match s {
+            "auto" => Ok(Compression::Auto),
+            "none" => Ok(Compression::None),
+            "gzip" => Ok(Compression::Gzip),
+            "zstd" => Ok(Compression::Zstd),
+            _ => Err(InputSchemaError(format!("Invalid compression: {}", s))),
+        }
+    }
+}
+
+/// Describes the layout of the csv files with training and test data.
+/// The default matches `SessionId ItemId Time` with a header line, whitespace separated fields and the time
+/// in epoch seconds.
+/// With `string_ids`, session and item ids can be arbitrary strings that are dictionary encoded into dense
+/// internal ids while reading.
+#[derive(Clone, Debug, PartialEq)]
+pub struct InputSchema {
+    pub delimiter: Delimiter,
+    pub has_header: bool,
+    pub session_id_column: Column,
+    pub item_id_column: Column,
+    pub time_column: Column,
+    pub event_type_column: Option<Column>,
+    pub timestamp_format: TimestampFormat,
+    pub compression: Compression,
+    pub string_ids: bool,
 }
 
-fn create_buffered_line_reader<P>(filename: P) -> io::Result<io::Lines<io::BufReader<File>>>
+impl Default for InputSchema {
+    fn default() -> Self {
+        InputSchema {
+            delimiter: Delimiter::default(),
+            has_header: true,
+            session_id_column: Column::Position(0),
+            item_id_column: Column::Position(1),
+            time_column: Column::Position(2),
+            event_type_column: None,
+            timestamp_format: TimestampFormat::default(),
+            compression: Compression::default(),
+            string_ids: false,
+        }
+    }
+}
+
+#[derive(Clone, Debug, PartialEq)]
+pub struct ParsedInteraction<S, I> {
+    pub session_id: S,
+    pub item_id: I,
+    pub time: Time,
+    pub event_type: Option<String>,
+}
+
+pub type Interaction = ParsedInteraction<TrainingSessionId, ItemId>;
+
+/// An interaction with ids that still have to be dictionary encoded.
+type RawInteraction = ParsedInteraction<String, String>;
+
+pub struct Interactions {
+    pub records: Vec<Interaction>,
+    pub qty_malformed_rows: usize,
+    /// The mapping from external to internal item ids, only present for schemas with `string_ids`.
+    pub item_dictionary: Option<IdDictionary>,
+}
+
+struct ColumnPositions {
+    session_id: usize,
+    item_id: usize,
+    time: usize,
+    event_type: Option<usize>,
+}
+
+/// Reads all interactions from a (compressed) csv file. Rows that can't be parsed according to the schema
+/// are skipped and counted in `qty_malformed_rows`. The order of the records is not preserved.
+pub fn read_interactions(path: &str, schema: &InputSchema) -> Result<Interactions, Box<dyn Error>> {
+    read_interactions_with_dictionary(path, schema, IdDictionary::default())
+}
+
+/// Like `read_interactions`, but for schemas with `string_ids` the item ids are encoded with (and added to)
+/// an existing dictionary, e.g. the dictionary of the training data when reading test data.
+/// The dictionary is ignored for numeric ids.
+pub fn read_interactions_with_dictionary(
+    path: &str,
+    schema: &InputSchema,
+    item_dictionary: IdDictionary,
+) -> Result<Interactions, Box<dyn Error>> {
+    let mut line_iterator = create_buffered_line_reader(path, schema.compression)?;
+
+    let header = if schema.has_header {
+        match line_iterator.next() {
+            Some(line) => Some(line?),
+            None => None,
+        }
+    } else {
+        None
+    };
+    let header_fields = header
+        .as_deref()
+        .map(|line| split_fields(line, schema.delimiter));
+    let positions = ColumnPositions {
+        session_id: resolve_column(&schema.session_id_column, &header_fields)?,
+        item_id: resolve_column(&schema.item_id_column, &header_fields)?,
+        time: resolve_column(&schema.time_column, &header_fields)?,
+        event_type: match &schema.event_type_column {
+            Some(column) => Some(resolve_column(column, &header_fields)?),
+            None => None,
+        },
+    };
+
+    if schema.string_ids {
+        let (raw_records, qty_malformed_rows) = parse_lines(line_iterator, |line| {
+            parse_row(line, &positions, schema, |session_id, item_id| {
+                Some((non_empty(session_id)?.to_string(), non_empty(item_id)?.to_string()))
+            })
+        });
+        let (records, item_dictionary) = encode_ids(raw_records, item_dictionary);
+        Ok(Interactions {
+            records,
+            qty_malformed_rows,
+            item_dictionary: Some(item_dictionary),
+        })
+    } else {
+        let (records, qty_malformed_rows) = parse_lines(line_iterator, |line| {
+            parse_row(line, &positions, schema, |session_id, item_id| {
+                Some((
+                    session_id.parse::<TrainingSessionId>().ok()?,
+                    item_id.parse::<ItemId>().ok()?,
+                ))
+            })
+        });
+        Ok(Interactions {
+            records,
+            qty_malformed_rows,
+            item_dictionary: None,
+        })
+    }
+}
+
+/// Parses all non-blank lines in parallel and counts the lines that can't be parsed.
+fn parse_lines<T, F>(line_iterator: LineReader, parse: F) -> (Vec<T>, usize)
 where
-    P: AsRef<Path>,
+    T: Send,
+    F: Fn(&str) -> Option<T> + Sync + Send,
 {
-    let file = File::open(filename)?;
-    Ok(io::BufReader::new(file).lines())
+    let qty_malformed_rows = AtomicUsize::new(0);
+    let records = line_iterator
+        .par_bridge()
+        .filter_map(|result| {
+            let parsed = match result {
+                Ok(rawline) if rawline.trim().is_empty() => return None,
+                Ok(rawline) => parse(&rawline),
+                Err(_) => None,
+            };
+            if parsed.is_none() {
+                qty_malformed_rows.fetch_add(1, Ordering::Relaxed);
+            }
+            parsed
+        })
+        .collect();
+    (records, qty_malformed_rows.into_inner())
+}
+
+/// Replaces the string ids with dense internal ids. New item ids are added to the dictionary in sorted order,
+/// so that the same input always results in the same dictionary regardless of the parallel parsing.
+fn encode_ids(
+    raw_records: Vec<RawInteraction>,
+    mut item_dictionary: IdDictionary,
+) -> (Vec<Interaction>, IdDictionary) {
+    let new_item_ids: Vec<&str> = raw_records
+        .iter()
+        .map(|raw| raw.item_id.as_str())
+        .filter(|item_id| item_dictionary.to_internal(item_id).is_none())
+        .sorted_unstable()
+        .dedup()
+        .collect();
+    for item_id in new_item_ids {
+        item_dictionary.encode(item_id);
+    }
+
+    // Session ids only need to be consistent within this file, their mapping is not kept.
+    let mut session_dictionary = IdDictionary::default();
+    for session_id in raw_records.iter().map(|raw| raw.session_id.as_str()).sorted_unstable() {
+        session_dictionary.encode(session_id);
+    }
+
+    let records = raw_records
+        .into_iter()
+        .map(|raw| Interaction {
+            session_id: session_dictionary.to_internal(&raw.session_id).unwrap() as TrainingSessionId,
+            item_id: item_dictionary.to_internal(&raw.item_id).unwrap(),
+            time: raw.time,
+            event_type: raw.event_type,
+        })
+        .collect();
+
+    (records, item_dictionary)
+}
+
+fn split_fields(line: &str, delimiter: Delimiter) -> Vec<&str> {
+    match delimiter {
+        Delimiter::Whitespace => line.split_whitespace().collect(),
+        Delimiter::Char(delimiter) => line.split(delimiter).map(|field| field.trim()).collect(),
+    }
+}
+
+fn resolve_column(column: &Column, header_fields: &Option<Vec<&str>>) -> Result<usize, InputSchemaError> {
+    match column {
+        Column::Position(position) => Ok(*position),
+        Column::Name(name) => match header_fields {
+            Some(fields) => fields
+                .iter()
+                .position(|field| field == name)
+                .ok_or_else(|| InputSchemaError(format!("Column {} not found in header {:?}", name, fields))),
+            None => Err(InputSchemaError(format!(
+                "Column {} is referenced by name, but the input has no header",
+                name
+            ))),
+        },
+    }
 }
 
-pub fn read_test_data_evolving(test_data_file: &str) -> HashMap<TrainingSessionId, Vec<ItemId>> {
-    let training_data = read_training_data(test_data_file);
-    let evolving_sessions: HashMap<TrainingSessionId, Vec<ItemId>> = training_data
+fn parse_row<S, I>(
+    line: &str,
+    positions: &ColumnPositions,
+    schema: &InputSchema,
+    parse_ids: impl Fn(&str, &str) -> Option<(S, I)>,
+) -> Option<ParsedInteraction<S, I>> {
+    let fields = split_fields(line, schema.delimiter);
+    let (session_id, item_id) = parse_ids(fields.get(positions.session_id)?, fields.get(positions.item_id)?)?;
+    let time = parse_time(fields.get(positions.time)?, schema.timestamp_format)?;
+    let event_type = match positions.event_type {
+        Some(position) => Some(fields.get(position)?.to_string()),
+        None => None,
+    };
+    Some(ParsedInteraction {
+        session_id,
+        item_id,
+        time,
+        event_type,
+    })
+}
+
+fn non_empty(field: &str) -> Option<&str> {
+    if field.is_empty() {
+        None
+    } else {
+        Some(field)
+    }
+}
+
+fn parse_time(raw: &str, timestamp_format: TimestampFormat) -> Option<Time> {
+    let seconds = match timestamp_format {
+        TimestampFormat::EpochSeconds => raw.parse::<f64>().ok()?.round(),
+        TimestampFormat::EpochMillis => (raw.parse::<f64>().ok()? / 1000.0).round(),
+        TimestampFormat::Iso8601 => {
+            let timestamp = match DateTime::parse_from_rfc3339(raw) {
+                Ok(date_time) => date_time.timestamp(),
+                Err(_) => NaiveDateTime::parse_from_str(raw, "%Y-%m-%dT%H:%M:%S%.f")
+                    .or_else(|_| NaiveDateTime::parse_from_str(raw, "%Y-%m-%d %H:%M:%S%.f"))
+                    .ok()?
+                    .and_utc()
+                    .timestamp(),
+            };
+            timestamp as f64
+        }
+    };
+    if seconds.is_finite() && seconds >= 0.0 {
+        Some(seconds as Time)
+    } else {
+        None
+    }
+}
+
+type LineReader = io::Lines<BufReader<Box<dyn Read + Send>>>;
+
+fn create_buffered_line_reader(path: &str, compression: Compression) -> io::Result<LineReader> {
+    let file = File::open(path)?;
+    let compression = match compression {
+        Compression::Auto => match Path::new(path).extension().and_then(|extension| extension.to_str()) {
+            Some("gz") => Compression::Gzip,
+            Some("zst") => Compression::Zstd,
+            _ => Compression::None,
+        },
+        other => other,
+    };
+    let reader: Box<dyn Read + Send> = match compression {
+        Compression::Gzip => Box::new(MultiGzDecoder::new(file)),
+        Compression::Zstd => Box::new(zstd::Decoder::new(file)?),
+        _ => Box::new(file),
+    };
+    Ok(BufReader::new(reader).lines())
+}
+
+pub(crate) fn report_malformed_rows(path: &str, interactions: &Interactions) {
+    if interactions.qty_malformed_rows > 0 {
+        eprintln!(
+            "Skipped {} malformed rows in {}",
+            interactions.qty_malformed_rows, path
+        );
+    }
+}
+
+pub fn read_training_data(
+    training_data_path: &str,
+    schema: &InputSchema,
+) -> Vec<(TrainingSessionId, ItemId, Time)> {
+    let interactions = read_interactions(training_data_path, schema)
+        .unwrap_or_else(|err| panic!("Unable to read {}: {}", training_data_path, err));
+    report_malformed_rows(training_data_path, &interactions);
+    interactions
+        .records
+        .into_iter()
+        .map(|interaction| (interaction.session_id, interaction.item_id, interaction.time))
+        .collect()
+}
+
+pub fn read_test_data_evolving(
+    test_data_file: &str,
+    schema: &InputSchema,
+) -> HashMap<TrainingSessionId, Vec<ItemId>> {
+    let training_data = read_training_data(test_data_file, schema);
+    group_into_evolving_sessions(training_data)
+}
+
+/// Reads test data with string ids, using the item dictionary of the training data so that the internal item
+/// ids match the index. Items that don't occur in the training data get new internal ids.
+pub fn read_test_data_evolving_with_dictionary(
+    test_data_file: &str,
+    schema: &InputSchema,
+    item_dictionary: &IdDictionary,
+) -> HashMap<TrainingSessionId, Vec<ItemId>> {
+    let interactions = read_interactions_with_dictionary(test_data_file, schema, item_dictionary.clone())
+        .unwrap_or_else(|err| panic!("Unable to read {}: {}", test_data_file, err));
+    report_malformed_rows(test_data_file, &interactions);
+    let test_data = interactions
+        .records
+        .into_iter()
+        .map(|interaction| (interaction.session_id, interaction.item_id, interaction.time))
+        .collect();
+    group_into_evolving_sessions(test_data)
+}
+
+/// Reads the training sessions with their items ordered by time, including repeated items, for the models that
+/// learn from the order of the items. Sessions are ordered by their session id. For schemas with `string_ids`
+/// the item ids are encoded with the given item dictionary, so that they match the index.
+pub fn read_training_sessions_ordered(
+    path: &str,
+    schema: &InputSchema,
+    item_dictionary: Option<&IdDictionary>,
+) -> Vec<Vec<ItemId>> {
+    let evolving_sessions = match item_dictionary {
+        Some(item_dictionary) => read_test_data_evolving_with_dictionary(path, schema, item_dictionary),
+        None => read_test_data_evolving(path, schema),
+    };
+    evolving_sessions
+        .into_iter()
+        .sorted_unstable_by_key(|(session_id, _)| *session_id)
+        .map(|(_, session_items)| session_items)
+        .collect()
+}
+
+fn group_into_evolving_sessions(
+    test_data: Vec<(TrainingSessionId, ItemId, Time)>,
+) -> HashMap<TrainingSessionId, Vec<ItemId>> {
+    let evolving_sessions: HashMap<TrainingSessionId, Vec<ItemId>> = test_data
         .into_iter()
         .map(|(session_id, item_id, time)| (session_id, (item_id, time)))
         .into_group_map()
@@ -57,3 +502,183 @@ pub fn read_test_data_evolving(test_data_file: &str) -> HashMap<TrainingSessionI
 
     evolving_sessions
 }
+
+/// Reads the training sessions from a csv file: the deduplicated items of each session in ascending order
+/// and the max timestamp of each session. Sessions are ordered by their session id.
+pub fn read_training_sessions(
+    path: &str,
+    schema: &InputSchema,
+) -> Result<TrainingSessions, Box<dyn Error>> {
+    let interactions = read_interactions(path, schema)?;
+    report_malformed_rows(path, &interactions);
+    Ok(group_into_sessions(&interactions.records))
+}
+
+/// Groups interactions by session id into the deduplicated items of each session in ascending order
+/// and the max timestamp of each session. Sessions are ordered by their session id.
+pub fn group_into_sessions(interactions: &[Interaction]) -> TrainingSessions {
+    let mut ordered: Vec<&Interaction> = interactions.iter().collect();
+    ordered.sort_unstable_by_key(|interaction| interaction.session_id);
+
+    let mut historical_sessions: Vec<Vec<ItemId>> = Vec::new();
+    let mut historical_sessions_max_time_stamp: Vec<u32> = Vec::new();
+    for (_session_id, session_interactions) in &ordered
+        .into_iter()
+        .group_by(|interaction| interaction.session_id)
+    {
+        let mut session_items = Vec::new();
+        let mut max_time_stamp = 0;
+        for interaction in session_interactions {
+            session_items.push(interaction.item_id);
+            max_time_stamp = max_time_stamp.max(interaction.time);
+        }
+        session_items.sort_unstable();
+        session_items.dedup();
+        historical_sessions.push(session_items);
+        historical_sessions_max_time_stamp.push(max_time_stamp as u32);
+    }
+
+    (historical_sessions, historical_sessions_max_time_stamp)
+}
+
+#[cfg(test)]
+mod io_test {
+    use std::io::Write;
+
+    use tempfile::NamedTempFile;
+
+    use super::*;
+
+    /// The file is deleted when it is dropped at the end of the test.
+    fn write_temp_file(name: &str, content: &[u8]) -> NamedTempFile {
+        let mut file = tempfile::Builder::new()
+            .prefix("serenade_io_test_")
+            .suffix(name)
+            .tempfile()
+            .unwrap();
+        file.write_all(content).unwrap();
+        file
+    }
+
+    fn path(file: &NamedTempFile) -> String {
+        file.path().display().to_string()
+    }
+
+    fn sorted(mut records: Vec<Interaction>) -> Vec<Interaction> {
+        records.sort_unstable_by_key(|interaction| (interaction.session_id, interaction.time));
+        records
+    }
+
+    #[test]
+    fn should_read_default_schema_and_count_malformed_rows() {
+        let file = write_temp_file(
+            "default.txt",
+            b"SessionId\tItemId\tTime\n10036\t14957\t1592337718.0\n10036\tabc\t1592337765.0\n10037 7267 1591979344\n\n",
+        );
+        let interactions = read_interactions(&path(&file), &InputSchema::default()).unwrap();
+        assert_eq!(1, interactions.qty_malformed_rows);
+        let records = sorted(interactions.records);
+        assert_eq!(2, records.len());
+        assert_eq!((10036, 14957, 1592337718), (records[0].session_id, records[0].item_id, records[0].time));
+        assert_eq!((10037, 7267, 1591979344), (records[1].session_id, records[1].item_id, records[1].time));
+    }
+
+    #[test]
+    fn should_read_named_columns_with_iso8601_and_event_type() {
+        let file = write_temp_file(
+            "named.csv",
+            b"event,time,item,session\nview,2020-06-16T19:21:58Z,14957,1\npurchase,2020-06-16 19:22:58,14713,1\n",
+        );
+        let schema = InputSchema {
+            delimiter: Delimiter::Char(','),
+            session_id_column: Column::Name("session".to_string()),
+            item_id_column: Column::Name("item".to_string()),
+            time_column: Column::Name("time".to_string()),
+            event_type_column: Some(Column::Name("event".to_string())),
+            timestamp_format: TimestampFormat::Iso8601,
+            ..InputSchema::default()
+        };
+        let interactions = read_interactions(&path(&file), &schema).unwrap();
+        assert_eq!(0, interactions.qty_malformed_rows);
+        let records = sorted(interactions.records);
+        assert_eq!(1592335318, records[0].time);
+        assert_eq!(Some("view".to_string()), records[0].event_type);
+        assert_eq!(1592335378, records[1].time);
+        assert_eq!(Some("purchase".to_string()), records[1].event_type);
+    }
+
+    #[test]
+    fn should_read_compressed_files_with_millis() {
+        let content = b"1;5;1592337718123\n2;6;1592337765999\n";
+        let mut encoder = flate2::write::GzEncoder::new(Vec::new(), flate2::Compression::default());
+        encoder.write_all(content).unwrap();
+        let gzip_file = write_temp_file("millis.txt.gz", &encoder.finish().unwrap());
+        let zstd_file = write_temp_file("millis.txt.zst", &zstd::encode_all(&content[..], 0).unwrap());
+
+        let schema = InputSchema {
+            delimiter: Delimiter::Char(';'),
+            has_header: false,
+            timestamp_format: TimestampFormat::EpochMillis,
+            ..InputSchema::default()
+        };
+        for file in &[gzip_file, zstd_file] {
+            let records = sorted(read_interactions(&path(file), &schema).unwrap().records);
+            assert_eq!(vec![1592337718, 1592337766], records.iter().map(|r| r.time).collect_vec());
+        }
+    }
+
+    #[test]
+    fn should_reject_unknown_column_names() {
+        let file = write_temp_file("unknown.txt", b"SessionId ItemId Time\n1 2 3\n");
+        let schema = InputSchema {
+            time_column: Column::Name("Timestamp".to_string()),
+            ..InputSchema::default()
+        };
+        assert!(read_interactions(&path(&file), &schema).is_err());
+    }
+
+    #[test]
+    fn should_dictionary_encode_string_ids() {
+        let train_file = write_temp_file(
+            "strings_train.csv",
+            b"visit,sku,ts\nv1,B07XJ8C8F5,10\nv1,A01,11\nv2,B07XJ8C8F5,12\nv3,,13\n",
+        );
+        let test_file = write_temp_file("strings_test.csv", b"visit,sku,ts\nt1,A01,20\nt1,Z99,21\n");
+        let schema = InputSchema {
+            delimiter: Delimiter::Char(','),
+            string_ids: true,
+            ..InputSchema::default()
+        };
+
+        let interactions = read_interactions(&path(&train_file), &schema).unwrap();
+        assert_eq!(1, interactions.qty_malformed_rows);
+        let item_dictionary = interactions.item_dictionary.unwrap();
+        // new ids are assigned in sorted order of the external ids
+        assert_eq!(Some(0), item_dictionary.to_internal("A01"));
+        assert_eq!(Some(1), item_dictionary.to_internal("B07XJ8C8F5"));
+        let records = sorted(interactions.records);
+        assert_eq!(vec![(0, 1), (0, 0), (1, 1)], records.iter().map(|r| (r.session_id, r.item_id)).collect_vec());
+
+        let test_sessions = read_test_data_evolving_with_dictionary(&path(&test_file), &schema, &item_dictionary);
+        assert_eq!(vec![0, 2], test_sessions[&0]);
+    }
+
+    #[test]
+    fn should_group_into_sessions() {
+        let interaction = |session_id, item_id, time| Interaction {
+            session_id,
+            item_id,
+            time,
+            event_type: None,
+        };
+        let interactions = vec![
+            interaction(2, 30, 7),
+            interaction(1, 20, 5),
+            interaction(1, 10, 9),
+            interaction(1, 20, 11),
+        ];
+        let (sessions, max_time_stamps) = group_into_sessions(&interactions);
+        assert_eq!(vec![vec![10, 20], vec![30]], sessions);
+        assert_eq!(vec![11, 7], max_time_stamps);
+    }
+}
@@ -1,10 +1,17 @@
 pub mod config;
 pub mod config_processors;
 pub mod dataframeutils;
+pub mod dictionary;
+pub mod diversity;
+pub mod ensemble;
 pub mod endpoints;
 pub mod hyperparameter;
 pub mod io;
 pub mod metrics;
+pub mod recommendation_cache;
+pub mod recommender;
+pub mod rules;
 pub mod sessions;
 pub mod stopwatch;
+pub mod trending;
 pub mod vmisknn;
@@ -2,6 +2,7 @@ use crate::io::{ItemId, Time, TrainingSessionId};
 use crate::metrics::coverage::Coverage;
 use crate::metrics::f1score::F1score;
 use crate::metrics::hitrate::HitRate;
+use crate::metrics::intra_list_diversity::IntraListDiversity;
 use crate::metrics::mrr::Mrr;
 use crate::metrics::ndcg::Ndcg;
 use crate::metrics::popularity::Popularity;
@@ -18,6 +19,8 @@ pub struct EvaluationReporter {
     coverage: Coverage,
     recall: Recall,
     f1: F1score,
+    /// Only reported for items with categories and brands.
+    intra_list_diversity: Option<IntraListDiversity>,
 }
 
 impl EvaluationReporter {}
@@ -47,8 +50,14 @@ impl EvaluationReporter {
             coverage,
             recall,
             f1,
+            intra_list_diversity: None,
         }
     }
+
+    /// Also reports the intra-list diversity of the recommendations, after the other metrics.
+    pub fn set_intra_list_diversity(&mut self, intra_list_diversity: IntraListDiversity) {
+        self.intra_list_diversity = Some(intra_list_diversity);
+    }
 }
 
 impl EvaluationReporter {
@@ -61,6 +70,9 @@ impl EvaluationReporter {
         self.coverage.add(recommendations, next_items);
         self.recall.add(recommendations, next_items);
         self.f1.add(recommendations, next_items);
+        if let Some(intra_list_diversity) = self.intra_list_diversity.as_mut() {
+            intra_list_diversity.add(recommendations, next_items);
+        }
     }
 
     pub fn result(&self) -> String {
@@ -72,7 +84,7 @@ impl EvaluationReporter {
         let coverage_score = format!("{:.4}", self.coverage.result());
         let recall_score = format!("{:.4}", self.recall.result());
         let f1_score = format!("{:.4}", self.f1.result());
-        format!(
+        let result = format!(
             "{},{},{},{},{},{},{},{}",
             mrr_score,
             ndcg_score,
@@ -82,7 +94,11 @@ impl EvaluationReporter {
             coverage_score,
             recall_score,
             f1_score
-        )
+        );
+        match &self.intra_list_diversity {
+            Some(intra_list_diversity) => format!("{},{:.4}", result, intra_list_diversity.result()),
+            None => result,
+        }
     }
 
     pub fn get_name(&self) -> String {
@@ -94,7 +110,7 @@ impl EvaluationReporter {
         let coverage_name = self.coverage.get_name();
         let recall_name = self.recall.get_name();
         let f1_name = self.f1.get_name();
-        format!(
+        let names = format!(
             "{},{},{},{},{},{},{},{}",
             mrr_name,
             ndcg_name,
@@ -104,6 +120,10 @@ impl EvaluationReporter {
             coverage_name,
             recall_name,
             f1_name
-        )
+        );
+        match &self.intra_list_diversity {
+            Some(intra_list_diversity) => format!("{},{}", names, intra_list_diversity.get_name()),
+            None => names,
+        }
     }
 }
@@ -0,0 +1,105 @@
+use crate::metrics::SessionMetric;
+use crate::vmisknn::offline_index::{OfflineIndex, ProductAttributes};
+
+use std::cmp;
+use std::collections::HashMap;
+
+/// The mean dissimilarity of the pairs of recommended items, by the category and the brand of the items. Items
+/// without attributes are dissimilar to every item.
+pub struct IntraListDiversity {
+    sum_of_scores: f64,
+    qty: usize,
+    item_to_attributes: HashMap<u64, ProductAttributes>,
+    length: usize,
+}
+
+impl IntraListDiversity {
+    pub fn new(index: &OfflineIndex, length: usize) -> IntraListDiversity {
+        let item_to_attributes = index
+            .item_ids
+            .iter()
+            .zip(index.item_to_product_attributes.iter())
+            .filter_map(|(item_id, attributes)| attributes.as_ref().map(|attributes| (*item_id, attributes.clone())))
+            .collect();
+        IntraListDiversity::from_attributes(item_to_attributes, length)
+    }
+
+    fn from_attributes(item_to_attributes: HashMap<u64, ProductAttributes>, length: usize) -> IntraListDiversity {
+        IntraListDiversity {
+            sum_of_scores: 0.0,
+            qty: 0,
+            item_to_attributes,
+            length,
+        }
+    }
+
+    fn similarity(&self, left: &u64, right: &u64) -> f64 {
+        match (self.item_to_attributes.get(left), self.item_to_attributes.get(right)) {
+            (Some(left), Some(right)) => left.similarity(right),
+            _ => 0.0,
+        }
+    }
+}
+
+impl SessionMetric for IntraListDiversity {
+    fn add(&mut self, recommendations: &[u64], _next_items: &[u64]) {
+        let top_recos = &recommendations[..cmp::min(recommendations.len(), self.length)];
+        // Lists with less than two items have no pairs.
+        if top_recos.len() < 2 {
+            return;
+        }
+        let mut sum = 0_f64;
+        let mut qty_pairs = 0;
+        for (position, left) in top_recos.iter().enumerate() {
+            for right in top_recos[position + 1..].iter() {
+                sum += 1.0 - self.similarity(left, right);
+                qty_pairs += 1;
+            }
+        }
+        self.sum_of_scores += sum / qty_pairs as f64;
+        self.qty += 1;
+    }
+
+    fn result(&self) -> f64 {
+        if self.qty > 0 {
+            self.sum_of_scores / self.qty as f64
+        } else {
+            0.0
+        }
+    }
+
+    fn get_name(&self) -> String {
+        format!("IntraListDiversity@{}", self.length)
+    }
+}
+
+#[cfg(test)]
+mod intra_list_diversity_test {
+    use super::*;
+
+    fn attributes(category: u64, brand: u64) -> ProductAttributes {
+        ProductAttributes {
+            is_adult: false,
+            is_for_sale: true,
+            category: Some(category),
+            brand: Some(brand),
+            group: None,
+            canonical_item_id: None,
+        }
+    }
+
+    #[test]
+    fn should_calculate_intra_list_diversity() {
+        let item_to_attributes = vec![(1, attributes(10, 100)), (2, attributes(10, 100)), (3, attributes(10, 101))]
+            .into_iter()
+            .collect();
+        let mut mymetric = IntraListDiversity::from_attributes(item_to_attributes, 3);
+        // The pairs (1, 2), (1, 3) and (2, 3) are dissimilar by 0, 0.5 and 0.5, item 4 is cut off.
+        mymetric.add(&[1, 2, 3, 4], &[]);
+        // Item 4 has no attributes.
+        mymetric.add(&[1, 4], &[]);
+        mymetric.add(&[1], &[]);
+        assert!((((1.0 / 3.0) + 1.0) / 2.0 - mymetric.result()).abs() < 1e-9);
+        assert_eq!("IntraListDiversity@3", mymetric.get_name());
+    }
+}
@@ -2,6 +2,7 @@ pub mod coverage;
 pub mod evaluation_reporter;
 pub mod f1score;
 pub mod hitrate;
+pub mod intra_list_diversity;
 pub mod mrr;
 pub mod ndcg;
 pub mod popularity;
@@ -0,0 +1,326 @@
+use std::collections::hash_map::DefaultHasher;
+use std::hash::{Hash, Hasher};
+use std::sync::atomic::{AtomicU64, Ordering};
+use std::sync::{Arc, Mutex, RwLock};
+use std::time::{Duration, Instant};
+
+use lru::LruCache;
+use prometheus::{IntCounter, Registry};
+
+use crate::recommender::{RecommendParams, Recommender, RecommenderKind};
+use crate::vmisknn::weighting::SessionEvents;
+use crate::vmisknn::ItemScore;
+
+/// The limits of the recommendation cache of the `[cache]` section. A `capacity` of 0 disables the cache.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub struct CacheConfig {
+    /// The maximum number of cached session states over all shards.
+    pub capacity: usize,
+    pub ttl: Duration,
+    /// The number of independently locked parts of the cache, so that the workers rarely wait for each other.
+    pub qty_shards: usize,
+}
+
+/// Everything that the recommendations depend on: the evolving session, which the server has already truncated to
+/// `max_items_in_session` items, its event types and the model parameters.
+#[derive(Clone, Debug, PartialEq, Eq, Hash)]
+struct CacheKey {
+    evolving_session: Vec<u64>,
+    /// The bits of the item weights, because `f64` is not hashable.
+    item_weights: Vec<u64>,
+    excluded_items: Vec<u64>,
+    params: RecommendParams,
+}
+
+impl CacheKey {
+    fn new(evolving_session: &[u64], session_events: &SessionEvents, params: &RecommendParams) -> Self {
+        CacheKey {
+            evolving_session: evolving_session.to_vec(),
+            item_weights: session_events
+                .item_weights
+                .iter()
+                .map(|weight| weight.to_bits())
+                .collect(),
+            excluded_items: session_events.excluded_items.clone(),
+            params: *params,
+        }
+    }
+}
+
+struct CacheEntry {
+    recommendations: Vec<ItemScore>,
+    created: Instant,
+}
+
+/// Caches the recommendations of a model per evolving session state in sharded LRU caches, whose entries expire
+/// after the `ttl`. `swap` replaces the model and invalidates all cached recommendations.
+pub struct CachedRecommender {
+    recommender: RwLock<Arc<dyn Recommender>>,
+    shards: Vec<Mutex<LruCache<CacheKey, CacheEntry>>>,
+    ttl: Duration,
+    /// Incremented by every swap. Recommendations computed with a swapped model are not cached anymore.
+    generation: AtomicU64,
+    hits: IntCounter,
+    misses: IntCounter,
+}
+
+impl CachedRecommender {
+    pub fn new(recommender: Arc<dyn Recommender>, cache_config: &CacheConfig) -> Self {
+        let qty_shards = cache_config.qty_shards.max(1);
+        let shard_capacity = (cache_config.capacity / qty_shards).max(1);
+        CachedRecommender {
+            recommender: RwLock::new(recommender),
+            shards: (0..qty_shards)
+                .map(|_| Mutex::new(LruCache::new(shard_capacity)))
+                .collect(),
+            ttl: cache_config.ttl,
+            generation: AtomicU64::new(0),
+            hits: IntCounter::new(
+                "recommendation_cache_hits_total",
+                "Recommendations served from the cache",
+            )
+            .unwrap(),
+            misses: IntCounter::new(
+                "recommendation_cache_misses_total",
+                "Recommendations computed by the model, because they were not cached or expired",
+            )
+            .unwrap(),
+        }
+    }
+
+    /// Registers the hit and miss counters, the hit rate is `hits / (hits + misses)`.
+    pub fn register_metrics(&self, registry: &Registry) -> prometheus::Result<()> {
+        registry.register(Box::new(self.hits.clone()))?;
+        registry.register(Box::new(self.misses.clone()))
+    }
+
+    pub fn qty_hits(&self) -> u64 {
+        self.hits.get()
+    }
+
+    pub fn qty_misses(&self) -> u64 {
+        self.misses.get()
+    }
+
+    /// Replaces the model, for example by one with a new index, and drops the recommendations of the old one.
+    pub fn swap(&self, recommender: Arc<dyn Recommender>) {
+        let mut current_recommender = self.recommender.write().unwrap();
+        *current_recommender = recommender;
+        self.generation.fetch_add(1, Ordering::SeqCst);
+        for shard in self.shards.iter() {
+            shard.lock().unwrap().clear();
+        }
+    }
+
+    fn shard(&self, key: &CacheKey) -> &Mutex<LruCache<CacheKey, CacheEntry>> {
+        let mut hasher = DefaultHasher::new();
+        key.hash(&mut hasher);
+        &self.shards[hasher.finish() as usize % self.shards.len()]
+    }
+}
+
+impl Recommender for CachedRecommender {
+    fn recommend_with_events(
+        &self,
+        evolving_session: &[u64],
+        session_events: &SessionEvents,
+        params: &RecommendParams,
+    ) -> Vec<ItemScore> {
+        let key = CacheKey::new(evolving_session, session_events, params);
+        let shard = self.shard(&key);
+        {
+            let mut cache = shard.lock().unwrap();
+            match cache.get(&key) {
+                Some(entry) if entry.created.elapsed() <= self.ttl => {
+                    self.hits.inc();
+                    return entry.recommendations.clone();
+                }
+                Some(_expired) => {
+                    cache.pop(&key);
+                }
+                None => {}
+            }
+        }
+        self.misses.inc();
+
+        // The model is computed without holding the lock of the shard.
+        let (recommendations, generation) = {
+            let recommender = self.recommender.read().unwrap();
+            let generation = self.generation.load(Ordering::SeqCst);
+            (
+                recommender.recommend_with_events(evolving_session, session_events, params),
+                generation,
+            )
+        };
+        let mut cache = shard.lock().unwrap();
+        if generation == self.generation.load(Ordering::SeqCst) {
+            cache.put(
+                key,
+                CacheEntry {
+                    recommendations: recommendations.clone(),
+                    created: Instant::now(),
+                },
+            );
+        }
+        recommendations
+    }
+
+    fn kind(&self) -> RecommenderKind {
+        self.recommender.read().unwrap().kind()
+    }
+}
+
+#[cfg(test)]
+mod recommendation_cache_test {
+    use std::sync::atomic::AtomicUsize;
+
+    use super::*;
+
+    /// Recommends the items after the most recent item and counts how often it is asked.
+    struct CountingRecommender {
+        offset: u64,
+        qty_calls: AtomicUsize,
+    }
+
+    impl CountingRecommender {
+        fn new(offset: u64) -> Arc<Self> {
+            Arc::new(CountingRecommender {
+                offset,
+                qty_calls: AtomicUsize::new(0),
+            })
+        }
+    }
+
+    impl Recommender for CountingRecommender {
+        fn recommend_with_events(
+            &self,
+            evolving_session: &[u64],
+            _session_events: &SessionEvents,
+            params: &RecommendParams,
+        ) -> Vec<ItemScore> {
+            self.qty_calls.fetch_add(1, Ordering::SeqCst);
+            let most_recent_item = *evolving_session.last().unwrap();
+            (1..=params.how_many as u64)
+                .map(|position| ItemScore::new(most_recent_item + self.offset + position, 1.0 / position as f64))
+                .collect()
+        }
+
+        fn kind(&self) -> RecommenderKind {
+            RecommenderKind::VmisKnn
+        }
+    }
+
+    fn params(how_many: usize) -> RecommendParams {
+        RecommendParams {
+            neighborhood_size_k: 10,
+            m_most_recent_sessions: 10,
+            how_many,
+            enable_business_logic: false,
+        }
+    }
+
+    fn cache_config(capacity: usize, ttl: Duration) -> CacheConfig {
+        CacheConfig {
+            capacity,
+            ttl,
+            qty_shards: 1,
+        }
+    }
+
+    fn recommended_items(recommender: &dyn Recommender, evolving_session: &[u64], how_many: usize) -> Vec<u64> {
+        recommender
+            .recommend(evolving_session, &params(how_many))
+            .iter()
+            .map(|scored| scored.id)
+            .collect()
+    }
+
+    #[test]
+    fn should_cache_per_session_state_and_parameters() {
+        let model = CountingRecommender::new(0);
+        let cached = CachedRecommender::new(model.clone(), &cache_config(10, Duration::from_secs(60)));
+
+        assert_eq!(vec![2, 3], recommended_items(&cached, &[1], 2));
+        assert_eq!(vec![2, 3], recommended_items(&cached, &[1], 2));
+        assert_eq!(1, model.qty_calls.load(Ordering::SeqCst));
+        assert_eq!((1, 1), (cached.qty_hits(), cached.qty_misses()));
+
+        assert_eq!(vec![2, 3, 4], recommended_items(&cached, &[1], 3));
+        assert_eq!(vec![3, 4], recommended_items(&cached, &[5, 2], 2));
+        let session_events = SessionEvents {
+            item_weights: vec![1.0],
+            excluded_items: vec![3],
+        };
+        cached.recommend_with_events(&[1], &session_events, &params(2));
+        assert_eq!(4, model.qty_calls.load(Ordering::SeqCst));
+    }
+
+    #[test]
+    fn should_evict_the_least_recently_used_and_expired_recommendations() {
+        let model = CountingRecommender::new(0);
+        let cached = CachedRecommender::new(model.clone(), &cache_config(2, Duration::from_secs(60)));
+        recommended_items(&cached, &[1], 2);
+        recommended_items(&cached, &[2], 2);
+        recommended_items(&cached, &[1], 2);
+        recommended_items(&cached, &[3], 2);
+        assert_eq!(3, model.qty_calls.load(Ordering::SeqCst));
+        // [2] was the least recently used session state.
+        recommended_items(&cached, &[2], 2);
+        assert_eq!(4, model.qty_calls.load(Ordering::SeqCst));
+
+        let model = CountingRecommender::new(0);
+        let cached = CachedRecommender::new(model.clone(), &cache_config(2, Duration::from_millis(1)));
+        recommended_items(&cached, &[1], 2);
+        std::thread::sleep(Duration::from_millis(5));
+        recommended_items(&cached, &[1], 2);
+        assert_eq!(2, model.qty_calls.load(Ordering::SeqCst));
+    }
+
+    #[test]
+    fn should_invalidate_the_cache_on_swap() {
+        let cached = CachedRecommender::new(CountingRecommender::new(0), &cache_config(10, Duration::from_secs(60)));
+        assert_eq!(vec![2, 3], recommended_items(&cached, &[1], 2));
+
+        cached.swap(CountingRecommender::new(10));
+        assert_eq!(vec![12, 13], recommended_items(&cached, &[1], 2));
+    }
+
+    #[test]
+    fn should_never_serve_the_recommendations_of_a_swapped_model() {
+        let cached = Arc::new(CachedRecommender::new(
+            CountingRecommender::new(0),
+            &cache_config(10, Duration::from_secs(60)),
+        ));
+        // Concurrent requests may still compute with the old model while it is swapped.
+        let requests = {
+            let cached = cached.clone();
+            std::thread::spawn(move || {
+                for _ in 0..1000 {
+                    recommended_items(cached.as_ref(), &[1], 2);
+                }
+            })
+        };
+        for offset in (10..=1000).step_by(10) {
+            cached.swap(CountingRecommender::new(offset));
+            assert_eq!(vec![offset + 2, offset + 3], recommended_items(cached.as_ref(), &[1], 2));
+        }
+        requests.join().unwrap();
+    }
+
+    #[test]
+    fn should_register_the_counters() {
+        let cached = CachedRecommender::new(CountingRecommender::new(0), &cache_config(10, Duration::from_secs(60)));
+        let registry = Registry::new();
+        cached.register_metrics(&registry).unwrap();
+        recommended_items(&cached, &[1], 2);
+        recommended_items(&cached, &[1], 2);
+
+        let metric_values: Vec<(String, f64)> = registry
+            .gather()
+            .iter()
+            .map(|family| (family.get_name().to_string(), family.get_metric()[0].get_counter().get_value()))
+            .collect();
+        assert!(metric_values.contains(&("recommendation_cache_hits_total".to_string(), 1.0)));
+        assert!(metric_values.contains(&("recommendation_cache_misses_total".to_string(), 1.0)));
+    }
+}
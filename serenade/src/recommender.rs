@@ -0,0 +1,421 @@
+use std::fmt;
+use std::path::Path;
+use std::str::FromStr;
+use std::sync::Arc;
+
+use serde::{Deserialize, Serialize};
+
+use crate::config::ModelConfig;
+use crate::diversity::{Diversification, DiversifiedRecommender};
+use crate::ensemble::EnsembleRecommender;
+use crate::io::{read_training_data, InputSchema};
+use crate::rules::association_rules::AssociationRules;
+use crate::rules::sequential_rules::SequentialRules;
+use crate::vmisknn::compressed_index::CompressedOfflineIndex;
+use crate::vmisknn::offline_index::OfflineIndex;
+use crate::vmisknn::stan_index::StanIndex;
+use crate::vmisknn::tree_index::TreeIndex;
+use crate::vmisknn::vmisknn_index::VMISSkNNIndex;
+use crate::vmisknn::vmisknn_index_noopt::VMISSkNNIndexNoOpt;
+use crate::vmisknn::vmisknn_index_smallopt::VMISSkNNIndexSmallOpt;
+use crate::vmisknn::vsknn_index::VSkNNIndex;
+use crate::vmisknn::weighting::SessionEvents;
+use crate::vmisknn::ItemScore;
+
+/// The hyperparameters of a recommendation. Every model uses the ones that apply to it.
+#[derive(Clone, Copy, Debug, PartialEq, Eq, Hash, Serialize, Deserialize)]
+pub struct RecommendParams {
+    pub neighborhood_size_k: usize,
+    pub m_most_recent_sessions: usize,
+    pub how_many: usize,
+    pub enable_business_logic: bool,
+}
+
+/// A session-based recommendation model: recommends the next items for the items of an evolving session.
+/// Implemented by every index and model, so that the evaluators and the server can swap them by name.
+pub trait Recommender: Send + Sync {
+    /// The best `how_many` items for the evolving session, the best item first.
+    fn recommend(&self, evolving_session: &[u64], params: &RecommendParams) -> Vec<ItemScore> {
+        let session_events = SessionEvents::unweighted(evolving_session);
+        self.recommend_with_events(evolving_session, &session_events, params)
+    }
+
+    /// Like `recommend`, with the evolving session items weighted by their event types where the model supports
+    /// it. The `excluded_items` of the `session_events` are never recommended.
+    fn recommend_with_events(
+        &self,
+        evolving_session: &[u64],
+        session_events: &SessionEvents,
+        params: &RecommendParams,
+    ) -> Vec<ItemScore>;
+
+    fn kind(&self) -> RecommenderKind;
+}
+
+/// The recommendation models that can be chosen in the `[model]` section of the config.
+#[derive(Clone, Copy, Debug, Default, PartialEq, Eq)]
+pub enum RecommenderKind {
+    /// VMIS-kNN on the `OfflineIndex`.
+    #[default]
+    VmisKnn,
+    /// VMIS-kNN on the `VMISSkNNIndex` of the runtime comparisons.
+    VmisKnnHashMap,
+    VmisKnnNoOpt,
+    VmisKnnSmallOpt,
+    VmisKnnTree,
+    VsKnn,
+    SequentialRules,
+    AssociationRules,
+    Stan,
+    /// The `ensemble_members` of the `[model]` section, combined by the `ensemble_fusion`.
+    Ensemble,
+}
+
+impl RecommenderKind {
+    /// Whether the model can apply the business rules, which need the item attributes of the `OfflineIndex`. The
+    /// indexes of the runtime comparisons have no item attributes.
+    pub fn applies_business_logic(&self) -> bool {
+        !matches!(
+            self,
+            RecommenderKind::VmisKnnHashMap
+                | RecommenderKind::VmisKnnNoOpt
+                | RecommenderKind::VmisKnnSmallOpt
+                | RecommenderKind::VmisKnnTree
+                | RecommenderKind::VsKnn
+        )
+    }
+
+    /// The individual models, without the ensemble of them.
+    pub const ALL: [RecommenderKind; 9] = [
+        RecommenderKind::VmisKnn,
+        RecommenderKind::VmisKnnHashMap,
+        RecommenderKind::VmisKnnNoOpt,
+        RecommenderKind::VmisKnnSmallOpt,
+        RecommenderKind::VmisKnnTree,
+        RecommenderKind::VsKnn,
+        RecommenderKind::SequentialRules,
+        RecommenderKind::AssociationRules,
+        RecommenderKind::Stan,
+    ];
+}
+
+#[derive(Debug)]
+pub struct RecommenderKindError(String);
+
+impl fmt::Display for RecommenderKindError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "{}", self.0)
+    }
+}
+
+impl std::error::Error for RecommenderKindError {}
+
+impl FromStr for RecommenderKind {
+    type Err = RecommenderKindError;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        match s {
+            "vmisknn" => Ok(RecommenderKind::VmisKnn),
+            "vmisknn_hashmap" => Ok(RecommenderKind::VmisKnnHashMap),
+            "vmisknn_noopt" => Ok(RecommenderKind::VmisKnnNoOpt),
+            "vmisknn_smallopt" => Ok(RecommenderKind::VmisKnnSmallOpt),
+            "vmisknn_tree" => Ok(RecommenderKind::VmisKnnTree),
+            "vsknn" => Ok(RecommenderKind::VsKnn),
+            "sequential_rules" => Ok(RecommenderKind::SequentialRules),
+            "association_rules" => Ok(RecommenderKind::AssociationRules),
+            "stan" => Ok(RecommenderKind::Stan),
+            "ensemble" => Ok(RecommenderKind::Ensemble),
+            _ => Err(RecommenderKindError(format!("Invalid recommender: {}", s))),
+        }
+    }
+}
+
+impl fmt::Display for RecommenderKind {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        let name = match self {
+            RecommenderKind::VmisKnn => "vmisknn",
+            RecommenderKind::VmisKnnHashMap => "vmisknn_hashmap",
+            RecommenderKind::VmisKnnNoOpt => "vmisknn_noopt",
+            RecommenderKind::VmisKnnSmallOpt => "vmisknn_smallopt",
+            RecommenderKind::VmisKnnTree => "vmisknn_tree",
+            RecommenderKind::VsKnn => "vsknn",
+            RecommenderKind::SequentialRules => "sequential_rules",
+            RecommenderKind::AssociationRules => "association_rules",
+            RecommenderKind::Stan => "stan",
+            RecommenderKind::Ensemble => "ensemble",
+        };
+        write!(f, "{}", name)
+    }
+}
+
+/// Creates the model of the `[model]` section. The models other than VMIS-kNN on the `OfflineIndex` are trained on
+/// the csv file with the training data and use the item ids of the index, because the index translates the item ids
+/// of the requests. The recommendations are re-ranked by the `diversification` of the section.
+/// With `enable_business_logic`, models that can't apply the business rules are rejected.
+pub fn create_recommender(
+    model_config: &ModelConfig,
+    index: Arc<OfflineIndex>,
+    training_data_path: &str,
+    input_schema: &InputSchema,
+    enable_business_logic: bool,
+) -> Arc<dyn Recommender> {
+    if enable_business_logic {
+        let recommender_kinds = match model_config.recommender {
+            RecommenderKind::Ensemble => model_config
+                .ensemble
+                .members
+                .iter()
+                .map(|(member_kind, _weight)| *member_kind)
+                .collect(),
+            recommender_kind => vec![recommender_kind],
+        };
+        if let Some(recommender_kind) = recommender_kinds
+            .iter()
+            .find(|recommender_kind| !recommender_kind.applies_business_logic())
+        {
+            panic!(
+                "The {} recommender doesn't apply the business rules, it requires enable_business_logic = false",
+                recommender_kind
+            );
+        }
+    }
+    let recommender = create_recommender_of_kind(
+        model_config.recommender,
+        model_config,
+        index.clone(),
+        training_data_path,
+        input_schema,
+    );
+    diversify(recommender, index, model_config)
+}
+
+/// Creates VMIS-kNN on the compressed layout of an index, for `compressed_index` in the `[model]` section. The
+/// recommendations are re-ranked by the `diversification` of the section, like the models of `create_recommender`.
+pub fn create_compressed_recommender(
+    model_config: &ModelConfig,
+    compressed_index: Arc<CompressedOfflineIndex>,
+) -> Arc<dyn Recommender> {
+    if model_config.recommender != RecommenderKind::VmisKnn {
+        panic!(
+            "The compressed index only serves the vmisknn recommender, not the {} recommender",
+            model_config.recommender
+        );
+    }
+    let index = compressed_index.index().clone();
+    diversify(compressed_index, index, model_config)
+}
+
+fn diversify(
+    recommender: Arc<dyn Recommender>,
+    index: Arc<OfflineIndex>,
+    model_config: &ModelConfig,
+) -> Arc<dyn Recommender> {
+    if model_config.diversity.diversification == Diversification::None {
+        recommender
+    } else {
+        Arc::new(DiversifiedRecommender::new(recommender, index, &model_config.diversity))
+    }
+}
+
+/// Creates the model `recommender_kind` with the hyperparameters of the `[model]` section.
+pub fn create_recommender_of_kind(
+    recommender_kind: RecommenderKind,
+    model_config: &ModelConfig,
+    index: Arc<OfflineIndex>,
+    training_data_path: &str,
+    input_schema: &InputSchema,
+) -> Arc<dyn Recommender> {
+    if recommender_kind != RecommenderKind::VmisKnn
+        && recommender_kind != RecommenderKind::Ensemble
+        && !Path::new(training_data_path).is_file()
+    {
+        panic!(
+            "The {} recommender is trained on a csv file with training data: {}",
+            recommender_kind, training_data_path
+        );
+    }
+    let m_most_recent_sessions = model_config.m_most_recent_sessions;
+    match recommender_kind {
+        RecommenderKind::VmisKnn => index,
+        RecommenderKind::VmisKnnHashMap => Arc::new(VMISSkNNIndex::new(
+            training_data_path,
+            input_schema,
+            m_most_recent_sessions,
+        )),
+        RecommenderKind::VmisKnnNoOpt => Arc::new(VMISSkNNIndexNoOpt::new(
+            training_data_path,
+            input_schema,
+            m_most_recent_sessions,
+        )),
+        RecommenderKind::VmisKnnSmallOpt => Arc::new(VMISSkNNIndexSmallOpt::new(
+            training_data_path,
+            input_schema,
+            m_most_recent_sessions,
+        )),
+        RecommenderKind::VmisKnnTree => Arc::new(TreeIndex::new(
+            training_data_path,
+            input_schema,
+            m_most_recent_sessions,
+        )),
+        RecommenderKind::VsKnn => Arc::new(VSkNNIndex::new(
+            read_training_data(training_data_path, input_schema),
+            m_most_recent_sessions,
+            usize::MAX,
+        )),
+        RecommenderKind::SequentialRules => Arc::new(SequentialRules::new_from_csv(
+            training_data_path,
+            input_schema,
+            index.clone(),
+            model_config.sr_steps,
+            model_config.rules_pruning,
+        )),
+        RecommenderKind::AssociationRules => Arc::new(AssociationRules::new_from_csv(
+            training_data_path,
+            input_schema,
+            index.clone(),
+            model_config.rules_pruning,
+        )),
+        RecommenderKind::Stan => Arc::new(StanIndex::new_from_csv(
+            training_data_path,
+            input_schema,
+            index,
+            model_config.stan,
+        )),
+        RecommenderKind::Ensemble => {
+            let ensemble = &model_config.ensemble;
+            if ensemble.members.is_empty() {
+                panic!("The ensemble recommender requires ensemble_members");
+            }
+            let members = ensemble
+                .members
+                .iter()
+                .map(|(member_kind, _weight)| {
+                    create_recommender_of_kind(
+                        *member_kind,
+                        model_config,
+                        index.clone(),
+                        training_data_path,
+                        input_schema,
+                    )
+                })
+                .collect();
+            let weights = ensemble.members.iter().map(|(_member_kind, weight)| *weight).collect();
+            Arc::new(EnsembleRecommender::new(
+                members,
+                weights,
+                ensemble.fusion,
+                ensemble.rrf_k,
+            ))
+        }
+    }
+}
+
+#[cfg(test)]
+mod recommender_test {
+    use super::*;
+
+    #[test]
+    fn should_parse_the_names_of_all_recommenders() {
+        for recommender_kind in RecommenderKind::ALL.iter() {
+            let name = recommender_kind.to_string();
+            assert_eq!(*recommender_kind, name.parse::<RecommenderKind>().unwrap());
+        }
+        assert_eq!(RecommenderKind::Ensemble, "ensemble".parse().unwrap());
+        assert!("vmis".parse::<RecommenderKind>().is_err());
+    }
+
+    #[test]
+    fn should_create_every_recommender_by_name() {
+        let path = std::env::temp_dir().join(format!("serenade_recommenders_{}.csv", std::process::id()));
+        let training_data = "SessionId\tItemId\tTime\n\
+            1\t1\t10\n1\t2\t11\n1\t3\t12\n\
+            2\t1\t20\n2\t2\t21\n2\t4\t22\n\
+            3\t5\t30\n3\t1\t31\n";
+        std::fs::write(&path, training_data).unwrap();
+        let training_data_path = path.display().to_string();
+        let input_schema = InputSchema::default();
+        let index = Arc::new(OfflineIndex::new_from_csv(&training_data_path, &input_schema, 10));
+        let params = RecommendParams {
+            neighborhood_size_k: 10,
+            m_most_recent_sessions: 10,
+            how_many: 10,
+            enable_business_logic: false,
+        };
+
+        let scores = |recommendations: &[ItemScore]| -> Vec<f64> {
+            recommendations.iter().map(|scored| scored.score).collect()
+        };
+        let expected_scores = scores(&index.recommend(&[1], &params));
+
+        for recommender_kind in RecommenderKind::ALL.iter() {
+            let model_config = ModelConfig {
+                recommender: *recommender_kind,
+                m_most_recent_sessions: 10,
+                ..ModelConfig::default()
+            };
+            let recommender =
+                create_recommender(&model_config, index.clone(), &training_data_path, &input_schema, false);
+            assert_eq!(*recommender_kind, recommender.kind());
+
+            let recommendations = recommender.recommend(&[1], &params);
+            assert!(!recommendations.is_empty(), "{} recommends nothing", recommender_kind);
+            assert!(recommendations.iter().all(|scored| scored.id != 1));
+            let is_vmisknn = matches!(
+                recommender_kind,
+                RecommenderKind::VmisKnnHashMap
+                    | RecommenderKind::VmisKnnNoOpt
+                    | RecommenderKind::VmisKnnSmallOpt
+                    | RecommenderKind::VmisKnnTree
+            );
+            if is_vmisknn {
+                // Same scores as the `OfflineIndex`, ties may be in another order.
+                let recommended_scores = scores(&recommendations);
+                assert_eq!(expected_scores.len(), recommended_scores.len());
+                for (expected, recommended) in expected_scores.iter().zip(recommended_scores.iter()) {
+                    assert!((expected - recommended).abs() < 1e-9, "{} scores differently", recommender_kind);
+                }
+            }
+        }
+        std::fs::remove_file(&path).unwrap();
+    }
+
+    #[test]
+    #[should_panic(expected = "The vsknn recommender doesn't apply the business rules")]
+    fn should_reject_models_without_business_logic() {
+        let index = Arc::new(OfflineIndex::new_for_test(vec![vec![1, 2]], vec![10], 10));
+        let model_config = ModelConfig {
+            recommender: RecommenderKind::VsKnn,
+            ..ModelConfig::default()
+        };
+        create_recommender(&model_config, index, "", &InputSchema::default(), true);
+    }
+
+    #[test]
+    fn should_recommend_like_the_index_with_the_compressed_index() {
+        let create_index = || {
+            OfflineIndex::new_for_test(vec![vec![1, 2, 3], vec![1, 2, 4], vec![1, 5]], vec![10, 20, 30], 10)
+        };
+        let index = create_index();
+        let compressed_index = Arc::new(CompressedOfflineIndex::new(create_index()));
+        let recommender = create_compressed_recommender(&ModelConfig::default(), compressed_index);
+        let params = RecommendParams {
+            neighborhood_size_k: 10,
+            m_most_recent_sessions: 10,
+            how_many: 10,
+            enable_business_logic: false,
+        };
+        assert_eq!(RecommenderKind::VmisKnn, recommender.kind());
+        assert_eq!(index.recommend(&[1], &params), recommender.recommend(&[1], &params));
+    }
+
+    #[test]
+    #[should_panic(expected = "The compressed index only serves the vmisknn recommender")]
+    fn should_reject_other_models_with_the_compressed_index() {
+        let index = OfflineIndex::new_for_test(vec![vec![1, 2]], vec![10], 10);
+        let model_config = ModelConfig {
+            recommender: RecommenderKind::Stan,
+            ..ModelConfig::default()
+        };
+        create_compressed_recommender(&model_config, Arc::new(CompressedOfflineIndex::new(index)));
+    }
+}
@@ -0,0 +1,103 @@
+use std::sync::Arc;
+
+use hashbrown::HashMap;
+use itertools::Itertools;
+
+use crate::io::{read_training_sessions_ordered, InputSchema};
+use crate::recommender::{RecommendParams, Recommender, RecommenderKind};
+use crate::rules::ItemRules;
+use crate::vmisknn::offline_index::OfflineIndex;
+use crate::vmisknn::weighting::SessionEvents;
+use crate::vmisknn::ItemScore;
+
+/// Association rules: a rule between items `a` and `b` for every training session that contains both,
+/// regardless of their order.
+pub struct AssociationRules {
+    rules: ItemRules,
+    /// The item attributes of the business rules, absent for the rules of `new`.
+    index: Option<Arc<OfflineIndex>>,
+}
+
+impl AssociationRules {
+    pub fn new(sessions: &[Vec<u64>], pruning: usize) -> Self {
+        let mut rule_scores: HashMap<u64, HashMap<u64, f64>> = HashMap::new();
+        for session in sessions.iter() {
+            let unique_items: Vec<u64> = session.iter().copied().unique().collect();
+            for item in unique_items.iter() {
+                let consequents = rule_scores.entry(*item).or_default();
+                for other_item in unique_items.iter().filter(|other_item| *other_item != item) {
+                    *consequents.entry(*other_item).or_default() += 1.0;
+                }
+            }
+        }
+        AssociationRules {
+            rules: ItemRules::new(rule_scores, pruning),
+            index: None,
+        }
+    }
+
+    /// The `index` translates the string ids of the training data and has the item attributes of the business rules.
+    pub fn new_from_csv(
+        path_to_training: &str,
+        input_schema: &InputSchema,
+        index: Arc<OfflineIndex>,
+        pruning: usize,
+    ) -> Self {
+        let sessions = read_training_sessions_ordered(path_to_training, input_schema, index.item_dictionary());
+        let mut association_rules = AssociationRules::new(&sessions, pruning);
+        println!(
+            "Association rules: {} rules from {} sessions",
+            association_rules.rules.qty_rules(),
+            sessions.len()
+        );
+        association_rules.index = Some(index);
+        association_rules
+    }
+}
+
+impl Recommender for AssociationRules {
+    fn recommend_with_events(
+        &self,
+        evolving_session: &[u64],
+        session_events: &SessionEvents,
+        params: &RecommendParams,
+    ) -> Vec<ItemScore> {
+        let index = self.index.as_deref().filter(|_| params.enable_business_logic);
+        self.rules.recommend(evolving_session, session_events, params.how_many, index)
+    }
+
+    fn kind(&self) -> RecommenderKind {
+        RecommenderKind::AssociationRules
+    }
+}
+
+#[cfg(test)]
+mod association_rules_test {
+    use super::*;
+
+    fn params() -> RecommendParams {
+        RecommendParams {
+            neighborhood_size_k: 10,
+            m_most_recent_sessions: 10,
+            how_many: 10,
+            enable_business_logic: false,
+        }
+    }
+
+    #[test]
+    fn should_count_co_occurrences_regardless_of_the_order() {
+        let sessions = vec![vec![1, 2, 2, 3], vec![3, 1], vec![4, 2]];
+        let association_rules = AssociationRules::new(&sessions, 0);
+
+        let recommendations = association_rules.recommend(&[1], &params());
+        let expected = vec![ItemScore { id: 3, score: 2.0 }, ItemScore { id: 2, score: 1.0 }];
+        assert_eq!(expected, recommendations);
+
+        let recommended: Vec<u64> = association_rules
+            .recommend(&[2], &params())
+            .iter()
+            .map(|scored| scored.id)
+            .collect();
+        assert_eq!(vec![1, 3, 4], recommended);
+    }
+}
@@ -0,0 +1,76 @@
+use std::cmp::Ordering;
+
+use hashbrown::HashMap;
+
+use crate::vmisknn::offline_index::OfflineIndex;
+use crate::vmisknn::weighting::SessionEvents;
+use crate::vmisknn::{passes_business_rules_of_items, ItemScore};
+
+pub mod association_rules;
+pub mod sequential_rules;
+
+/// Rules from an item to the items that follow it, with the rules of every item ordered by descending score.
+/// Both rules models recommend the items of the rules of the most recent item in the evolving session.
+#[derive(Debug, Default)]
+pub struct ItemRules {
+    item_to_rules: HashMap<u64, Vec<ItemScore>>,
+}
+
+impl ItemRules {
+    /// Keeps the `pruning` best rules per item, 0 keeps all of them. Ties are broken by the item id, so that the
+    /// pruned rules don't depend on the order of the training data.
+    pub(crate) fn new(rule_scores: HashMap<u64, HashMap<u64, f64>>, pruning: usize) -> Self {
+        let item_to_rules = rule_scores
+            .into_iter()
+            .map(|(item, consequents)| {
+                let mut rules: Vec<ItemScore> = consequents
+                    .into_iter()
+                    .map(|(id, score)| ItemScore::new(id, score))
+                    .collect();
+                rules.sort_unstable_by(|a, b| {
+                    b.score
+                        .partial_cmp(&a.score)
+                        .unwrap_or(Ordering::Equal)
+                        .then(a.id.cmp(&b.id))
+                });
+                if pruning > 0 {
+                    rules.truncate(pruning);
+                }
+                rules.shrink_to_fit();
+                (item, rules)
+            })
+            .collect();
+        ItemRules { item_to_rules }
+    }
+
+    pub fn qty_rules(&self) -> usize {
+        self.item_to_rules.values().map(|rules| rules.len()).sum()
+    }
+
+    /// With an `index`, only the items that pass the business rules by their attributes in the index are recommended.
+    pub(crate) fn recommend(
+        &self,
+        evolving_session: &[u64],
+        session_events: &SessionEvents,
+        how_many: usize,
+        index: Option<&OfflineIndex>,
+    ) -> Vec<ItemScore> {
+        let most_recent_item = match evolving_session.last() {
+            Some(item) => item,
+            None => return Vec::new(),
+        };
+        match self.item_to_rules.get(most_recent_item) {
+            Some(rules) => rules
+                .iter()
+                .filter(|rule| {
+                    rule.id != *most_recent_item
+                        && !session_events.excluded_items.contains(&rule.id)
+                        && index.is_none_or(|index| passes_business_rules_of_items(index, most_recent_item, &rule.id))
+                })
+                .take(how_many)
+                .map(|rule| ItemScore::new(rule.id, rule.score))
+                .collect(),
+            None => Vec::new(),
+        }
+    }
+}
@@ -0,0 +1,152 @@
+use std::sync::Arc;
+
+use hashbrown::HashMap;
+
+use crate::io::{read_training_sessions_ordered, InputSchema};
+use crate::recommender::{RecommendParams, Recommender, RecommenderKind};
+use crate::rules::ItemRules;
+use crate::vmisknn::offline_index::OfflineIndex;
+use crate::vmisknn::weighting::SessionEvents;
+use crate::vmisknn::ItemScore;
+
+/// Sequential rules (Ludewig and Jannach, 2018): a rule from item `a` to item `b` for every time that `b` follows
+/// `a` within `steps` positions in a training session, weighted by one over the distance between them.
+pub struct SequentialRules {
+    rules: ItemRules,
+    /// The item attributes of the business rules, absent for the rules of `new`.
+    index: Option<Arc<OfflineIndex>>,
+}
+
+impl SequentialRules {
+    /// `sessions` are the items of the training sessions ordered by time.
+    pub fn new(sessions: &[Vec<u64>], steps: usize, pruning: usize) -> Self {
+        let mut rule_scores: HashMap<u64, HashMap<u64, f64>> = HashMap::new();
+        for session in sessions.iter() {
+            for (position, item) in session.iter().enumerate() {
+                for distance in 1..=steps.min(position) {
+                    let previous_item = session[position - distance];
+                    if previous_item != *item {
+                        *rule_scores
+                            .entry(previous_item)
+                            .or_default()
+                            .entry(*item)
+                            .or_default() += 1.0 / distance as f64;
+                    }
+                }
+            }
+        }
+        SequentialRules {
+            rules: ItemRules::new(rule_scores, pruning),
+            index: None,
+        }
+    }
+
+    /// The `index` translates the string ids of the training data and has the item attributes of the business rules.
+    pub fn new_from_csv(
+        path_to_training: &str,
+        input_schema: &InputSchema,
+        index: Arc<OfflineIndex>,
+        steps: usize,
+        pruning: usize,
+    ) -> Self {
+        let sessions = read_training_sessions_ordered(path_to_training, input_schema, index.item_dictionary());
+        let mut sequential_rules = SequentialRules::new(&sessions, steps, pruning);
+        println!(
+            "Sequential rules: {} rules from {} sessions",
+            sequential_rules.rules.qty_rules(),
+            sessions.len()
+        );
+        sequential_rules.index = Some(index);
+        sequential_rules
+    }
+}
+
+impl Recommender for SequentialRules {
+    fn recommend_with_events(
+        &self,
+        evolving_session: &[u64],
+        session_events: &SessionEvents,
+        params: &RecommendParams,
+    ) -> Vec<ItemScore> {
+        let index = self.index.as_deref().filter(|_| params.enable_business_logic);
+        self.rules.recommend(evolving_session, session_events, params.how_many, index)
+    }
+
+    fn kind(&self) -> RecommenderKind {
+        RecommenderKind::SequentialRules
+    }
+}
+
+#[cfg(test)]
+mod sequential_rules_test {
+    use crate::vmisknn::similarity_indexed::SimilarityComputationNew;
+
+    use super::*;
+
+    fn params() -> RecommendParams {
+        RecommendParams {
+            neighborhood_size_k: 10,
+            m_most_recent_sessions: 10,
+            how_many: 10,
+            enable_business_logic: false,
+        }
+    }
+
+    #[test]
+    fn should_weight_rules_by_the_distance_of_the_items() {
+        let sessions = vec![vec![1, 2, 3], vec![1, 3], vec![2, 1, 1, 4]];
+        let sequential_rules = SequentialRules::new(&sessions, 2, 0);
+
+        let recommendations = sequential_rules.recommend(&[1], &params());
+
+        // 1 -> 3 and 1 -> 4 once at distance 1 and once at distance 2, 1 -> 2 once at distance 1.
+        let expected = vec![
+            ItemScore { id: 3, score: 1.5 },
+            ItemScore { id: 4, score: 1.5 },
+            ItemScore { id: 2, score: 1.0 },
+        ];
+        assert_eq!(expected, recommendations);
+    }
+
+    #[test]
+    fn should_only_use_the_most_recent_item_and_skip_excluded_items() {
+        let sessions = vec![vec![1, 2], vec![3, 4, 5], vec![3, 5]];
+        let sequential_rules = SequentialRules::new(&sessions, 1, 1);
+
+        let recommended: Vec<u64> = sequential_rules
+            .recommend(&[1, 3], &params())
+            .iter()
+            .map(|scored| scored.id)
+            .collect();
+        // The pruning keeps a single rule per item, the tie between 4 and 5 is broken by the item id.
+        assert_eq!(vec![4], recommended);
+
+        let session_events = SessionEvents {
+            item_weights: vec![1.0],
+            excluded_items: vec![4],
+        };
+        assert!(sequential_rules
+            .recommend_with_events(&[3], &session_events, &params())
+            .is_empty());
+    }
+
+    #[test]
+    fn should_apply_the_business_rules_of_the_index() {
+        let sessions = vec![vec![1, 2, 3], vec![1, 3], vec![1, 4]];
+        let mut index = OfflineIndex::new_for_test(sessions.clone(), vec![10, 20, 30], 10);
+        let item = index.internal_item_id(&3).unwrap();
+        index.item_to_product_attributes[item as usize].as_mut().unwrap().is_for_sale = false;
+        let mut sequential_rules = SequentialRules::new(&sessions, 1, 0);
+        sequential_rules.index = Some(Arc::new(index));
+
+        let recommended = |enable_business_logic: bool| -> Vec<u64> {
+            let params = RecommendParams {
+                enable_business_logic,
+                ..params()
+            };
+            sequential_rules.recommend(&[1], &params).iter().map(|scored| scored.id).collect()
+        };
+        assert_eq!(vec![2, 3, 4], recommended(false));
+        assert_eq!(vec![2, 4], recommended(true));
+    }
+}
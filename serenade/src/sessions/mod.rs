@@ -2,16 +2,19 @@ use rocksdb::{DB, Options};
 use bincode;
 use std::time::{Duration, SystemTime};
 use crate::io::ItemId;
+use crate::vmisknn::weighting::EventTypeWeights;
 use serde::{Serialize, Deserialize};
 
 pub struct RocksDBSessionStore {
     rocks_db: DB,
     max_session_idle_duration_in_seconds: u64,
 }
-#[derive(Serialize, Deserialize, Debug)]
+#[derive(Serialize, Deserialize, Debug, Default)]
 pub struct DBValue {
     session_items: Vec<ItemId>,
     epoch_secs: u64,
+    /// The event type of every session item, `None` if it is unknown.
+    session_event_types: Vec<Option<String>>,
 }
 
 
@@ -35,42 +38,258 @@ impl RocksDBSessionStore {
     }
 
     pub fn get_session_items(&self, evolving_session_id: &u128) -> Vec<u64> {
+        self.get_session(evolving_session_id).0
+    }
+
+    /// The items of a session with the event type of every item.
+    pub fn get_session(&self, evolving_session_id: &u128) -> (Vec<u64>, Vec<Option<String>>) {
+        match self.get_active_session(evolving_session_id) {
+            Some(payload) => (payload.session_items, payload.session_event_types),
+            None => (Vec::new(), Vec::new()),
+        }
+    }
+
+    /// The stored session, unless it is idle for too long.
+    fn get_active_session(&self, evolving_session_id: &u128) -> Option<DBValue> {
         let serialized_session_id =
             bincode::serialize(&evolving_session_id).unwrap();
 
-        let bytes = self.rocks_db.get(&serialized_session_id).unwrap();
-
-        let session_items: Vec<u64> = match bytes {
-            Some(bytes) => {
-                let payload:DBValue = bincode::deserialize(&bytes).unwrap();
-                let now = self.get_seconds_since_epoch();
-                let seconds_since_last_event = now - payload.epoch_secs;
-                if seconds_since_last_event <= self.max_session_idle_duration_in_seconds {
-                    payload.session_items
-                } else {
-                    Vec::new()
-                }
-            }
-            None => Vec::new(),
-        };
-        session_items
+        let bytes = self.rocks_db.get(&serialized_session_id).unwrap()?;
+
+        // Sessions that were stored before the event types were added can't be read and start over.
+        let mut payload = bincode::deserialize::<DBValue>(&bytes).ok()?;
+        let now = self.get_seconds_since_epoch();
+        let seconds_since_last_event = now - payload.epoch_secs;
+        if seconds_since_last_event <= self.max_session_idle_duration_in_seconds {
+            // Keeps the event types aligned with the items.
+            payload.session_event_types.resize(payload.session_items.len(), None);
+            Some(payload)
+        } else {
+            None
+        }
     }
 
     pub fn update_session_items(&self, evolving_session_id: &u128,
                                 session_items: &[u64]) {
+        self.update_session(evolving_session_id, session_items, &vec![None; session_items.len()]);
+    }
+
+    /// Stores the items of a session with the event type of every item.
+    pub fn update_session(&self, evolving_session_id: &u128,
+                          session_items: &[u64], session_event_types: &[Option<String>]) {
         let serialized_session_id =
             bincode::serialize(evolving_session_id).unwrap();
         let now = self.get_seconds_since_epoch();
         let payload = DBValue {
             session_items: Vec::from(session_items),
             epoch_secs: now,
+            session_event_types: Vec::from(session_event_types),
         };
         let bytes = bincode::serialize(&payload).unwrap();
 
         let _ = self.rocks_db.put(&serialized_session_id, &bytes).unwrap();
     }
 
+    /// Moves the items of the session `from_session_id` into the session `into_session_id`, e.g. when a visitor logs
+    /// in and the session id changes. The session with the older last interaction comes first, and only the
+    /// `max_items_in_session` most recent items are kept. The source session is deleted.
+    pub fn merge_sessions(
+        &self,
+        from_session_id: &u128,
+        into_session_id: &u128,
+        max_items_in_session: usize,
+        event_type_weights: &EventTypeWeights,
+    ) {
+        if from_session_id == into_session_id {
+            return;
+        }
+        if let Some(from_session) = self.get_active_session(from_session_id) {
+            let merged_session = match self.get_active_session(into_session_id) {
+                Some(into_session) if into_session.epoch_secs < from_session.epoch_secs => {
+                    merge_session_items(into_session, from_session, max_items_in_session, event_type_weights)
+                }
+                Some(into_session) => {
+                    merge_session_items(from_session, into_session, max_items_in_session, event_type_weights)
+                }
+                None => merge_session_items(DBValue::default(), from_session, max_items_in_session, event_type_weights),
+            };
+            self.update_session(into_session_id, &merged_session.session_items, &merged_session.session_event_types);
+        }
+        let serialized_session_id = bincode::serialize(from_session_id).unwrap();
+        self.rocks_db.delete(&serialized_session_id).unwrap();
+    }
+
     fn get_seconds_since_epoch(&self) -> u64 {
         SystemTime::now().duration_since(SystemTime::UNIX_EPOCH).unwrap().as_secs()
     }
 }
+
+/// Appends the items of the newer session to the older one. A repeated item at the junction is only kept once, like a
+/// repeated interaction in `update_evolving_session`, with the stronger of both event types.
+fn merge_session_items(
+    older: DBValue,
+    newer: DBValue,
+    max_items_in_session: usize,
+    event_type_weights: &EventTypeWeights,
+) -> DBValue {
+    let mut session_items = older.session_items;
+    let mut session_event_types = older.session_event_types;
+    for (item_id, event_type) in newer.session_items.into_iter().zip(newer.session_event_types) {
+        if session_items.last() == Some(&item_id) {
+            let last_event_type = session_event_types.last_mut().unwrap();
+            if event_type_weights.is_stronger(event_type.as_deref(), last_event_type.as_deref()) {
+                *last_event_type = event_type;
+            }
+        } else {
+            session_items.push(item_id);
+            session_event_types.push(event_type);
+        }
+    }
+    if session_items.len() > max_items_in_session {
+        let qty_dropped = session_items.len() - max_items_in_session;
+        session_items.drain(0..qty_dropped);
+        session_event_types.drain(0..qty_dropped);
+    }
+    DBValue {
+        session_items,
+        epoch_secs: newer.epoch_secs,
+        session_event_types,
+    }
+}
+
+/// The recent items of every user over all of their sessions and devices, keyed by the hashed user id.
+pub struct RocksDBUserHistoryStore {
+    rocks_db: DB,
+    max_items: usize,
+}
+
+#[derive(Serialize, Deserialize, Debug, Default)]
+pub struct UserHistoryValue {
+    /// The most recent item last.
+    recent_items: Vec<ItemId>,
+    /// The hashed id of the session of every recent item.
+    session_ids: Vec<u128>,
+}
+
+impl RocksDBUserHistoryStore {
+    /// Users without an interaction for `ttl` are forgotten, and only the `max_items` most recent items are kept.
+    pub fn new(database_file: &str, ttl: Duration, max_items: usize) -> Self {
+        let mut options = Options::default();
+        options.create_if_missing(true);
+        options.optimize_for_point_lookup(5000);
+
+        let rocks_db = DB::open_with_ttl(&options, database_file, ttl).unwrap();
+
+        Self { rocks_db, max_items }
+    }
+
+    /// The recent items of the user from other sessions than `session_id`, the most recent item last. The items of
+    /// the current session are left out, because the evolving session already contains its most recent items.
+    pub fn get_recent_items(&self, user_id: &u128, session_id: &u128) -> Vec<u64> {
+        recent_items_of_other_sessions(self.get_history(user_id), session_id)
+    }
+
+    /// Moves the item to the end of the recent items of the user and drops the oldest items beyond `max_items`.
+    pub fn add_item(&self, user_id: &u128, session_id: &u128, item_id: u64) {
+        let payload = add_recent_item(self.get_history(user_id), session_id, item_id, self.max_items);
+        let serialized_user_id = bincode::serialize(user_id).unwrap();
+        let bytes = bincode::serialize(&payload).unwrap();
+        self.rocks_db.put(&serialized_user_id, &bytes).unwrap();
+    }
+
+    fn get_history(&self, user_id: &u128) -> UserHistoryValue {
+        let serialized_user_id = bincode::serialize(user_id).unwrap();
+        match self.rocks_db.get(&serialized_user_id).unwrap() {
+            Some(bytes) => bincode::deserialize::<UserHistoryValue>(&bytes).unwrap_or_default(),
+            None => UserHistoryValue::default(),
+        }
+    }
+}
+
+fn recent_items_of_other_sessions(history: UserHistoryValue, session_id: &u128) -> Vec<u64> {
+    history
+        .recent_items
+        .into_iter()
+        .zip(history.session_ids)
+        .filter(|(_, item_session_id)| item_session_id != session_id)
+        .map(|(item_id, _)| item_id)
+        .collect()
+}
+
+fn add_recent_item(history: UserHistoryValue, session_id: &u128, item_id: u64, max_items: usize) -> UserHistoryValue {
+    let mut recent_items = Vec::with_capacity(history.recent_items.len() + 1);
+    let mut session_ids = Vec::with_capacity(history.session_ids.len() + 1);
+    for (recent_item, item_session_id) in history.recent_items.into_iter().zip(history.session_ids) {
+        if recent_item != item_id {
+            recent_items.push(recent_item);
+            session_ids.push(item_session_id);
+        }
+    }
+    recent_items.push(item_id);
+    session_ids.push(*session_id);
+    if recent_items.len() > max_items {
+        let qty_dropped = recent_items.len() - max_items;
+        recent_items.drain(0..qty_dropped);
+        session_ids.drain(0..qty_dropped);
+    }
+    UserHistoryValue { recent_items, session_ids }
+}
+
+#[cfg(test)]
+mod sessions_test {
+    use crate::vmisknn::weighting::parse_event_type_weights;
+
+    use super::*;
+
+    fn session(session_items: Vec<u64>, event_types: Vec<Option<&str>>, epoch_secs: u64) -> DBValue {
+        DBValue {
+            session_items,
+            epoch_secs,
+            session_event_types: event_types.into_iter().map(|event_type| event_type.map(String::from)).collect(),
+        }
+    }
+
+    fn event_type_weights() -> EventTypeWeights {
+        let weights = parse_event_type_weights("view:1,cart:2").unwrap();
+        EventTypeWeights::new(weights, vec!["purchase".to_string()])
+    }
+
+    #[test]
+    fn should_merge_sessions_in_chronological_order() {
+        let anonymous = session(vec![1, 2], vec![None, Some("view")], 100);
+        let logged_in = session(vec![2, 3], vec![Some("cart"), Some("view")], 200);
+        let merged = merge_session_items(anonymous, logged_in, 10, &event_type_weights());
+        assert_eq!(vec![1, 2, 3], merged.session_items);
+        assert_eq!(vec![None, Some("cart".to_string()), Some("view".to_string())], merged.session_event_types);
+        assert_eq!(200, merged.epoch_secs);
+
+        // The stronger event type of the repeated item wins, also if it belongs to the older session.
+        let anonymous = session(vec![1, 2], vec![None, Some("purchase")], 100);
+        let logged_in = session(vec![2, 3], vec![Some("view"), None], 200);
+        let merged = merge_session_items(anonymous, logged_in, 10, &event_type_weights());
+        assert_eq!(vec![None, Some("purchase".to_string()), None], merged.session_event_types);
+
+        let anonymous = session(vec![1, 2], vec![None, None], 100);
+        let logged_in = session(vec![3, 4], vec![None, None], 200);
+        let merged = merge_session_items(anonymous, logged_in, 3, &event_type_weights());
+        assert_eq!(vec![2, 3, 4], merged.session_items);
+        assert_eq!(3, merged.session_event_types.len());
+    }
+
+    #[test]
+    fn should_leave_out_the_items_of_the_current_session() {
+        let history = add_recent_item(UserHistoryValue::default(), &1, 10, 3);
+        let history = add_recent_item(history, &1, 11, 3);
+        let history = add_recent_item(history, &2, 12, 3);
+        assert_eq!(vec![10, 11], recent_items_of_other_sessions(history, &2));
+
+        // A repeated item moves to the current session, and the oldest items are dropped beyond the maximum.
+        let history = add_recent_item(UserHistoryValue::default(), &1, 10, 3);
+        let history = add_recent_item(history, &1, 11, 3);
+        let history = add_recent_item(history, &2, 10, 3);
+        let history = add_recent_item(history, &2, 12, 3);
+        let history = add_recent_item(history, &2, 13, 3);
+        assert_eq!(vec![10, 12, 13], history.recent_items);
+        assert!(recent_items_of_other_sessions(history, &2).is_empty());
+    }
+}
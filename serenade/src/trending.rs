@@ -0,0 +1,284 @@
+use std::collections::VecDeque;
+use std::sync::{Arc, Mutex, RwLock};
+use std::time::{Duration, Instant};
+
+use hashbrown::HashMap;
+
+use crate::dictionary::IdDictionary;
+use crate::vmisknn::ItemScore;
+
+/// The number of buckets that the window is divided into, so that the counts leave the window minute by minute for
+/// a window of an hour.
+const QTY_BUCKETS: u32 = 60;
+
+/// The number of the most trending items that can fill up the recommendations.
+const QTY_TOP_TRENDING_ITEMS: usize = 100;
+
+/// The id of the first item that is not part of the item dictionary of the index. The ids of the dictionary are dense
+/// from zero, so the ids of the new items never collide with them, also not after the model is swapped.
+const FIRST_NEW_ITEM_ID: u64 = 1 << 63;
+
+/// The trending boost of the `[trending]` section. A `weight` of 0 disables it.
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub struct TrendingConfig {
+    /// The weight of the trending score of an item relative to its recommendation score, both divided by the best
+    /// score.
+    pub weight: f64,
+    /// Interactions older than the window are not counted.
+    pub window: Duration,
+    /// The count of an interaction halves with every half-life that passed.
+    pub half_life: Duration,
+}
+
+/// The decayed interaction counts of the items at the time that they were computed.
+#[derive(Default)]
+struct TrendingSnapshot {
+    computed: Option<Instant>,
+    item_to_score: HashMap<u64, f64>,
+    /// The most trending items, the best first.
+    top_items: Vec<ItemScore>,
+}
+
+/// Counts the interactions with the items in a sliding window of time buckets, e.g. the views of the last hour, so
+/// that items that suddenly trend can be recommended before they are part of the index.
+pub struct TrendingCounter {
+    config: TrendingConfig,
+    bucket_duration: Duration,
+    /// The start of every bucket with the interaction counts of its items, the most recent bucket last.
+    buckets: Mutex<VecDeque<(Instant, HashMap<u64, u32>)>>,
+    /// Recomputed at most once per bucket, so reading the scores doesn't sum up the buckets every time.
+    snapshot: RwLock<Arc<TrendingSnapshot>>,
+    /// The external ids of the interacted items that the item dictionary of the index doesn't know, numbered from
+    /// `FIRST_NEW_ITEM_ID`.
+    new_items: RwLock<IdDictionary>,
+}
+
+impl TrendingCounter {
+    pub fn new(config: &TrendingConfig) -> Self {
+        TrendingCounter {
+            config: *config,
+            bucket_duration: (config.window / QTY_BUCKETS).max(Duration::from_millis(1)),
+            buckets: Mutex::new(VecDeque::new()),
+            snapshot: RwLock::new(Arc::new(TrendingSnapshot::default())),
+            new_items: RwLock::new(IdDictionary::default()),
+        }
+    }
+
+    /// The id of an item of the `item_dictionary`, or the id that is assigned to a new item that is not part of the
+    /// index, so that the new item can be counted and recommended before it is part of the index.
+    pub fn item_id(&self, item_dictionary: &IdDictionary, external_item_id: &str) -> u64 {
+        if let Some(item_id) = item_dictionary.to_internal(external_item_id) {
+            return item_id;
+        }
+        if let Some(new_item_id) = self.new_items.read().unwrap().to_internal(external_item_id) {
+            return FIRST_NEW_ITEM_ID + new_item_id;
+        }
+        FIRST_NEW_ITEM_ID + self.new_items.write().unwrap().encode(external_item_id)
+    }
+
+    /// The external ids of the recommended items, of the `item_dictionary` or of the new items.
+    pub fn external_item_ids(&self, item_dictionary: &IdDictionary, recommendations: &[ItemScore]) -> Vec<String> {
+        let new_items = self.new_items.read().unwrap();
+        recommendations
+            .iter()
+            .filter_map(|scored| match scored.id.checked_sub(FIRST_NEW_ITEM_ID) {
+                Some(new_item_id) => new_items.to_external(new_item_id),
+                None => item_dictionary.to_external(scored.id),
+            })
+            .map(|external_item_id| external_item_id.to_string())
+            .collect()
+    }
+
+    pub fn record(&self, item_id: u64) {
+        self.record_at(item_id, Instant::now());
+    }
+
+    fn record_at(&self, item_id: u64, now: Instant) {
+        let mut buckets = self.buckets.lock().unwrap();
+        let is_new_bucket = buckets
+            .back()
+            .is_none_or(|(start, _)| now.saturating_duration_since(*start) >= self.bucket_duration);
+        if is_new_bucket {
+            buckets.push_back((now, HashMap::new()));
+            while let Some((start, _)) = buckets.front() {
+                if now.saturating_duration_since(*start) >= self.config.window {
+                    buckets.pop_front();
+                } else {
+                    break;
+                }
+            }
+        }
+        *buckets.back_mut().unwrap().1.entry(item_id).or_default() += 1;
+    }
+
+    fn snapshot_at(&self, now: Instant) -> Arc<TrendingSnapshot> {
+        {
+            let snapshot = self.snapshot.read().unwrap();
+            let is_recent = snapshot
+                .computed
+                .is_some_and(|computed| now.saturating_duration_since(computed) < self.bucket_duration);
+            if is_recent {
+                return snapshot.clone();
+            }
+        }
+
+        let mut item_to_score: HashMap<u64, f64> = HashMap::new();
+        for (start, item_to_count) in self.buckets.lock().unwrap().iter() {
+            let age = now.saturating_duration_since(*start);
+            if age >= self.config.window {
+                continue;
+            }
+            let decay = 0.5_f64.powf(age.as_secs_f64() / self.config.half_life.as_secs_f64());
+            for (item_id, count) in item_to_count.iter() {
+                *item_to_score.entry(*item_id).or_default() += decay * *count as f64;
+            }
+        }
+        let mut top_items: Vec<ItemScore> = item_to_score
+            .iter()
+            .map(|(item_id, score)| ItemScore::new(*item_id, *score))
+            .collect();
+        top_items.sort_unstable();
+        top_items.truncate(QTY_TOP_TRENDING_ITEMS);
+
+        let snapshot = Arc::new(TrendingSnapshot {
+            computed: Some(now),
+            item_to_score,
+            top_items,
+        });
+        *self.snapshot.write().unwrap() = snapshot.clone();
+        snapshot
+    }
+
+    /// Adds the weighted trending score to the recommendations and fills up the missing recommendations with the
+    /// most trending items that `is_candidate` accepts, e.g. those that are not part of the session and pass the
+    /// business rules. The scores are divided by the best recommendation and the most trending item.
+    pub fn boost(
+        &self,
+        recommendations: Vec<ItemScore>,
+        how_many: usize,
+        is_candidate: impl Fn(u64) -> bool,
+    ) -> Vec<ItemScore> {
+        self.boost_at(recommendations, how_many, is_candidate, Instant::now())
+    }
+
+    fn boost_at(
+        &self,
+        recommendations: Vec<ItemScore>,
+        how_many: usize,
+        is_candidate: impl Fn(u64) -> bool,
+        now: Instant,
+    ) -> Vec<ItemScore> {
+        let snapshot = self.snapshot_at(now);
+        let max_trending_score = match snapshot.top_items.first() {
+            Some(most_trending) if most_trending.score > 0.0 => most_trending.score,
+            _ => return recommendations,
+        };
+        let trending_boost = |item_id: &u64| {
+            self.config.weight * snapshot.item_to_score.get(item_id).copied().unwrap_or(0.0) / max_trending_score
+        };
+
+        let best_score = recommendations
+            .first()
+            .map_or(0.0, |scored| scored.score)
+            .max(f64::MIN_POSITIVE);
+        let mut boosted: Vec<ItemScore> = recommendations
+            .iter()
+            .map(|scored| ItemScore::new(scored.id, scored.score / best_score + trending_boost(&scored.id)))
+            .collect();
+        boosted.sort_unstable();
+
+        for trending in snapshot.top_items.iter() {
+            if boosted.len() >= how_many {
+                break;
+            }
+            if is_candidate(trending.id) && !boosted.iter().any(|scored| scored.id == trending.id) {
+                boosted.push(ItemScore::new(trending.id, trending_boost(&trending.id)));
+            }
+        }
+        boosted.truncate(how_many);
+        boosted
+    }
+}
+
+#[cfg(test)]
+mod trending_test {
+    use super::*;
+
+    fn config(weight: f64) -> TrendingConfig {
+        TrendingConfig {
+            weight,
+            window: Duration::from_secs(60 * 60),
+            half_life: Duration::from_secs(15 * 60),
+        }
+    }
+
+    fn ids(recommendations: &[ItemScore]) -> Vec<u64> {
+        recommendations.iter().map(|scored| scored.id).collect()
+    }
+
+    #[test]
+    fn should_decay_and_forget_the_interactions() {
+        let counter = TrendingCounter::new(&config(1.0));
+        let start = Instant::now();
+        counter.record_at(1, start);
+        counter.record_at(1, start);
+        counter.record_at(2, start + Duration::from_secs(15 * 60));
+
+        let snapshot = counter.snapshot_at(start + Duration::from_secs(15 * 60));
+        assert!((snapshot.item_to_score[&1] - 1.0).abs() < 1e-9);
+        assert!((snapshot.item_to_score[&2] - 1.0).abs() < 1e-9);
+
+        // Item 1 left the window.
+        counter.record_at(3, start + Duration::from_secs(61 * 60));
+        let snapshot = counter.snapshot_at(start + Duration::from_secs(61 * 60));
+        assert!(!snapshot.item_to_score.contains_key(&1));
+        assert_eq!(vec![3, 2], ids(&snapshot.top_items));
+    }
+
+    #[test]
+    fn should_boost_and_fill_up_the_recommendations() {
+        let counter = TrendingCounter::new(&config(1.0));
+        let now = Instant::now();
+        for _ in 0..3 {
+            counter.record_at(30, now);
+        }
+        counter.record_at(20, now);
+        counter.record_at(40, now);
+        counter.record_at(50, now);
+
+        let recommendations = vec![ItemScore::new(10, 2.0), ItemScore::new(20, 1.8)];
+        // Item 20 gets 1/3 of the boost of the most trending item 30, which is excluded.
+        let boosted = counter.boost_at(recommendations.clone(), 4, |item_id| item_id != 30, now);
+        assert_eq!(vec![20, 10, 40, 50], ids(&boosted));
+        assert!((boosted[0].score - (0.9 + 1.0 / 3.0)).abs() < 1e-9);
+
+        // Empty recommendations are filled with the most trending items.
+        let filled = counter.boost_at(Vec::new(), 2, |_| true, now);
+        assert_eq!(vec![30, 20], ids(&filled));
+
+        let without_interactions = TrendingCounter::new(&config(1.0));
+        assert_eq!(recommendations, without_interactions.boost_at(recommendations.clone(), 4, |_| true, now));
+    }
+
+    #[test]
+    fn should_count_and_recommend_a_brand_new_item() {
+        let mut item_dictionary = IdDictionary::default();
+        item_dictionary.encode("A");
+        item_dictionary.encode("B");
+        let counter = TrendingCounter::new(&config(1.0));
+        let now = Instant::now();
+
+        let new_item_id = counter.item_id(&item_dictionary, "NEW");
+        assert_eq!(FIRST_NEW_ITEM_ID, new_item_id);
+        assert_eq!(new_item_id, counter.item_id(&item_dictionary, "NEW"));
+        assert_eq!(1, counter.item_id(&item_dictionary, "B"));
+
+        counter.record_at(new_item_id, now);
+        counter.record_at(new_item_id, now);
+        counter.record_at(1, now);
+
+        let filled = counter.boost_at(Vec::new(), 3, |_| true, now);
+        assert_eq!(vec![new_item_id, 1], ids(&filled));
+        assert_eq!(vec!["NEW", "B"], counter.external_item_ids(&item_dictionary, &filled));
+    }
+}
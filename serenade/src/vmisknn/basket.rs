@@ -0,0 +1,145 @@
+use std::collections::{BinaryHeap, HashMap};
+
+use crate::vmisknn::offline_index::find_neighbors_in_posting_lists;
+use crate::vmisknn::similarity_indexed::{PostingLists, SimilarityComputationNew};
+use crate::vmisknn::weighting::{SessionEvents, Weighting, WeightingFunction};
+use crate::vmisknn::{select_top_items, ItemScore, ITEM_SCORES};
+
+/// Recommends items for a set of items without an order, such as a basket, without an evolving session.
+/// The neighbor sessions are scored by the weighted overlap with the basket: every basket item adds its weight to
+/// the similarity of the sessions that contain it, regardless of its position. The items of the neighbors are scored
+/// like in `predict` without the match weight. Basket items are not recommended, and the business rules allow adult
+/// items if the basket contains one.
+pub fn predict_basket<I: SimilarityComputationNew + PostingLists>(
+    index: &I,
+    basket_items: &[u64],
+    item_weights: &[f64],
+    k: usize,
+    m: usize,
+    how_many: usize,
+    enable_business_logic: bool,
+) -> BinaryHeap<ItemScore> {
+    assert_eq!(
+        basket_items.len(),
+        item_weights.len(),
+        "A basket requires a weight for each of its items"
+    );
+    // Repeated items add up their weights, the neighbor search only counts one occurrence of an item.
+    let mut item_to_position: HashMap<u64, usize> = HashMap::with_capacity(basket_items.len());
+    let mut unique_items: Vec<u64> = Vec::with_capacity(basket_items.len());
+    let mut unique_weights: Vec<f64> = Vec::with_capacity(basket_items.len());
+    for (item_id, weight) in basket_items.iter().zip(item_weights.iter()) {
+        match item_to_position.get(item_id) {
+            Some(position) => unique_weights[*position] += weight,
+            None => {
+                item_to_position.insert(*item_id, unique_items.len());
+                unique_items.push(*item_id);
+                unique_weights.push(*weight);
+            }
+        }
+    }
+
+    let unordered = Weighting {
+        position_decay: WeightingFunction::Constant,
+        match_weight: WeightingFunction::Constant,
+        ..index.weighting()
+    };
+    let neighbors = find_neighbors_in_posting_lists(
+        &unique_items,
+        &unique_weights,
+        k,
+        m,
+        index.session_to_max_time_stamp(),
+        index.max_time_stamp(),
+        &unordered,
+        |item_id| index.internal_item_id(item_id).map(|item| index.posting_list(&item)),
+    );
+
+    // The business rules compare the recommended items to one basket item, an adult one if there is one.
+    let basket_internal: Vec<_> = unique_items
+        .iter()
+        .filter_map(|item_id| index.internal_item_id(item_id))
+        .collect();
+    let reference_item = basket_internal
+        .iter()
+        .find(|item| index.find_attributes(item).is_some_and(|attributes| attributes.is_adult))
+        .or_else(|| basket_internal.first())
+        .copied();
+    let session_events = SessionEvents {
+        item_weights: unique_weights,
+        excluded_items: unique_items,
+    };
+
+    ITEM_SCORES.with(|item_scores| {
+        let mut item_scores = item_scores.borrow_mut();
+        item_scores.prepare(index.qty_items());
+
+        for scored_session in neighbors.into_sorted_vec() {
+            for (position, item) in index.items_for_session(&scored_session.id).enumerate() {
+                let event_type_weight = index.session_item_weight(&scored_session.id, position);
+                item_scores.add(item, event_type_weight * index.idf(&item) * scored_session.score);
+            }
+        }
+
+        select_top_items(
+            index,
+            &mut item_scores,
+            reference_item,
+            &session_events,
+            how_many,
+            enable_business_logic,
+        )
+    })
+}
+
+#[cfg(test)]
+mod basket_test {
+    use crate::vmisknn;
+    use crate::vmisknn::offline_index::OfflineIndex;
+
+    use super::*;
+
+    fn create_index() -> OfflineIndex {
+        let historical_sessions = vec![vec![1, 2, 3], vec![1, 2, 6], vec![1, 3, 4], vec![2, 4], vec![2, 5]];
+        let max_time_stamps = vec![10, 20, 30, 40, 50];
+        OfflineIndex::new_for_test(historical_sessions, max_time_stamps, 10)
+    }
+
+    fn recommended_items(recommendations: BinaryHeap<ItemScore>) -> Vec<u64> {
+        recommendations
+            .into_sorted_vec()
+            .iter()
+            .map(|scored| scored.id)
+            .collect()
+    }
+
+    #[test]
+    fn should_ignore_the_order_of_the_basket() {
+        let index = create_index();
+        let basket = recommended_items(predict_basket(&index, &[1, 2], &[1.0, 1.0], 10, 10, 10, false));
+        let reversed_basket = recommended_items(predict_basket(&index, &[2, 1], &[1.0, 1.0], 10, 10, 10, false));
+        assert_eq!(basket, reversed_basket);
+        assert!(!basket.contains(&1) && !basket.contains(&2));
+
+        // The evolving session [1, 2] weights the more recent item 2 higher and also recommends item 1.
+        let session = recommended_items(vmisknn::predict(&index, &[1, 2], 10, 10, 10, false));
+        assert!(session.contains(&1));
+    }
+
+    #[test]
+    fn should_score_the_neighbors_by_weighted_overlap() {
+        let index = create_index();
+        // With k = 1, the only neighbor is the most recent session with the largest overlap.
+        assert_eq!(vec![6], recommended_items(predict_basket(&index, &[1, 2], &[1.0, 1.0], 1, 10, 10, false)));
+        // Item 1 outweighs item 2, so the neighbor is the most recent session with item 1.
+        assert_eq!(
+            vec![3, 4],
+            recommended_items(predict_basket(&index, &[1, 5], &[3.0, 1.0], 1, 10, 10, false))
+        );
+        // Repeated items add up their weights.
+        assert_eq!(
+            recommended_items(predict_basket(&index, &[1, 5, 1], &[1.0, 2.0, 2.0], 1, 10, 10, false)),
+            recommended_items(predict_basket(&index, &[1, 5], &[3.0, 2.0], 1, 10, 10, false))
+        );
+    }
+}
@@ -0,0 +1,406 @@
+use std::collections::BinaryHeap;
+use std::mem;
+use std::sync::Arc;
+
+use crate::recommender::{RecommendParams, Recommender, RecommenderKind};
+use crate::vmisknn;
+use crate::vmisknn::offline_index::{find_neighbors_in_posting_lists, OfflineIndex, ProductAttributes};
+use crate::vmisknn::product_groups::ProductGroups;
+use crate::vmisknn::similarity_indexed::{PostingLists, SimilarityComputationNew};
+use crate::vmisknn::weighting::{SessionEvents, Weighting};
+use crate::vmisknn::{InternalItemId, ItemScore, SessionScore};
+
+/// The position of the posting list of an item in `CompressedOfflineIndex::posting_list_bytes`.
+struct PostingList {
+    offset: usize,
+    qty_sessions: u32,
+}
+
+/// A memory efficient variant of the `OfflineIndex` for very large amounts of training sessions.
+/// The posting lists with the most recent sessions per item are delta encoded (zigzag, because they are
+/// ordered by time and not by session id) and stored as varints in a single buffer. The items per session
+/// are ascending, so they are delta encoded as varints as well. Both are decoded on the fly.
+/// The item data, such as the idf, the attributes and the item dictionary, stays in the `OfflineIndex` that the
+/// lists were taken from. The server uses this layout with `compressed_index` in the `[model]` section.
+pub struct CompressedOfflineIndex {
+    /// The index without its posting lists and session item lists, which are only kept compressed.
+    index: Arc<OfflineIndex>,
+    item_to_posting_list: Vec<PostingList>,
+    posting_list_bytes: Vec<u8>,
+    /// The start of the items of session `i` in `session_items_bytes`, with an extra end offset at the end.
+    session_offsets: Vec<usize>,
+    session_items_bytes: Vec<u8>,
+}
+
+impl CompressedOfflineIndex {
+    /// Compresses the posting lists and session item lists of a complete index, including its weighting, event
+    /// type weights, item attributes and product groups.
+    pub fn new(mut index: OfflineIndex) -> Self {
+        let item_to_top_sessions_ordered = mem::take(&mut index.item_to_top_sessions_ordered);
+        let mut item_to_posting_list = Vec::with_capacity(item_to_top_sessions_ordered.len());
+        let mut posting_list_bytes = Vec::new();
+        for session_ids in item_to_top_sessions_ordered.iter() {
+            let offset = posting_list_bytes.len();
+            let mut previous = 0_i64;
+            for session_id in session_ids.iter() {
+                let session_id = *session_id as i64;
+                write_varint(&mut posting_list_bytes, zigzag_encode(session_id - previous));
+                previous = session_id;
+            }
+            item_to_posting_list.push(PostingList {
+                offset,
+                qty_sessions: session_ids.len() as u32,
+            });
+        }
+        drop(item_to_top_sessions_ordered);
+        posting_list_bytes.shrink_to_fit();
+
+        let session_to_items_sorted = mem::take(&mut index.session_to_items_sorted);
+        let mut session_offsets = Vec::with_capacity(session_to_items_sorted.len() + 1);
+        let mut session_items_bytes = Vec::new();
+        for items in session_to_items_sorted.iter() {
+            session_offsets.push(session_items_bytes.len());
+            let mut previous = 0;
+            for item in items.iter() {
+                write_varint(&mut session_items_bytes, (item - previous) as u64);
+                previous = *item;
+            }
+        }
+        session_offsets.push(session_items_bytes.len());
+        drop(session_to_items_sorted);
+        session_items_bytes.shrink_to_fit();
+
+        CompressedOfflineIndex {
+            index: Arc::new(index),
+            item_to_posting_list,
+            posting_list_bytes,
+            session_offsets,
+            session_items_bytes,
+        }
+    }
+
+    /// The index that holds the item data, for the lookups of items and their attributes. Its posting lists and
+    /// session item lists are empty, so it can't search neighbors itself.
+    pub fn index(&self) -> &Arc<OfflineIndex> {
+        &self.index
+    }
+
+    /// The approximate heap memory of the posting lists and the items per session.
+    pub fn lists_size_in_bytes(&self) -> usize {
+        self.item_to_posting_list.capacity() * mem::size_of::<PostingList>()
+            + self.posting_list_bytes.capacity()
+            + self.session_offsets.capacity() * mem::size_of::<usize>()
+            + self.session_items_bytes.capacity()
+    }
+}
+
+impl OfflineIndex {
+    /// The approximate heap memory of the posting lists and the items per session.
+    pub fn lists_size_in_bytes(&self) -> usize {
+        self.item_to_top_sessions_ordered.capacity() * mem::size_of::<Vec<u32>>()
+            + self
+                .item_to_top_sessions_ordered
+                .iter()
+                .map(|session_ids| session_ids.capacity() * mem::size_of::<u32>())
+                .sum::<usize>()
+            + self.session_to_items_sorted.capacity() * mem::size_of::<Vec<InternalItemId>>()
+            + self
+                .session_to_items_sorted
+                .iter()
+                .map(|items| items.capacity() * mem::size_of::<InternalItemId>())
+                .sum::<usize>()
+    }
+}
+
+/// Decodes a posting list of a `CompressedOfflineIndex`.
+pub struct PostingListIterator<'a> {
+    bytes: &'a [u8],
+    position: usize,
+    qty_remaining: u32,
+    previous: i64,
+}
+
+impl<'a> Iterator for PostingListIterator<'a> {
+    type Item = u32;
+
+    fn next(&mut self) -> Option<u32> {
+        if self.qty_remaining == 0 {
+            return None;
+        }
+        self.qty_remaining -= 1;
+        self.previous += zigzag_decode(read_varint(self.bytes, &mut self.position));
+        Some(self.previous as u32)
+    }
+}
+
+/// Decodes the items of a session of a `CompressedOfflineIndex`.
+pub struct SessionItemsIterator<'a> {
+    bytes: &'a [u8],
+    position: usize,
+    end: usize,
+    previous: InternalItemId,
+}
+
+impl<'a> Iterator for SessionItemsIterator<'a> {
+    type Item = InternalItemId;
+
+    fn next(&mut self) -> Option<InternalItemId> {
+        if self.position == self.end {
+            return None;
+        }
+        self.previous += read_varint(self.bytes, &mut self.position) as InternalItemId;
+        Some(self.previous)
+    }
+}
+
+impl PostingLists for CompressedOfflineIndex {
+    type PostingList<'a> = PostingListIterator<'a>;
+
+    fn posting_list(&self, item: &InternalItemId) -> Self::PostingList<'_> {
+        let posting_list = &self.item_to_posting_list[*item as usize];
+        PostingListIterator {
+            bytes: &self.posting_list_bytes,
+            position: posting_list.offset,
+            qty_remaining: posting_list.qty_sessions,
+            previous: 0,
+        }
+    }
+
+    fn session_to_max_time_stamp(&self) -> &[u32] {
+        self.index.session_to_max_time_stamp()
+    }
+
+    fn max_time_stamp(&self) -> u32 {
+        self.index.max_time_stamp()
+    }
+}
+
+impl SimilarityComputationNew for CompressedOfflineIndex {
+    type SessionItems<'a> = SessionItemsIterator<'a>;
+
+    fn items_for_session(&self, session: &u32) -> Self::SessionItems<'_> {
+        SessionItemsIterator {
+            bytes: &self.session_items_bytes,
+            position: self.session_offsets[*session as usize],
+            end: self.session_offsets[*session as usize + 1],
+            previous: 0,
+        }
+    }
+
+    fn idf(&self, item: &InternalItemId) -> f64 {
+        self.index.idf(item)
+    }
+
+    fn find_weighted_neighbors(
+        &self,
+        evolving_session: &[u64],
+        item_weights: &[f64],
+        k: usize,
+        m: usize,
+    ) -> BinaryHeap<SessionScore> {
+        find_neighbors_in_posting_lists(
+            evolving_session,
+            item_weights,
+            k,
+            m,
+            self.session_to_max_time_stamp(),
+            self.max_time_stamp(),
+            &self.weighting(),
+            |item_id| self.internal_item_id(item_id).map(|item| self.posting_list(&item)),
+        )
+    }
+
+    fn session_item_weight(&self, session: &u32, position: usize) -> f64 {
+        self.index.session_item_weight(session, position)
+    }
+
+    fn find_attributes(&self, item: &InternalItemId) -> Option<&ProductAttributes> {
+        self.index.find_attributes(item)
+    }
+
+    fn qty_items(&self) -> usize {
+        self.index.qty_items()
+    }
+
+    fn internal_item_id(&self, item_id: &u64) -> Option<InternalItemId> {
+        self.index.internal_item_id(item_id)
+    }
+
+    fn external_item_id(&self, item: &InternalItemId) -> u64 {
+        self.index.external_item_id(item)
+    }
+
+    fn weighting(&self) -> Weighting {
+        self.index.weighting()
+    }
+
+    fn product_groups(&self) -> ProductGroups {
+        self.index.product_groups()
+    }
+}
+
+impl Recommender for CompressedOfflineIndex {
+    fn recommend_with_events(
+        &self,
+        evolving_session: &[u64],
+        session_events: &SessionEvents,
+        params: &RecommendParams,
+    ) -> Vec<ItemScore> {
+        vmisknn::predict_with_events(
+            self,
+            evolving_session,
+            session_events,
+            params.neighborhood_size_k,
+            params.m_most_recent_sessions,
+            params.how_many,
+            params.enable_business_logic,
+        )
+        .into_sorted_vec()
+    }
+
+    fn kind(&self) -> RecommenderKind {
+        RecommenderKind::VmisKnn
+    }
+}
+
+fn zigzag_encode(value: i64) -> u64 {
+    ((value << 1) ^ (value >> 63)) as u64
+}
+
+fn zigzag_decode(value: u64) -> i64 {
+    ((value >> 1) as i64) ^ -((value & 1) as i64)
+}
+
+/// LEB128: seven bits per byte, the high bit marks that more bytes follow.
+fn write_varint(bytes: &mut Vec<u8>, mut value: u64) {
+    while value >= 0x80 {
+        bytes.push((value as u8 & 0x7f) | 0x80);
+        value >>= 7;
+    }
+    bytes.push(value as u8);
+}
+
+fn read_varint(bytes: &[u8], position: &mut usize) -> u64 {
+    let mut value = 0_u64;
+    let mut shift = 0;
+    loop {
+        let byte = bytes[*position];
+        *position += 1;
+        value |= ((byte & 0x7f) as u64) << shift;
+        if byte & 0x80 == 0 {
+            return value;
+        }
+        shift += 7;
+    }
+}
+
+#[cfg(test)]
+mod compressed_index_test {
+    use itertools::Itertools;
+
+    use crate::vmisknn;
+
+    use super::*;
+
+    #[test]
+    fn should_roundtrip_varints_and_zigzag() {
+        let values = vec![0_i64, 1, -1, 127, -128, 300, i32::MAX as i64, -(u32::MAX as i64)];
+        let mut bytes = Vec::new();
+        for value in values.iter() {
+            write_varint(&mut bytes, zigzag_encode(*value));
+        }
+        let mut position = 0;
+        let decoded: Vec<i64> = values
+            .iter()
+            .map(|_| zigzag_decode(read_varint(&bytes, &mut position)))
+            .collect();
+        assert_eq!(values, decoded);
+        assert_eq!(bytes.len(), position);
+    }
+
+    fn create_index() -> OfflineIndex {
+        let historical_sessions = vec![
+            vec![1, 2, 300],
+            vec![2, 3],
+            vec![1, 3, 70_000],
+            vec![2, 300, 70_000],
+            vec![1, 2, 3, 300],
+        ];
+        OfflineIndex::new_for_test(historical_sessions, vec![10, 40, 20, 50, 30], 3)
+    }
+
+    #[test]
+    fn should_predict_the_same_as_the_uncompressed_index() {
+        let uncompressed = create_index();
+        let compressed = CompressedOfflineIndex::new(create_index());
+
+        for session_id in 0..5 {
+            assert_eq!(
+                uncompressed.items_for_session(&session_id).collect_vec(),
+                compressed.items_for_session(&session_id).collect_vec()
+            );
+        }
+        for item in 0..uncompressed.qty_items() as InternalItemId {
+            assert_eq!(
+                uncompressed.posting_list(&item).collect_vec(),
+                compressed.posting_list(&item).collect_vec()
+            );
+        }
+        for evolving_session in [vec![1], vec![2, 3], vec![300, 1, 70_000]].iter() {
+            let mut expected = uncompressed.find_neighbors(evolving_session, 5, 3).into_vec();
+            let mut actual = compressed.find_neighbors(evolving_session, 5, 3).into_vec();
+            expected.sort_unstable_by_key(|scored| scored.id);
+            actual.sort_unstable_by_key(|scored| scored.id);
+            assert_eq!(expected, actual);
+
+            let expected_items: Vec<u64> = vmisknn::predict(&uncompressed, evolving_session, 5, 3, 10, false)
+                .into_iter()
+                .map(|scored| scored.id)
+                .sorted()
+                .collect();
+            let actual_items: Vec<u64> = vmisknn::predict(&compressed, evolving_session, 5, 3, 10, false)
+                .into_iter()
+                .map(|scored| scored.id)
+                .sorted()
+                .collect();
+            assert_eq!(expected_items, actual_items);
+        }
+        assert!(compressed.lists_size_in_bytes() < uncompressed.lists_size_in_bytes());
+    }
+
+    #[test]
+    fn should_recommend_with_the_item_data_of_the_index() {
+        let group_attributes = "item_id,group_id\n300,7\n70000,7\n";
+        let product_groups = ProductGroups {
+            max_items_per_group: 0,
+            collapse: true,
+        };
+        let create_grouped_index = || {
+            let mut index = create_index();
+            index.read_item_attributes_for_test(group_attributes);
+            index.set_product_groups(product_groups);
+            index
+        };
+        let uncompressed = create_grouped_index();
+        let compressed = CompressedOfflineIndex::new(create_grouped_index());
+        assert_eq!(product_groups, compressed.product_groups());
+        assert_eq!(0, compressed.index().lists_size_in_bytes());
+
+        let params = RecommendParams {
+            neighborhood_size_k: 5,
+            m_most_recent_sessions: 3,
+            how_many: 10,
+            enable_business_logic: true,
+        };
+        for evolving_session in [vec![1], vec![2, 3], vec![300, 1, 70_000]].iter() {
+            let expected = uncompressed.recommend(evolving_session, &params);
+            let actual = compressed.recommend(evolving_session, &params);
+            assert_eq!(expected, actual);
+            let qty_grouped = actual
+                .iter()
+                .filter(|scored| scored.id == 300 || scored.id == 70_000)
+                .count();
+            assert!(qty_grouped <= 1);
+        }
+    }
+}
@@ -0,0 +1,317 @@
+use std::cmp;
+use std::fmt;
+
+use chrono::NaiveDateTime;
+use hashbrown::{HashMap, HashSet};
+use itertools::Itertools;
+
+use crate::io::{ItemId, TrainingSessionId};
+use crate::vmisknn;
+use crate::vmisknn::offline_index::OfflineIndex;
+use crate::vmisknn::similarity_indexed::SimilarityComputationNew;
+
+/// A session identified by its content instead of its session index, because the session indices and the
+/// internal item ids are assigned independently for every index build.
+type SessionFingerprint = (u32, Vec<ItemId>);
+
+pub struct IdfShift {
+    pub item_id: ItemId,
+    pub idf_before: f64,
+    pub idf_after: f64,
+}
+
+pub struct SessionListChurn {
+    pub item_id: ItemId,
+    pub qty_sessions_before: usize,
+    pub qty_sessions_after: usize,
+    pub churn: f64,
+}
+
+pub struct IndexDiff {
+    pub time_window_before: (NaiveDateTime, NaiveDateTime),
+    pub time_window_after: (NaiveDateTime, NaiveDateTime),
+    pub qty_items_before: usize,
+    pub qty_items_after: usize,
+    pub added_items: Vec<ItemId>,
+    pub removed_items: Vec<ItemId>,
+    pub largest_idf_shifts: Vec<IdfShift>,
+    pub mean_session_list_churn: f64,
+    pub largest_session_list_churn: Vec<SessionListChurn>,
+}
+
+/// Compares the structure of two index builds.
+/// The churn of an item's top-`m` session list is `1 - jaccard` of the sessions in both lists.
+/// `top_n` bounds the amount of items reported for the idf shifts and the session list churn.
+pub fn diff_indexes(before: &OfflineIndex, after: &OfflineIndex, top_n: usize) -> IndexDiff {
+    let items_before: HashSet<ItemId> = indexed_items(before).collect();
+    let items_after: HashSet<ItemId> = indexed_items(after).collect();
+
+    let added_items: Vec<ItemId> = items_after.difference(&items_before).cloned().sorted().collect();
+    let removed_items: Vec<ItemId> = items_before.difference(&items_after).cloned().sorted().collect();
+
+    let common_items: Vec<ItemId> = items_before.intersection(&items_after).cloned().sorted().collect();
+
+    let mut idf_shifts: Vec<IdfShift> = common_items
+        .iter()
+        .filter_map(|item_id| {
+            let idf_before = before.idf(&before.internal_item_id(item_id)?);
+            let idf_after = after.idf(&after.internal_item_id(item_id)?);
+            Some(IdfShift {
+                item_id: *item_id,
+                idf_before,
+                idf_after,
+            })
+        })
+        .collect();
+    idf_shifts.sort_by(|left, right| {
+        let left_shift = (left.idf_after - left.idf_before).abs();
+        let right_shift = (right.idf_after - right.idf_before).abs();
+        right_shift
+            .partial_cmp(&left_shift)
+            .unwrap_or(cmp::Ordering::Equal)
+            .then(left.item_id.cmp(&right.item_id))
+    });
+    idf_shifts.truncate(top_n);
+
+    let mut churn_per_item: Vec<SessionListChurn> = common_items
+        .iter()
+        .map(|item_id| {
+            let sessions_before = fingerprints(before, top_sessions(before, item_id));
+            let sessions_after = fingerprints(after, top_sessions(after, item_id));
+            let qty_union = sessions_before.union(&sessions_after).count();
+            let qty_intersection = sessions_before.intersection(&sessions_after).count();
+            let churn = if qty_union > 0 {
+                1.0 - qty_intersection as f64 / qty_union as f64
+            } else {
+                0.0
+            };
+            SessionListChurn {
+                item_id: *item_id,
+                qty_sessions_before: sessions_before.len(),
+                qty_sessions_after: sessions_after.len(),
+                churn,
+            }
+        })
+        .collect();
+    let mean_session_list_churn = if churn_per_item.is_empty() {
+        0.0
+    } else {
+        churn_per_item.iter().map(|item| item.churn).sum::<f64>() / churn_per_item.len() as f64
+    };
+    churn_per_item.sort_by(|left, right| {
+        right
+            .churn
+            .partial_cmp(&left.churn)
+            .unwrap_or(cmp::Ordering::Equal)
+            .then(left.item_id.cmp(&right.item_id))
+    });
+    churn_per_item.truncate(top_n);
+
+    IndexDiff {
+        time_window_before: time_window(before),
+        time_window_after: time_window(after),
+        qty_items_before: items_before.len(),
+        qty_items_after: items_after.len(),
+        added_items,
+        removed_items,
+        largest_idf_shifts: idf_shifts,
+        mean_session_list_churn,
+        largest_session_list_churn: churn_per_item,
+    }
+}
+
+/// The items with a posting list, by external item id.
+fn indexed_items(index: &OfflineIndex) -> impl Iterator<Item = ItemId> + '_ {
+    index
+        .item_ids
+        .iter()
+        .zip(index.item_to_top_sessions_ordered.iter())
+        .filter(|(_, session_ids)| !session_ids.is_empty())
+        .map(|(item_id, _)| *item_id)
+}
+
+fn top_sessions<'a>(index: &'a OfflineIndex, item_id: &ItemId) -> &'a [u32] {
+    let item = index.internal_item_id(item_id).unwrap();
+    &index.item_to_top_sessions_ordered[item as usize]
+}
+
+fn fingerprints(index: &OfflineIndex, session_ids: &[u32]) -> HashSet<SessionFingerprint> {
+    session_ids
+        .iter()
+        .filter_map(|session_id| {
+            let items = index.session_to_items_sorted.get(*session_id as usize)?;
+            let time = index.session_to_max_time_stamp[*session_id as usize];
+            let item_ids = items.iter().map(|item| index.external_item_id(item)).collect();
+            Some((time, item_ids))
+        })
+        .collect()
+}
+
+fn time_window(index: &OfflineIndex) -> (NaiveDateTime, NaiveDateTime) {
+    let stats = &index.training_data_stats;
+    (stats.min_time_date_time, stats.max_time_date_time)
+}
+
+/// The average fraction of recommended items that both indexes have in common, relative to the index that recommends
+/// more items, for every state of every evolving session with recommendations. Each session state uses the last `max_items_in_session` items, like the serving endpoint.
+#[allow(clippy::too_many_arguments)]
+pub fn recommendation_overlap(
+    before: &OfflineIndex,
+    after: &OfflineIndex,
+    evolving_sessions: &HashMap<TrainingSessionId, Vec<ItemId>>,
+    neighborhood_size_k: usize,
+    m_most_recent_sessions: usize,
+    max_items_in_session: usize,
+    how_many: usize,
+    enable_business_logic: bool,
+) -> f64 {
+    let mut sum_of_overlaps = 0_f64;
+    let mut qty = 0_usize;
+    for evolving_session_items in evolving_sessions.values() {
+        for session_state in 1..=evolving_session_items.len() {
+            let start_index = session_state.saturating_sub(max_items_in_session);
+            let session = &evolving_session_items[start_index..session_state];
+            let recommended_before: HashSet<ItemId> = vmisknn::predict(
+                before,
+                session,
+                neighborhood_size_k,
+                m_most_recent_sessions,
+                how_many,
+                enable_business_logic,
+            )
+            .into_iter()
+            .map(|scored| scored.id)
+            .collect();
+            let recommended_after: HashSet<ItemId> = vmisknn::predict(
+                after,
+                session,
+                neighborhood_size_k,
+                m_most_recent_sessions,
+                how_many,
+                enable_business_logic,
+            )
+            .into_iter()
+            .map(|scored| scored.id)
+            .collect();
+            // States without recommendations of either index don't tell how similar the indexes are.
+            let qty_recommended = recommended_before.len().max(recommended_after.len());
+            if qty_recommended == 0 {
+                continue;
+            }
+            qty += 1;
+            sum_of_overlaps +=
+                recommended_before.intersection(&recommended_after).count() as f64 / qty_recommended as f64;
+        }
+    }
+    if qty > 0 {
+        sum_of_overlaps / qty as f64
+    } else {
+        0.0
+    }
+}
+
+impl fmt::Display for IndexDiff {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        writeln!(
+            f,
+            "Time window before: {} / {}",
+            self.time_window_before.0, self.time_window_before.1
+        )?;
+        writeln!(
+            f,
+            "Time window after: {} / {}",
+            self.time_window_after.0, self.time_window_after.1
+        )?;
+        writeln!(f, "Items before: {}", self.qty_items_before)?;
+        writeln!(f, "Items after: {}", self.qty_items_after)?;
+        writeln!(
+            f,
+            "Added items: {} {:?}",
+            self.added_items.len(),
+            self.added_items.iter().take(20).collect_vec()
+        )?;
+        writeln!(
+            f,
+            "Removed items: {} {:?}",
+            self.removed_items.len(),
+            self.removed_items.iter().take(20).collect_vec()
+        )?;
+        writeln!(f, "Largest idf shifts (item_id: before -> after):")?;
+        for shift in self.largest_idf_shifts.iter() {
+            writeln!(
+                f,
+                "\t{}: {:.4} -> {:.4}",
+                shift.item_id, shift.idf_before, shift.idf_after
+            )?;
+        }
+        writeln!(
+            f,
+            "Mean churn in top-m session lists: {:.4}",
+            self.mean_session_list_churn
+        )?;
+        write!(f, "Largest churn in top-m session lists (item_id: churn, sessions before -> after):")?;
+        for item in self.largest_session_list_churn.iter() {
+            write!(
+                f,
+                "\n\t{}: {:.4}, {} -> {}",
+                item.item_id, item.churn, item.qty_sessions_before, item.qty_sessions_after
+            )?;
+        }
+        Ok(())
+    }
+}
+
+#[cfg(test)]
+mod index_diff_test {
+    use super::*;
+
+    #[test]
+    fn should_diff_items_idf_and_churn() {
+        let before = OfflineIndex::new_for_test_from_item_maps(
+            vec![(1, vec![1, 0]), (2, vec![1]), (3, vec![0])].into_iter().collect(),
+            vec![vec![1, 3], vec![1, 2]],
+            vec![10, 20],
+            vec![(1, 0.1), (2, 0.7), (3, 0.7)].into_iter().collect(),
+        );
+        // the same sessions as before, but with swapped session indices and one new session for item 1
+        let after = OfflineIndex::new_for_test_from_item_maps(
+            vec![(1, vec![2, 0, 1]), (2, vec![0]), (4, vec![2])].into_iter().collect(),
+            vec![vec![1, 2], vec![1, 3], vec![1, 4]],
+            vec![20, 10, 30],
+            vec![(1, 0.2), (2, 0.3), (4, 1.1)].into_iter().collect(),
+        );
+
+        let diff = diff_indexes(&before, &after, 10);
+
+        assert_eq!(vec![4], diff.added_items);
+        assert_eq!(vec![3], diff.removed_items);
+        assert_eq!(2, diff.largest_idf_shifts[0].item_id);
+        assert_eq!(1, diff.largest_idf_shifts[1].item_id);
+        let churn: HashMap<u64, f64> = diff
+            .largest_session_list_churn
+            .iter()
+            .map(|item| (item.item_id, item.churn))
+            .collect();
+        assert_eq!(0.0, churn[&2]);
+        assert!((churn[&1] - 1.0 / 3.0).abs() < 1e-9);
+    }
+
+    #[test]
+    fn should_compare_the_recommended_items_only() {
+        let index = || {
+            OfflineIndex::new_for_test_from_item_maps(
+                vec![(1, vec![1, 0]), (2, vec![1]), (3, vec![0])].into_iter().collect(),
+                vec![vec![1, 3], vec![1, 2]],
+                vec![10, 20],
+                vec![(1, 0.1), (2, 0.7), (3, 0.7)].into_iter().collect(),
+            )
+        };
+        let (before, after) = (index(), index());
+        // Item 4 is unknown, so its states have no recommendations and are skipped.
+        let evolving_sessions: HashMap<TrainingSessionId, Vec<ItemId>> =
+            vec![(1, vec![1, 2]), (2, vec![4])].into_iter().collect();
+
+        // Identical indexes overlap completely, although they recommend fewer than 21 items.
+        assert_eq!(1.0, recommendation_overlap(&before, &after, &evolving_sessions, 10, 10, 2, 21, false));
+    }
+}
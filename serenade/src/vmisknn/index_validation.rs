@@ -0,0 +1,228 @@
+use std::fmt;
+use std::time::SystemTime;
+
+use crate::vmisknn::offline_index::OfflineIndex;
+
+// 2000-01-01T00:00:00Z, anything older than this is considered a broken timestamp.
+const MIN_PLAUSIBLE_TIMESTAMP: u32 = 946_684_800;
+// Allow for some clock skew between the machine that built the index and this one.
+const MAX_CLOCK_SKEW_SECS: u64 = 24 * 60 * 60;
+// Amount of example violations kept per check for the report.
+const MAX_EXAMPLES_PER_CHECK: usize = 5;
+
+/// The outcome of a single integrity check on an `OfflineIndex`.
+pub struct CheckResult {
+    pub name: &'static str,
+    pub qty_checked: usize,
+    pub qty_violations: usize,
+    pub examples: Vec<String>,
+}
+
+impl CheckResult {
+    fn new(name: &'static str) -> Self {
+        CheckResult {
+            name,
+            qty_checked: 0,
+            qty_violations: 0,
+            examples: Vec::with_capacity(MAX_EXAMPLES_PER_CHECK),
+        }
+    }
+
+    fn check(&mut self, passed: bool, describe_violation: impl FnOnce() -> String) {
+        self.qty_checked += 1;
+        if !passed {
+            self.qty_violations += 1;
+            if self.examples.len() < MAX_EXAMPLES_PER_CHECK {
+                self.examples.push(describe_violation());
+            }
+        }
+    }
+
+    pub fn passed(&self) -> bool {
+        self.qty_violations == 0
+    }
+}
+
+pub struct IndexValidationReport {
+    pub qty_items: usize,
+    pub qty_session_slots: usize,
+    pub checks: Vec<CheckResult>,
+}
+
+impl IndexValidationReport {
+    pub fn is_valid(&self) -> bool {
+        self.checks.iter().all(|check| check.passed())
+    }
+}
+
+impl fmt::Display for IndexValidationReport {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        writeln!(f, "Items in item index: {}", self.qty_items)?;
+        writeln!(f, "Session slots in session index: {}", self.qty_session_slots)?;
+        for check in self.checks.iter() {
+            let status = if check.passed() { "OK" } else { "FAILED" };
+            writeln!(
+                f,
+                "[{}] {}: {} violations in {} checks",
+                status, check.name, check.qty_violations, check.qty_checked
+            )?;
+            for example in check.examples.iter() {
+                writeln!(f, "\t{}", example)?;
+            }
+        }
+        let verdict = if self.is_valid() { "VALID" } else { "INVALID" };
+        write!(f, "Index is {}", verdict)
+    }
+}
+
+/// Checks the integrity of an index computed offline, without assuming anything about its validity.
+/// The serving code indexes directly into the session vectors, so a violation here would otherwise show up
+/// as a panic at request time.
+pub fn validate_index(index: &OfflineIndex) -> IndexValidationReport {
+    let session_exists = |session_id: u32| {
+        index
+            .session_to_items_sorted
+            .get(session_id as usize)
+            .is_some_and(|items| !items.is_empty())
+    };
+
+    let mut referenced_sessions = CheckResult::new("referenced sessions exist in sessionindex");
+    let mut posting_lists_ordered = CheckResult::new("posting lists are in descending time order");
+    for (item, session_ids) in index.item_to_top_sessions_ordered.iter().enumerate() {
+        let item_id = index.item_ids[item];
+        for session_id in session_ids.iter() {
+            referenced_sessions.check(session_exists(*session_id), || {
+                format!("item {} references missing session {}", item_id, session_id)
+            });
+        }
+        let mut previous: Option<(u32, u32)> = None;
+        for session_id in session_ids.iter().filter(|session_id| session_exists(**session_id)) {
+            let time = index.session_to_max_time_stamp[*session_id as usize];
+            if let Some((previous_session_id, previous_time)) = previous {
+                posting_lists_ordered.check(previous_time >= time, || {
+                    format!(
+                        "item {}: session {} (time {}) precedes session {} (time {})",
+                        item_id, previous_session_id, previous_time, session_id, time
+                    )
+                });
+            }
+            previous = Some((*session_id, time));
+        }
+    }
+
+    let mut items_sorted = CheckResult::new("session items are sorted ascending and deduplicated");
+    let mut referenced_items = CheckResult::new("session items exist in itemindex");
+    for (session_id, items) in index.session_to_items_sorted.iter().enumerate() {
+        if items.is_empty() {
+            continue;
+        }
+        let strictly_ascending = items.windows(2).all(|pair| pair[0] < pair[1]);
+        items_sorted.check(strictly_ascending, || {
+            let item_ids: Vec<u64> = items.iter().map(|item| index.item_ids[*item as usize]).collect();
+            format!("session {} has item_ids_asc {:?}", session_id, item_ids)
+        });
+        for item in items.iter() {
+            // The item index has the posting list and the idf of every item.
+            let in_item_index = !index.item_to_top_sessions_ordered[*item as usize].is_empty();
+            referenced_items.check(in_item_index, || {
+                format!(
+                    "session {} references item {} missing in itemindex",
+                    session_id, index.item_ids[*item as usize]
+                )
+            });
+        }
+    }
+
+    let mut idf_values = CheckResult::new("idf values are finite and positive");
+    // Items without an idf are reported as unreferenced above.
+    let mut qty_items = 0;
+    for (item, idf) in index.item_to_idf_score.iter().enumerate() {
+        if index.item_to_top_sessions_ordered[item].is_empty() {
+            continue;
+        }
+        qty_items += 1;
+        idf_values.check(idf.is_finite() && *idf > 0.0, || {
+            format!("item {} has idf {}", index.item_ids[item], idf)
+        });
+    }
+
+    let max_plausible_timestamp = SystemTime::now()
+        .duration_since(SystemTime::UNIX_EPOCH)
+        .unwrap()
+        .as_secs()
+        + MAX_CLOCK_SKEW_SECS;
+    let mut timestamps = CheckResult::new("session timestamps are plausible");
+    for (session_id, items) in index.session_to_items_sorted.iter().enumerate() {
+        if items.is_empty() {
+            continue;
+        }
+        let time = index.session_to_max_time_stamp[session_id];
+        let plausible =
+            time >= MIN_PLAUSIBLE_TIMESTAMP && (time as u64) <= max_plausible_timestamp;
+        timestamps.check(plausible, || {
+            format!("session {} has timestamp {}", session_id, time)
+        });
+    }
+
+    IndexValidationReport {
+        qty_items,
+        qty_session_slots: index.session_to_items_sorted.len(),
+        checks: vec![
+            referenced_sessions,
+            posting_lists_ordered,
+            items_sorted,
+            referenced_items,
+            idf_values,
+            timestamps,
+        ],
+    }
+}
+
+#[cfg(test)]
+mod index_validation_test {
+    use super::*;
+
+    #[test]
+    fn should_accept_consistent_index() {
+        let index = OfflineIndex::new_for_test_from_item_maps(
+            vec![(1, vec![1, 0]), (2, vec![1])].into_iter().collect(),
+            vec![vec![1], vec![1, 2]],
+            vec![1_600_000_000, 1_600_000_100],
+            vec![(1, 0.5), (2, 1.2)].into_iter().collect(),
+        );
+        let report = validate_index(&index);
+        assert!(report.is_valid(), "{}", report);
+    }
+
+    #[test]
+    fn should_report_each_violation() {
+        let index = OfflineIndex::new_for_test_from_item_maps(
+            // session 7 does not exist and the posting list of item 1 is in ascending time order
+            vec![(1, vec![0, 1, 7]), (2, vec![1])].into_iter().collect(),
+            // session 1 is not deduplicated and references item 3 which is not in the item index
+            vec![vec![1], vec![2, 1, 1, 3]],
+            vec![1_600_000_000, 1_600_000_100],
+            vec![(1, -1.0), (2, 1.2)].into_iter().collect(),
+        );
+        let report = validate_index(&index);
+        assert!(!report.is_valid());
+        let violations: Vec<usize> = report
+            .checks
+            .iter()
+            .map(|check| check.qty_violations)
+            .collect();
+        assert_eq!(vec![1, 1, 1, 1, 1, 0], violations);
+    }
+
+    #[test]
+    fn should_reject_implausible_timestamps() {
+        let index = OfflineIndex::new_for_test_from_item_maps(
+            vec![(1, vec![0])].into_iter().collect(),
+            vec![vec![1]],
+            vec![42],
+            vec![(1, 0.5)].into_iter().collect(),
+        );
+        let report = validate_index(&index);
+        assert_eq!(1, report.checks.last().unwrap().qty_violations);
+    }
+}
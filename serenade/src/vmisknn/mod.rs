@@ -1,20 +1,35 @@
+use std::cell::RefCell;
 use std::cmp::Ordering;
 use std::collections::BinaryHeap;
 
-use hashbrown::hash_map::Entry;
-use hashbrown::HashMap;
-
 use crate::vmisknn::similarity_indexed::SimilarityComputationNew;
 use crate::vmisknn::offline_index::ProductAttributes;
+use crate::vmisknn::weighting::SessionEvents;
 
 pub mod vsknn_index;
 pub mod vmisknn_index_noopt;
 pub mod vmisknn_index;
+pub mod vmisknn_index_smallopt;
 pub mod similarity_hashed;
 pub mod similarity_indexed;
 pub mod offline_index;
 pub mod tree_index;
-
+pub mod index_validation;
+pub mod index_diff;
+pub mod compressed_index;
+pub mod sharded_index;
+pub mod weighting;
+pub mod stan_index;
+pub mod single_item_table;
+pub mod similar_items;
+pub mod basket;
+pub mod product_groups;
+
+/// The dense id of an item inside an index, see `SimilarityComputationNew::internal_item_id`.
+pub type InternalItemId = u32;
+
+/// A neighbor session with its similarity. Ordered from the most to the least similar session and then by ascending
+/// session id, so that a heap of the best sessions doesn't depend on the order in which they were pushed.
 #[derive(PartialEq, Debug)]
 pub struct SessionScore {
     pub id: u32,
@@ -31,11 +46,8 @@ impl Eq for SessionScore {}
 
 impl Ord for SessionScore {
     fn cmp(&self, other: &Self) -> Ordering {
-        match self.score.partial_cmp(&other.score) {
-            Some(Ordering::Less) => Ordering::Greater,
-            Some(Ordering::Greater) => Ordering::Less,
-            _ => Ordering::Equal,
-        }
+        // reverse order by score, ties by session id
+        other.score.total_cmp(&self.score).then(self.id.cmp(&other.id))
     }
 }
 
@@ -45,14 +57,16 @@ impl PartialOrd for SessionScore {
     }
 }
 
-#[derive(PartialEq, Debug)]
+/// A recommended item with its score. Ordered from the best to the worst item: by descending score and then by
+/// ascending item id, so that equally scored items are always recommended in the same order.
+#[derive(Clone, PartialEq, Debug)]
 pub struct ItemScore {
     pub id: u64,
     pub score: f64,
 }
 
 impl ItemScore {
-    fn new(id: u64, score: f64) -> Self {
+    pub(crate) fn new(id: u64, score: f64) -> Self {
         ItemScore { id, score }
     }
 }
@@ -61,12 +75,8 @@ impl Eq for ItemScore {}
 
 impl Ord for ItemScore {
     fn cmp(&self, other: &Self) -> Ordering {
-        // reverse order by score
-        match self.score.partial_cmp(&other.score) {
-            Some(Ordering::Less) => Ordering::Greater,
-            Some(Ordering::Greater) => Ordering::Less,
-            _ => Ordering::Equal,
-        }
+        // reverse order by score, ties by item id
+        other.score.total_cmp(&self.score).then(self.id.cmp(&other.id))
     }
 }
 
@@ -110,14 +120,56 @@ impl PartialEq for SessionTime {
 }
 
 
-fn linear_score(pos: usize) -> f64 {
-    if pos < 100 {
-        1.0 - (0.1 * pos as f64)
-    } else {
-        0.0
+/// Accumulates the scores of the recommendation candidates in a vector indexed by internal item id.
+/// Only the touched items are visited and reset afterwards, so the vector can be reused for every prediction.
+#[derive(Default)]
+struct DenseScoreAccumulator {
+    scores: Vec<f64>,
+    is_touched: Vec<bool>,
+    touched: Vec<InternalItemId>,
+}
+
+impl DenseScoreAccumulator {
+    fn prepare(&mut self, qty_items: usize) {
+        if self.scores.len() < qty_items {
+            self.scores.resize(qty_items, 0.0);
+            self.is_touched.resize(qty_items, false);
+        }
+    }
+
+    fn add(&mut self, item: InternalItemId, score: f64) {
+        let position = item as usize;
+        if !self.is_touched[position] {
+            self.is_touched[position] = true;
+            self.touched.push(item);
+        }
+        self.scores[position] += score;
+    }
+
+    fn remove(&mut self, item: InternalItemId) {
+        let position = item as usize;
+        if self.is_touched[position] {
+            self.is_touched[position] = false;
+            self.scores[position] = 0.0;
+            self.touched.retain(|touched_item| *touched_item != item);
+        }
+    }
+
+    /// Visits the scored items and resets the accumulator.
+    fn drain(&mut self, mut visit: impl FnMut(InternalItemId, f64)) {
+        for item in self.touched.drain(..) {
+            let position = item as usize;
+            visit(item, self.scores[position]);
+            self.scores[position] = 0.0;
+            self.is_touched[position] = false;
+        }
     }
 }
 
+thread_local! {
+    static ITEM_SCORES: RefCell<DenseScoreAccumulator> = RefCell::new(DenseScoreAccumulator::default());
+}
+
 pub fn predict<I: SimilarityComputationNew + Send + Sync>(
     index: &I,
     evolving_session: &[u64],
@@ -126,79 +178,126 @@ pub fn predict<I: SimilarityComputationNew + Send + Sync>(
     how_many: usize,
     enable_business_logic: bool,
 ) -> BinaryHeap<ItemScore> {
-    let neighbors = index.find_neighbors(evolving_session, k, m);
-
-    let mut item_scores: HashMap<u64, f64> = HashMap::with_capacity(1000);
-
-    for scored_session in neighbors.into_iter() {
-        let training_item_ids: &[u64] = index.items_for_session(&scored_session.id);
-
-        let (first_match_index, _) = evolving_session
-            .iter()
-            .rev()
-            .enumerate()
-            .find(|(_, item_id)| training_item_ids.contains(*item_id))
-            .unwrap();
-
-        let first_match_pos = first_match_index + 1;
-
-        let session_weight = linear_score(first_match_pos);
+    let session_events = SessionEvents::unweighted(evolving_session);
+    predict_with_events(index, evolving_session, &session_events, k, m, how_many, enable_business_logic)
+}
 
-        for item_id in training_item_ids.iter() {
-            let item_idf = index.idf(item_id);
-            *item_scores.entry(*item_id).or_insert(0.0) +=
-                session_weight * item_idf * scored_session.score;
+/// Like `predict`, with the evolving session items weighted by their event types in the neighbor similarity and
+/// without recommending the `excluded_items` of the `session_events`.
+pub fn predict_with_events<I: SimilarityComputationNew + Send + Sync>(
+    index: &I,
+    evolving_session: &[u64],
+    session_events: &SessionEvents,
+    k: usize,
+    m: usize,
+    how_many: usize,
+    enable_business_logic: bool,
+) -> BinaryHeap<ItemScore> {
+    let neighbors = index.find_weighted_neighbors(evolving_session, &session_events.item_weights, k, m);
+
+    // External item ids are only translated when they enter and leave the index.
+    let evolving_session_internal: Vec<Option<InternalItemId>> = evolving_session
+        .iter()
+        .map(|item_id| index.internal_item_id(item_id))
+        .collect();
+
+    let weighting = index.weighting();
+
+    ITEM_SCORES.with(|item_scores| {
+        let mut item_scores = item_scores.borrow_mut();
+        item_scores.prepare(index.qty_items());
+
+        // In the order of the neighbors, so that the item scores are summed up in the same order every time.
+        for scored_session in neighbors.into_sorted_vec() {
+            let (first_match_index, _) = evolving_session_internal
+                .iter()
+                .rev()
+                .enumerate()
+                .find(|(_, item)| {
+                    item.is_some_and(|item| {
+                        index
+                            .items_for_session(&scored_session.id)
+                            .any(|training_item| training_item == item)
+                    })
+                })
+                .unwrap();
+
+            let first_match_pos = first_match_index + 1;
+
+            let session_weight = weighting.match_weight(first_match_pos);
+
+            for (position, item) in index.items_for_session(&scored_session.id).enumerate() {
+                let item_idf = index.idf(&item);
+                let event_type_weight = index.session_item_weight(&scored_session.id, position);
+                item_scores.add(item, session_weight * event_type_weight * item_idf * scored_session.score);
+            }
         }
-    }
 
+        let most_recent_item = *evolving_session_internal.last().unwrap();
+        select_top_items(
+            index,
+            &mut item_scores,
+            most_recent_item,
+            session_events,
+            how_many,
+            enable_business_logic,
+        )
+    })
+}
+
+/// Drains the scored items into the best `how_many` recommendations, without the most recent item of the
+/// evolving session and the excluded items, and with the `ProductGroups` of the index. Equally scored items are ranked by their external item id, see `ItemScore`.
+fn select_top_items<I: SimilarityComputationNew>(
+    index: &I,
+    item_scores: &mut DenseScoreAccumulator,
+    most_recent_item: Option<InternalItemId>,
+    session_events: &SessionEvents,
+    how_many: usize,
+    enable_business_logic: bool,
+) -> BinaryHeap<ItemScore> {
     // Remove most recent item if it has been scored as well
-    let most_recent_item = *evolving_session.last().unwrap();
-    if let Entry::Occupied(entry) = item_scores.entry(most_recent_item) {
-        entry.remove_entry();
+    if let Some(most_recent_item) = most_recent_item {
+        item_scores.remove(most_recent_item);
     }
-
-    fn passes_business_rules(
-        current_item_attribs: Option<&ProductAttributes>,
-        reco_item_attribs: Option<&ProductAttributes>,
-    ) -> bool {
-        if reco_item_attribs.is_none() {
-            return false;
-        }
-        let reco_attribs = reco_item_attribs.unwrap();
-        if reco_attribs.is_for_sale {
-            if reco_attribs.is_adult {
-                if let Some(current_attribs) = current_item_attribs {
-                    return current_attribs.is_adult;
-                } else {
-                    return false;
-                }
-            } else {
-                return true;
-            }
+    for excluded_item in session_events.excluded_items.iter() {
+        if let Some(excluded_item) = index.internal_item_id(excluded_item) {
+            item_scores.remove(excluded_item);
         }
-        false
+    }
+
+    let current_item_attribs: Option<&ProductAttributes> =
+        most_recent_item.and_then(|item| index.find_attributes(&item));
+    if index.product_groups().is_enabled() {
+        return product_groups::select_top_items_per_group(
+            index,
+            item_scores,
+            current_item_attribs,
+            most_recent_item,
+            session_events,
+            how_many,
+            enable_business_logic,
+        );
     }
 
     // Return the proper amount of recommendations and filter them using business rules.
     let mut top_items: BinaryHeap<ItemScore> = BinaryHeap::with_capacity(how_many);
-    let current_item_attribs: Option<&ProductAttributes> = index.find_attributes(&most_recent_item);
-    for (reco_item_id, reco_item_score) in item_scores.into_iter() {
-        let scored_item = ItemScore::new(reco_item_id, reco_item_score);
-
+    item_scores.drain(|reco_item, reco_item_score| {
         if top_items.len() < how_many {
             if enable_business_logic {
-                let reco_item_attribs:Option<&ProductAttributes> = index.find_attributes(&reco_item_id);
+                let reco_item_attribs:Option<&ProductAttributes> = index.find_attributes(&reco_item);
                 if passes_business_rules(current_item_attribs, reco_item_attribs) {
-                    top_items.push(scored_item);
+                    top_items.push(ItemScore::new(index.external_item_id(&reco_item), reco_item_score));
                 }
             } else {
-                top_items.push(scored_item);
+                top_items.push(ItemScore::new(index.external_item_id(&reco_item), reco_item_score));
             }
         } else {
             let mut bottom = top_items.peek_mut().unwrap();
-            if scored_item.score > bottom.score {
+            let scored_item = ItemScore::new(index.external_item_id(&reco_item), reco_item_score);
+            // `bottom` is the worst of the top items, see the `Ord` of `ItemScore`
+            if scored_item < *bottom {
                 if enable_business_logic {
-                    let reco_item_attribs = index.find_attributes(&reco_item_id);
+                    let reco_item_attribs = index.find_attributes(&reco_item);
                     if passes_business_rules(current_item_attribs, reco_item_attribs) {
                         *bottom = scored_item;
                     }
@@ -207,18 +306,73 @@ pub fn predict<I: SimilarityComputationNew + Send + Sync>(
                 }
             }
         }
-    }
+    });
 
     top_items
 }
 
+/// The business rules for external item ids, for items that are recommended without `predict`.
+pub(crate) fn passes_business_rules_of_items<I: SimilarityComputationNew>(
+    index: &I,
+    current_item_id: &u64,
+    reco_item_id: &u64,
+) -> bool {
+    let current_item_attribs = index
+        .internal_item_id(current_item_id)
+        .and_then(|item| index.find_attributes(&item));
+    let reco_item_attribs = index
+        .internal_item_id(reco_item_id)
+        .and_then(|item| index.find_attributes(&item));
+    passes_business_rules(current_item_attribs, reco_item_attribs)
+}
+
+/// The business rules for the trending items that fill up the recommendations. Items of the index follow
+/// `passes_business_rules_of_items`, so items of an index without attributes are still rejected. An item that is not
+/// part of the index has no attributes yet and passes like an item that is for sale and not adult, otherwise a
+/// trending item could never be recommended before it is part of the index.
+pub(crate) fn passes_business_rules_of_trending_items<I: SimilarityComputationNew>(
+    index: &I,
+    current_item_id: &u64,
+    reco_item_id: &u64,
+) -> bool {
+    match index.internal_item_id(reco_item_id) {
+        Some(_) => passes_business_rules_of_items(index, current_item_id, reco_item_id),
+        None => true,
+    }
+}
+
+fn passes_business_rules(
+    current_item_attribs: Option<&ProductAttributes>,
+    reco_item_attribs: Option<&ProductAttributes>,
+) -> bool {
+    if reco_item_attribs.is_none() {
+        return false;
+    }
+    let reco_attribs = reco_item_attribs.unwrap();
+    if reco_attribs.is_for_sale {
+        if reco_attribs.is_adult {
+            if let Some(current_attribs) = current_item_attribs {
+                return current_attribs.is_adult;
+            } else {
+                return false;
+            }
+        } else {
+            return true;
+        }
+    }
+    false
+}
+
 #[cfg(test)]
 mod offline_index_test {
     use chrono::NaiveDateTime;
 
     use crate::dataframeutils::TrainingDataStats;
     use crate::vmisknn::offline_index::prepare_hashmap;
-    use crate::vmisknn::offline_index::OfflineIndex;
+    use crate::vmisknn::offline_index::{read_item_dictionary, OfflineIndex};
+    use crate::io::{Column, InputSchema};
+    use crate::vmisknn::weighting::{EventTypeWeights, ItemWeighting, Weighting};
+    use hashbrown::HashMap;
 
     use super::*;
     use dary_heap::OctonaryHeap;
@@ -279,14 +433,14 @@ mod offline_index_test {
             training_data_stats.qty_events_p99_5 as usize,
         );
 
-        let vsknn_index = OfflineIndex {
-            item_to_top_sessions_ordered: item_to_top_sessions_ordered,
-            session_to_max_time_stamp: historical_sessions_max_time_stamp,
-            item_to_idf_score: item_to_idf_score,
-            session_to_items_sorted: historical_sessions_train,
-            training_data_stats: training_data_stats,
-            item_to_product_attributes: item_to_product_attributes,
-        };
+        let vsknn_index = OfflineIndex::new_from_item_maps(
+            item_to_top_sessions_ordered,
+            historical_sessions_max_time_stamp,
+            item_to_idf_score,
+            historical_sessions_train,
+            training_data_stats,
+            item_to_product_attributes,
+        );
 
         let session_items = vec![920005];
 
@@ -305,6 +459,141 @@ mod offline_index_test {
         assert_eq!(920004, recommended_items[0]);
     }
 
+    #[test]
+    fn should_prefer_recent_neighbors_with_a_recency_half_life() {
+        // The old session shares both items with the evolving session, the recent one only a single item.
+        let historical_sessions = vec![vec![1, 2, 3], vec![1, 4]];
+        let mut index = OfflineIndex::new_for_test(historical_sessions, vec![0, 100_000], 10);
+        let evolving_session = vec![1, 2];
+
+        let closest = index.find_neighbors(&evolving_session, 1, 10).pop().unwrap();
+        assert_eq!(0, closest.id);
+
+        index.set_weighting(Weighting {
+            recency_half_life_secs: Some(3600),
+            ..Weighting::default()
+        });
+        let closest = index.find_neighbors(&evolving_session, 1, 10).pop().unwrap();
+        assert_eq!(1, closest.id);
+    }
+
+    #[test]
+    fn should_pass_the_business_rules_for_trending_items_that_are_not_part_of_the_index() {
+        let item_to_top_sessions_ordered = vec![(1, vec![0]), (2, vec![0])].into_iter().collect();
+        let item_to_idf_score = vec![(1, 0.0), (2, 0.0)].into_iter().collect();
+        // The items of the index have no attributes.
+        let index = OfflineIndex::new_for_test_from_item_maps(
+            item_to_top_sessions_ordered,
+            vec![vec![1, 2]],
+            vec![1],
+            item_to_idf_score,
+        );
+
+        assert!(!passes_business_rules_of_items(&index, &1, &2));
+        assert!(!passes_business_rules_of_trending_items(&index, &1, &2));
+        assert!(!passes_business_rules_of_items(&index, &1, &3));
+        assert!(passes_business_rules_of_trending_items(&index, &1, &3));
+    }
+
+    #[test]
+    fn should_switch_the_item_weighting() {
+        let historical_sessions = vec![vec![1, 2, 3], vec![1, 4], vec![1, 2]];
+        let (_, item_to_idf_score, _, _) =
+            prepare_hashmap(&historical_sessions, &[1, 2, 3], 10, 10);
+        let mut index = OfflineIndex::new_for_test(historical_sessions, vec![1, 2, 3], 10);
+
+        // the default matches the idf of the index build
+        assert_eq!(Some(ItemWeighting::IdfInteractions), index.item_weighting());
+        for (item_id, idf) in item_to_idf_score.iter() {
+            let item = index.internal_item_id(item_id).unwrap();
+            assert!((index.idf(&item) - idf).abs() < 1e-9);
+        }
+
+        index.set_item_weighting(ItemWeighting::IdfSessions);
+        let item = index.internal_item_id(&1).unwrap();
+        assert!(index.idf(&item).abs() < 1e-9);
+        let item = index.internal_item_id(&4).unwrap();
+        assert!((index.idf(&item) - 3.0_f64.ln()).abs() < 1e-9);
+
+        index.set_item_weighting(ItemWeighting::None);
+        assert_eq!(1.0, index.idf(&item));
+    }
+
+    #[test]
+    fn should_keep_the_idf_over_the_deduplicated_sessions_of_csv_files() {
+        let dir = tempfile::tempdir().unwrap();
+        let path = dir.path().join("training.tsv");
+        // Item 1 is repeated in the first session.
+        let training_data = "SessionId\tItemId\tTime\n\
+            1\t1\t10\n1\t2\t11\n1\t1\t12\n1\t1\t13\n\
+            2\t1\t20\n2\t3\t21\n\
+            3\t2\t30\n3\t3\t31\n";
+        std::fs::write(&path, training_data).unwrap();
+        let mut index = OfflineIndex::new_from_csv(&path.display().to_string(), &InputSchema::default(), 10);
+
+        let deduplicated_sessions = [vec![1, 2], vec![1, 3], vec![2, 3]];
+        let (_, item_to_idf_score, _, _) = prepare_hashmap(&deduplicated_sessions, &[13, 21, 31], 10, 10);
+        assert_eq!(None, index.item_weighting());
+        for (item_id, idf) in item_to_idf_score.iter() {
+            let item = index.internal_item_id(item_id).unwrap();
+            assert!((index.idf(&item) - idf).abs() < 1e-9);
+        }
+
+        // The configured weightings count the repeated interactions.
+        index.set_item_weighting(ItemWeighting::IdfInteractions);
+        let item = index.internal_item_id(&1).unwrap();
+        assert!((index.idf(&item) - 2.0_f64.ln()).abs() < 1e-9);
+    }
+
+    #[test]
+    fn should_weight_and_exclude_event_types() {
+        let path = std::env::temp_dir().join(format!("serenade_event_types_{}.csv", std::process::id()));
+        let training_data = "SessionId\tItemId\tTime\tEvent\n\
+            1\t1\t10\tview\n1\t2\t11\tview\n1\t3\t12\tpurchase\n\
+            2\t1\t20\tview\n2\t2\t21\tview\n2\t4\t22\tview\n";
+        std::fs::write(&path, training_data).unwrap();
+        let input_schema = InputSchema {
+            event_type_column: Some(Column::Name("Event".to_string())),
+            ..InputSchema::default()
+        };
+        let mut index = OfflineIndex::new_from_csv(&path.display().to_string(), &input_schema, 10);
+        std::fs::remove_file(&path).unwrap();
+        let top_item = |index: &OfflineIndex| predict(index, &[1], 10, 10, 10, false).into_sorted_vec()[0].id;
+
+        assert_eq!(2, top_item(&index));
+        let mut weights = HashMap::new();
+        weights.insert("purchase".to_string(), 5.0);
+        let event_type_weights = EventTypeWeights::new(weights, vec!["purchase".to_string()]);
+        index.set_event_type_weights(&event_type_weights);
+        assert_eq!(3, top_item(&index));
+
+        let evolving_session = [3, 1];
+        let event_types = [Some("purchase".to_string()), Some("view".to_string())];
+        let session_events = event_type_weights.session_events(&evolving_session, &event_types);
+        let recommendations = predict_with_events(&index, &evolving_session, &session_events, 10, 10, 10, false);
+        assert!(recommendations.iter().all(|scored| scored.id != 3));
+        let recommendations = predict(&index, &evolving_session, 10, 10, 10, false);
+        assert!(recommendations.iter().any(|scored| scored.id == 3));
+    }
+
+    #[test]
+    fn should_persist_the_item_dictionary_with_the_index() {
+        let dir = tempfile::tempdir().unwrap();
+        let path = dir.path().join("training.tsv");
+        let training_data = "SessionId\tItemId\tTime\ns1\tsku-b\t10\ns1\tsku-a\t11\ns2\tsku-c\t20\ns2\tsku-b\t21\n";
+        std::fs::write(&path, training_data).unwrap();
+        let input_schema = InputSchema {
+            string_ids: true,
+            ..InputSchema::default()
+        };
+        let index = OfflineIndex::new_from_csv(&path.display().to_string(), &input_schema, 10);
+
+        let index_path = dir.path().display().to_string();
+        assert_eq!(None, read_item_dictionary(&index_path));
+        index.write_item_dictionary(&index_path).unwrap();
+        assert_eq!(index.item_dictionary(), read_item_dictionary(&index_path).as_ref());
+    }
+
     #[test]
     fn handle_reverse_ordering_itemscore() {
         let largest = ItemScore::new(123, 5000 as f64);
@@ -409,3 +698,114 @@ mod offline_index_test {
 
 
 }
+
+#[cfg(test)]
+mod ranking_test {
+    use proptest::prelude::*;
+
+    use crate::vmisknn::offline_index::OfflineIndex;
+
+    use super::*;
+
+    /// Few items and few distinct timestamps, so that there are many ties among the item scores and the sessions.
+    fn training_sessions() -> impl Strategy<Value = (Vec<Vec<u64>>, Vec<u32>)> {
+        prop::collection::vec(
+            (prop::collection::btree_set(1_u64..12, 1..5), 1_u32..4),
+            1..40,
+        )
+        .prop_map(|sessions| {
+            sessions
+                .into_iter()
+                .map(|(items, time)| (items.into_iter().collect::<Vec<u64>>(), time))
+                .unzip()
+        })
+    }
+
+    fn recommend(index: &OfflineIndex, evolving_session: &[u64], k: usize, m: usize, how_many: usize) -> Vec<(u64, f64)> {
+        predict(index, evolving_session, k, m, how_many, false)
+            .into_sorted_vec()
+            .iter()
+            .map(|scored| (scored.id, scored.score))
+            .collect()
+    }
+
+    proptest! {
+        #![proptest_config(ProptestConfig::with_cases(64))]
+
+        #[test]
+        fn should_sort_item_scores_regardless_of_their_order(
+            scores in prop::collection::btree_map(0_u64..20, 0_u8..4, 0..20),
+            seed in any::<u64>(),
+        ) {
+            let scores: Vec<(u64, f64)> = scores.into_iter().map(|(id, score)| (id, score as f64)).collect();
+            let to_heap = |scores: &[(u64, f64)]| -> Vec<ItemScore> {
+                let heap: BinaryHeap<ItemScore> = scores.iter().map(|(id, score)| ItemScore::new(*id, *score)).collect();
+                heap.into_sorted_vec()
+            };
+            let mut shuffled = scores.clone();
+            let rotation = seed as usize % shuffled.len().max(1);
+            shuffled.rotate_left(rotation);
+            shuffled.reverse();
+
+            let sorted = to_heap(&scores);
+            prop_assert_eq!(&sorted, &to_heap(&shuffled));
+            for (better, worse) in sorted.iter().zip(sorted.iter().skip(1)) {
+                prop_assert!(better.score > worse.score || (better.score == worse.score && better.id < worse.id));
+            }
+        }
+
+        #[test]
+        fn should_recommend_the_same_items_for_equal_indexes(
+            (historical_sessions, max_time_stamps) in training_sessions(),
+            evolving_session in prop::collection::vec(1_u64..12, 1..4),
+            k in 1_usize..8,
+            m in 1_usize..16,
+        ) {
+            // The neighbor search collects the session similarities in a randomly seeded hash map, so the rankings
+            // of two equal indexes only match if the ties are broken by the ids.
+            let index = OfflineIndex::new_for_test(historical_sessions.clone(), max_time_stamps.clone(), m);
+            let other_index = OfflineIndex::new_for_test(historical_sessions, max_time_stamps, m);
+            prop_assert_eq!(
+                recommend(&index, &evolving_session, k, m, 20),
+                recommend(&other_index, &evolving_session, k, m, 20)
+            );
+            let neighbors = index.find_neighbors(&evolving_session, k, m).into_sorted_vec();
+            let other_neighbors = other_index.find_neighbors(&evolving_session, k, m).into_sorted_vec();
+            prop_assert_eq!(neighbors, other_neighbors);
+        }
+
+        #[test]
+        fn should_recommend_a_prefix_of_the_longer_recommendations(
+            (historical_sessions, max_time_stamps) in training_sessions(),
+            evolving_session in prop::collection::vec(1_u64..12, 1..4),
+            how_many in 1_usize..6,
+        ) {
+            let index = OfflineIndex::new_for_test(historical_sessions, max_time_stamps, 10);
+            let all_recommendations = recommend(&index, &evolving_session, 5, 10, 20);
+            let recommendations = recommend(&index, &evolving_session, 5, 10, how_many);
+            let qty_expected = how_many.min(all_recommendations.len());
+            prop_assert_eq!(&all_recommendations[..qty_expected], &recommendations[..]);
+        }
+
+        #[test]
+        fn should_find_the_best_sessions_of_the_larger_neighborhoods(
+            (historical_sessions, max_time_stamps) in training_sessions(),
+            evolving_session in prop::collection::vec(1_u64..12, 1..4),
+            k in 1_usize..6,
+        ) {
+            let index = OfflineIndex::new_for_test(historical_sessions, max_time_stamps.clone(), 10);
+            let mut all_neighbors = index.find_neighbors(&evolving_session, 10, 10).into_vec();
+            // The more recent session and then the smaller session id win ties.
+            all_neighbors.sort_by(|left, right| {
+                right
+                    .score
+                    .total_cmp(&left.score)
+                    .then(max_time_stamps[right.id as usize].cmp(&max_time_stamps[left.id as usize]))
+                    .then(left.id.cmp(&right.id))
+            });
+            all_neighbors.truncate(k);
+            let neighbors = index.find_neighbors(&evolving_session, k, 10).into_sorted_vec();
+            prop_assert_eq!(BinaryHeap::from(all_neighbors).into_sorted_vec(), neighbors);
+        }
+    }
+}
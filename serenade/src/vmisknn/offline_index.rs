@@ -1,16 +1,29 @@
 use crate::dataframeutils::TrainingDataStats;
-use crate::vmisknn::similarity_indexed::SimilarityComputationNew;
-use crate::vmisknn::SessionScore;
+use crate::dictionary::IdDictionary;
+use crate::io::{
+    group_into_sessions, read_interactions, report_malformed_rows, InputSchema, Interaction, ItemId,
+    TrainingSessionId,
+};
+use crate::recommender::{RecommendParams, Recommender, RecommenderKind};
+use crate::vmisknn;
+use crate::vmisknn::product_groups::ProductGroups;
+use crate::vmisknn::sharded_index::ShardAssignment;
+use crate::vmisknn::similarity_indexed::{PostingLists, SimilarityComputationNew};
+use crate::vmisknn::weighting::{EventTypeWeights, ItemCount, ItemCounts, ItemWeighting, SessionEvents, Weighting};
+use crate::vmisknn::{ItemScore, SessionScore};
 use crate::vmisknn::SessionTime;
+use crate::vmisknn::InternalItemId;
 use chrono::NaiveDateTime;
 use dary_heap::OctonaryHeap;
 use hashbrown::HashMap;
 use rayon::prelude::*;
-use serde::Deserialize;
+use serde::{Deserialize, Serialize};
 use std::collections::BinaryHeap;
 use std::fs;
 use std::fs::File;
+use std::iter::Copied;
 use std::path::{Path, PathBuf};
+use std::slice;
 use std::time::Instant;
 use tdigest::TDigest;
 use std::error::Error;
@@ -20,39 +33,152 @@ use avro_rs::Reader;
 use itertools::Itertools;
 use std::sync::{Arc, Mutex};
 
+#[derive(Clone, Debug, Serialize, Deserialize)]
 pub struct ProductAttributes {
     pub(crate) is_adult: bool,
     pub(crate) is_for_sale: bool,
+    /// The category and the brand of the item, used to diversify the recommendations.
+    #[serde(default)]
+    pub(crate) category: Option<u64>,
+    #[serde(default)]
+    pub(crate) brand: Option<u64>,
+    /// The product group of the item, e.g. of its color and size variants, see `ProductGroups`.
+    #[serde(default)]
+    pub(crate) group: Option<u64>,
+    /// The item that represents the group when it is collapsed, the same for all items of the group.
+    #[serde(default)]
+    pub(crate) canonical_item_id: Option<u64>,
 }
 
+impl ProductAttributes {
+    /// Half for a shared category and half for a shared brand, so 1 for items of the same category and brand.
+    pub fn similarity(&self, other: &ProductAttributes) -> f64 {
+        let shares = |left: Option<u64>, right: Option<u64>| left.is_some() && left == right;
+        let mut similarity = 0.0;
+        if shares(self.category, other.category) {
+            similarity += 0.5;
+        }
+        if shares(self.brand, other.brand) {
+            similarity += 0.5;
+        }
+        similarity
+    }
+}
+
+/// All item data is stored in vectors indexed by a dense internal item id. The internal ids are assigned in
+/// ascending order of the external item ids, so the items of a session stay sorted.
 pub struct OfflineIndex {
-    pub(crate) item_to_top_sessions_ordered: HashMap<u64, Vec<u32>>,
+    /// The external item id of every internal item id, in ascending order.
+    pub(crate) item_ids: Vec<u64>,
+    pub(crate) item_to_top_sessions_ordered: Vec<Vec<u32>>,
     pub(crate) session_to_max_time_stamp: Vec<u32>,
-    pub(crate) item_to_idf_score: HashMap<u64, f64>,
-    pub(crate) session_to_items_sorted: Vec<Vec<u64>>,
+    /// Zero for items that occur in sessions but are missing in the item index, so that they don't add to the scores.
+    pub(crate) item_to_idf_score: Vec<f64>,
+    pub(crate) session_to_items_sorted: Vec<Vec<InternalItemId>>,
     pub(crate) training_data_stats: TrainingDataStats,
-    pub(crate) item_to_product_attributes: HashMap<u64, ProductAttributes>,
+    pub(crate) item_to_product_attributes: Vec<Option<ProductAttributes>>,
+    /// Maps external string item ids to the internal item ids, absent when the item ids are numeric.
+    pub(crate) item_dictionary: Option<IdDictionary>,
+    pub(crate) weighting: Weighting,
+    /// The most recent session, the reference time of the recency weighting.
+    pub(crate) max_time_stamp: u32,
+    /// Absent for avro indexes that only contain the idf precomputed by the index build.
+    pub(crate) item_counts: Option<ItemCounts>,
+    /// The weighting of `item_to_idf_score`, `None` for the idf of the index build: precomputed by Spark for avro
+    /// indexes, over the deduplicated training sessions for csv files.
+    pub(crate) item_weighting: Option<ItemWeighting>,
+    /// The event types of the training data, each one a bit in `session_to_item_event_types`.
+    pub(crate) event_types: Vec<String>,
+    /// The bitmask of the event types of every item of a training session, empty without event types.
+    pub(crate) session_to_item_event_types: Vec<Vec<u8>>,
+    /// The weight of every bitmask of event types, empty without event type weights.
+    pub(crate) event_types_to_weight: Vec<f64>,
+    pub(crate) product_groups: ProductGroups,
+}
+
+/// The amount of event types that fit in the bitmasks of `session_to_item_event_types`.
+pub const MAX_EVENT_TYPES: usize = 8;
+
+/// The item counts keyed by external item id, with the total amount of sessions and interactions.
+type RawItemCounts = (HashMap<u64, ItemCount>, u64, u64);
+
+/// The event types and the bitmasks of the event types of every session item.
+type SessionEventTypes = (Vec<String>, Vec<Vec<u8>>);
+
+/// The training data of a csv file, with the sessions deduplicated and their items in ascending order.
+pub struct TrainingDataFromFile {
+    pub sessions: Vec<Vec<u64>>,
+    pub max_time_stamps: Vec<u32>,
+    pub training_data_stats: TrainingDataStats,
+    pub item_dictionary: Option<IdDictionary>,
+    pub item_counts: RawItemCounts,
+    /// Absent if the input schema has no event type column.
+    pub event_types: Option<SessionEventTypes>,
+}
+
+/// The file in the directory of an offline index with the item dictionary, see `IdDictionary::write_to_file`.
+pub const ITEM_DICTIONARY_FILE_NAME: &str = "itemdictionary.txt";
+
+/// The item dictionary of an offline index directory, `None` for indexes with numeric item ids. The index build
+/// writes it for string item ids, so that the server maps them to the same internal ids as the build.
+pub fn read_item_dictionary(base_path: &str) -> Option<IdDictionary> {
+    let item_dictionary_path = Path::new(base_path).join(ITEM_DICTIONARY_FILE_NAME);
+    if !item_dictionary_path.exists() {
+        return None;
+    }
+    let item_dictionary_path = item_dictionary_path.display().to_string();
+    let item_dictionary = IdDictionary::read_from_file(&item_dictionary_path)
+        .unwrap_or_else(|err| panic!("Unable to read {}: {}", item_dictionary_path, err));
+    println!("item dictionary: {} items", item_dictionary.len());
+    Some(item_dictionary)
 }
 
 impl OfflineIndex {
-    pub fn new_from_csv(path_to_training: &str, m_most_recent_sessions: usize) -> Self {
+    pub fn new_from_csv(
+        path_to_training: &str,
+        input_schema: &InputSchema,
+        m_most_recent_sessions: usize,
+    ) -> Self {
         let start_time = Instant::now();
         println!(
             "reading training data, determine items per training session {}",
             &path_to_training
         );
-        let data_train = read_from_file(path_to_training);
-        let (
-            historical_sessions_train,
-            _historical_sessions_id_train,
-            historical_sessions_max_time_stamp,
-            training_data_stats,
-        ) = data_train.unwrap();
+        let data_train = read_from_file(path_to_training, input_schema)
+            .unwrap_or_else(|err| panic!("Unable to read {}: {}", path_to_training, err));
+        let (item_to_count, qty_sessions, qty_interactions) = data_train.item_counts;
         println!(
             "reading training data, determine items per training session:{} micros",
             start_time.elapsed().as_micros()
         );
 
+        let mut index = OfflineIndex::new_from_sessions(
+            data_train.sessions,
+            data_train.max_time_stamps,
+            data_train.training_data_stats,
+            m_most_recent_sessions,
+        );
+        index.item_dictionary = data_train.item_dictionary;
+        if let Some((event_types, session_to_item_event_types)) = data_train.event_types {
+            println!("event types: {}", event_types.join(","));
+            index.event_types = event_types;
+            index.session_to_item_event_types = session_to_item_event_types;
+        }
+        // The sessions are deduplicated, so the interactions have to be counted on the raw training data. Only a
+        // configured item weighting uses them, by default the index keeps the idf over the deduplicated sessions.
+        index.item_weighting = None;
+        index.set_item_counts(item_to_count, qty_sessions, qty_interactions);
+        index
+    }
+
+    /// Builds the index from training sessions with their items in ascending order and their max timestamps.
+    /// Sessions with more items than `qty_events_p99_5` of the statistics are not indexed.
+    pub fn new_from_sessions(
+        historical_sessions: Vec<Vec<u64>>,
+        historical_sessions_max_time_stamp: Vec<u32>,
+        training_data_stats: TrainingDataStats,
+        m_most_recent_sessions: usize,
+    ) -> Self {
         let start_time = Instant::now();
         println!("prepare indexes");
         let (
@@ -61,7 +187,7 @@ impl OfflineIndex {
             _session_to_items_sorted,
             item_to_product_attributes,
         ) = prepare_hashmap(
-            &historical_sessions_train,
+            &historical_sessions,
             &historical_sessions_max_time_stamp,
             m_most_recent_sessions,
             training_data_stats.qty_events_p99_5 as usize,
@@ -71,35 +197,168 @@ impl OfflineIndex {
             start_time.elapsed().as_micros()
         );
 
-        OfflineIndex {
+        let max_training_session_length = training_data_stats.qty_events_p99_5 as usize;
+        let (item_to_count, qty_sessions, qty_interactions) =
+            count_session_items(&historical_sessions, max_training_session_length);
+        let mut index = OfflineIndex::new_from_item_maps(
             item_to_top_sessions_ordered,
-            session_to_max_time_stamp: historical_sessions_max_time_stamp,
+            historical_sessions_max_time_stamp,
             item_to_idf_score,
-            session_to_items_sorted: historical_sessions_train,
+            historical_sessions,
             training_data_stats,
             item_to_product_attributes,
+        );
+        index.set_item_counts(item_to_count, qty_sessions, qty_interactions);
+        index.set_item_weighting(ItemWeighting::default());
+        index
+    }
+
+    /// The index of the unit tests, for a few sessions of up to 10 items.
+    #[cfg(test)]
+    pub(crate) fn new_for_test(
+        historical_sessions: Vec<Vec<u64>>,
+        historical_sessions_max_time_stamp: Vec<u32>,
+        m_most_recent_sessions: usize,
+    ) -> Self {
+        let training_data_stats = TrainingDataStats {
+            qty_events_p99_5: 10,
+            ..TrainingDataStats::default()
+        };
+        OfflineIndex::new_from_sessions(
+            historical_sessions,
+            historical_sessions_max_time_stamp,
+            training_data_stats,
+            m_most_recent_sessions,
+        )
+    }
+
+    /// Reads the item attributes of the unit tests, such as `"item_id,group_id\n2,7\n"`, via a temporary csv file.
+    #[cfg(test)]
+    pub(crate) fn read_item_attributes_for_test(&mut self, item_attributes: &str) {
+        static QTY_FILES: std::sync::atomic::AtomicUsize = std::sync::atomic::AtomicUsize::new(0);
+        let file_index = QTY_FILES.fetch_add(1, std::sync::atomic::Ordering::Relaxed);
+        let path = std::env::temp_dir().join(format!(
+            "serenade_item_attributes_{}_{}.csv",
+            std::process::id(),
+            file_index
+        ));
+        std::fs::write(&path, item_attributes).unwrap();
+        let result = self.read_item_attributes(&path.display().to_string());
+        std::fs::remove_file(&path).unwrap();
+        result.unwrap();
+    }
+
+    /// The index of the unit tests for precomputed posting lists and idf, like those of an avro index.
+    #[cfg(test)]
+    pub(crate) fn new_for_test_from_item_maps(
+        item_to_top_sessions_ordered: HashMap<u64, Vec<u32>>,
+        session_to_items_sorted: Vec<Vec<u64>>,
+        session_to_max_time_stamp: Vec<u32>,
+        item_to_idf_score: HashMap<u64, f64>,
+    ) -> Self {
+        OfflineIndex::new_from_item_maps(
+            item_to_top_sessions_ordered,
+            session_to_max_time_stamp,
+            item_to_idf_score,
+            session_to_items_sorted,
+            TrainingDataStats::default(),
+            HashMap::new(),
+        )
+    }
+
+    /// Converts indices that are keyed by external item ids into the dense layout.
+    pub(crate) fn new_from_item_maps(
+        item_to_top_sessions_ordered: HashMap<u64, Vec<u32>>,
+        session_to_max_time_stamp: Vec<u32>,
+        item_to_idf_score: HashMap<u64, f64>,
+        session_to_items_sorted: Vec<Vec<u64>>,
+        training_data_stats: TrainingDataStats,
+        mut item_to_product_attributes: HashMap<u64, ProductAttributes>,
+    ) -> Self {
+        let mut item_ids: Vec<u64> = item_to_top_sessions_ordered
+            .keys()
+            .chain(item_to_idf_score.keys())
+            .chain(session_to_items_sorted.iter().flatten())
+            .copied()
+            .collect();
+        item_ids.par_sort_unstable();
+        item_ids.dedup();
+        let to_internal =
+            |item_id: &u64| item_ids.binary_search(item_id).unwrap() as InternalItemId;
+
+        let session_to_items_sorted: Vec<Vec<InternalItemId>> = session_to_items_sorted
+            .into_par_iter()
+            .map(|items| items.iter().map(to_internal).collect())
+            .collect();
+
+        let mut dense_top_sessions_ordered = vec![Vec::new(); item_ids.len()];
+        for (item_id, top_sessions_ordered) in item_to_top_sessions_ordered.into_iter() {
+            dense_top_sessions_ordered[to_internal(&item_id) as usize] = top_sessions_ordered;
+        }
+        let mut dense_idf_score = vec![0.0; item_ids.len()];
+        for (item_id, idf_score) in item_to_idf_score.into_iter() {
+            dense_idf_score[to_internal(&item_id) as usize] = idf_score;
+        }
+        let max_time_stamp = session_to_max_time_stamp
+            .par_iter()
+            .max()
+            .copied()
+            .unwrap_or_default();
+        let dense_product_attributes = item_ids
+            .iter()
+            .map(|item_id| item_to_product_attributes.remove(item_id))
+            .collect();
+
+        OfflineIndex {
+            item_ids,
+            item_to_top_sessions_ordered: dense_top_sessions_ordered,
+            session_to_max_time_stamp,
+            item_to_idf_score: dense_idf_score,
+            session_to_items_sorted,
+            training_data_stats,
+            item_to_product_attributes: dense_product_attributes,
+            item_dictionary: None,
+            weighting: Weighting::default(),
+            max_time_stamp,
+            item_counts: None,
+            item_weighting: None,
+            event_types: Vec::new(),
+            session_to_item_event_types: Vec::new(),
+            event_types_to_weight: Vec::new(),
+            product_groups: ProductGroups::default(),
         }
     }
 
     pub fn new(base_path: &str) -> Self {
+        OfflineIndex::new_from_avro(base_path, None)
+    }
+
+    /// Loads the part of an offline index that belongs to a shard. The item data is loaded for all items, but the
+    /// posting lists and the session items only for the sessions of the shard.
+    pub fn new_shard(base_path: &str, shard: &ShardAssignment) -> Self {
+        OfflineIndex::new_from_avro(base_path, Some(shard))
+    }
+
+    fn new_from_avro(base_path: &str, shard: Option<&ShardAssignment>) -> Self {
         println!(
             "reading training data, determine items per training session {}",
             &base_path
         );
         let start_time = Instant::now();
-        let (item_to_top_sessions_ordered, item_to_idf_score, item_to_product_attributes) =
-            create_item_indices_from_avro(&*(base_path.to_owned() + "/itemindex/"));
+        let (item_to_top_sessions_ordered, item_to_idf_score, item_to_product_attributes, item_to_count) =
+            create_item_indices_from_avro(&*(base_path.to_owned() + "/itemindex/"), shard);
         println!(
             "indexing item indices: {} secs",
             start_time.elapsed().as_secs()
         );
         let start_time = Instant::now();
         let (session_to_items_sorted, session_to_max_time_stamp) =
-            create_session_indices_from_avro(&*(base_path.to_owned() + "/sessionindex/"));
+            create_session_indices_from_avro(&*(base_path.to_owned() + "/sessionindex/"), shard);
         println!(
             "indexing session indices: {} secs",
             start_time.elapsed().as_secs()
         );
+        let item_dictionary = read_item_dictionary(base_path);
 
         println!("Using hardcoded session duration percentiles.");
         let session_duration_p05 = 14_u64;
@@ -188,19 +447,28 @@ impl OfflineIndex {
             idf: f64,
             ForSale: bool,
             IsAdult: bool,
+            // The raw counts are only written by newer index builds.
+            qty_sessions: Option<i64>,
+            qty_interactions: Option<i64>,
+            CategoryId: Option<i64>,
+            BrandId: Option<i64>,
+            GroupId: Option<i64>,
         }
         fn create_item_indices_from_avro(
             dir: &str,
+            shard: Option<&ShardAssignment>,
         ) -> (
             HashMap<u64, Vec<u32>>,
             HashMap<u64, f64>,
             HashMap<u64, ProductAttributes>,
+            HashMap<u64, ItemCount>,
         ) {
             // determine_qty_records_in_avro_files(dir);
             // single threaded: indexing item indices: 161 secs
             let mut item_to_top_sessions_ordered = HashMap::with_capacity(10_000_000);
             let mut item_to_idf = HashMap::with_capacity(10_000_000);
             let mut item_to_product_attributes = HashMap::with_capacity(10_000_000);
+            let mut item_to_count = HashMap::new();
             let dir_entry = fs::read_dir(dir).unwrap();
             for path in dir_entry {
                 let full_path_to_file = path.unwrap().path().display().to_string();
@@ -215,6 +483,7 @@ impl OfflineIndex {
                                     .session_indices_time_ordered
                                     .iter()
                                     .map(|x| *x as u32)
+                                    .filter(|session_id| shard.is_none_or(|shard| shard.contains(*session_id)))
                                     .collect_vec();
                                 item_to_top_sessions_ordered
                                     .insert(item_index.ItemId as u64, top_sessions_ordered);
@@ -222,9 +491,22 @@ impl OfflineIndex {
                                 let attributes = ProductAttributes {
                                     is_adult: item_index.IsAdult,
                                     is_for_sale: item_index.ForSale,
+                                    category: item_index.CategoryId.map(|category| category as u64),
+                                    brand: item_index.BrandId.map(|brand| brand as u64),
+                                    group: item_index.GroupId.map(|group| group as u64),
+                                    canonical_item_id: None,
                                 };
                                 item_to_product_attributes
                                     .insert(item_index.ItemId as u64, attributes);
+                                if let (Some(qty_sessions), Some(qty_interactions)) =
+                                    (item_index.qty_sessions, item_index.qty_interactions)
+                                {
+                                    let item_count = ItemCount {
+                                        qty_sessions: qty_sessions as u32,
+                                        qty_interactions: qty_interactions as u32,
+                                    };
+                                    item_to_count.insert(item_index.ItemId as u64, item_count);
+                                }
                             }
                             Err(err) => {
                                 println!("{:?}", err);
@@ -242,9 +524,34 @@ impl OfflineIndex {
                 item_to_top_sessions_ordered,
                 item_to_idf,
                 item_to_product_attributes,
+                item_to_count,
             )
         }
 
+        #[derive(Debug, Deserialize)]
+        struct MetadataAvroSchema {
+            qty_sessions: i64,
+            qty_interactions: i64,
+            /// The `ItemWeighting` of the `idf` column, absent for older index builds.
+            #[serde(default)]
+            item_weighting: Option<String>,
+        }
+        /// The total amount of sessions and interactions that the item counts refer to.
+        fn read_metadata_from_avro(dir: &str) -> Option<MetadataAvroSchema> {
+            let dir_entry = fs::read_dir(dir).ok()?;
+            for path in dir_entry {
+                let full_path_to_file = path.unwrap().path().display().to_string();
+                if full_path_to_file.ends_with(".avro") {
+                    let file = File::open(&full_path_to_file).unwrap();
+                    let reader = Reader::new(file).unwrap();
+                    if let Some(value) = reader.into_iter().next() {
+                        return Some(from_value::<MetadataAvroSchema>(&value.unwrap()).unwrap());
+                    }
+                }
+            }
+            None
+        }
+
         #[allow(non_snake_case)]
         #[derive(Debug, Deserialize)]
         struct SessionIdexAvroSchema {
@@ -252,7 +559,10 @@ impl OfflineIndex {
             item_ids_asc: Vec<i64>,
             Time: i32,
         }
-        fn create_session_indices_from_avro(dir: &str) -> (Vec<Vec<u64>>, Vec<u32>) {
+        fn create_session_indices_from_avro(
+            dir: &str,
+            shard: Option<&ShardAssignment>,
+        ) -> (Vec<Vec<u64>>, Vec<u32>) {
             let mut max_used_session_index_position = 0;
             let mut session_to_items_sorted = vec![Vec::new(); 150_000_000];
             let mut timestamps = vec![0; 150_000_000];
@@ -280,7 +590,10 @@ impl OfflineIndex {
                                     session_to_items_sorted.resize(new_size, Vec::new());
                                     timestamps.resize(new_size, 0);
                                 }
-                                session_to_items_sorted[session_id] = session_items_asc;
+                                // The timestamps of all sessions are kept, so that the session indices stay global.
+                                if shard.is_none_or(|shard| shard.contains(session_id as u32)) {
+                                    session_to_items_sorted[session_id] = session_items_asc;
+                                }
                                 timestamps[session_id] = session_index.Time as u32;
                             }
                             Err(err) => {
@@ -301,85 +614,406 @@ impl OfflineIndex {
             (session_to_items_sorted, timestamps)
         }
 
-        OfflineIndex {
+        let qty_items_with_idf = item_to_idf_score.len();
+        let start_time = Instant::now();
+        let mut index = OfflineIndex::new_from_item_maps(
             item_to_top_sessions_ordered,
             session_to_max_time_stamp,
             item_to_idf_score,
             session_to_items_sorted,
             training_data_stats,
             item_to_product_attributes,
+        );
+        index.item_dictionary = item_dictionary;
+        println!(
+            "assigning internal item ids: {} secs",
+            start_time.elapsed().as_secs()
+        );
+        let metadata = read_metadata_from_avro(&(base_path.to_owned() + "/metadata/"));
+        match &metadata {
+            Some(metadata) if item_to_count.len() == qty_items_with_idf => {
+                index.set_item_counts(item_to_count, metadata.qty_sessions as u64, metadata.qty_interactions as u64);
+            }
+            _ => println!("The index has no item counts, using its precomputed idf"),
+        }
+        // The precomputed idf is kept, the weighting only documents how the index build computed it.
+        index.item_weighting = metadata
+            .and_then(|metadata| metadata.item_weighting)
+            .and_then(|item_weighting| item_weighting.parse().ok());
+        index.update_canonical_items();
+        index
+    }
+
+    /// Drops the posting list entries and the items of the sessions that belong to other shards.
+    /// The session indices stay global, so that the coordinator can merge the neighbors of all shards.
+    pub fn retain_shard(&mut self, shard: &ShardAssignment) {
+        self.item_to_top_sessions_ordered
+            .par_iter_mut()
+            .for_each(|session_ids| {
+                session_ids.retain(|session_id| shard.contains(*session_id));
+                session_ids.shrink_to_fit();
+            });
+        self.session_to_items_sorted
+            .par_iter_mut()
+            .enumerate()
+            .filter(|(session_id, _)| !shard.contains(*session_id as u32))
+            .for_each(|(_, items)| *items = Vec::new());
+        self.session_to_item_event_types
+            .par_iter_mut()
+            .enumerate()
+            .filter(|(session_id, _)| !shard.contains(*session_id as u32))
+            .for_each(|(_, event_types)| *event_types = Vec::new());
+    }
+
+    pub fn set_weighting(&mut self, weighting: Weighting) {
+        self.weighting = weighting;
+    }
+
+    /// Recomputes the item weights from the item counts. Indexes without item counts keep their precomputed idf.
+    pub fn set_item_weighting(&mut self, item_weighting: ItemWeighting) {
+        match &self.item_counts {
+            Some(item_counts) => {
+                self.item_to_idf_score = item_counts.item_weights(item_weighting);
+                self.item_weighting = Some(item_weighting);
+            }
+            None => println!(
+                "The index has no item counts, using its precomputed idf instead of {}",
+                item_weighting
+            ),
+        }
+    }
+
+    /// Weights the items of the training sessions by the largest weight of the event types of their interactions.
+    pub fn set_event_type_weights(&mut self, event_type_weights: &EventTypeWeights) {
+        if self.event_types.is_empty() {
+            return;
+        }
+        self.event_types_to_weight = (0..=u8::MAX)
+            .map(|event_types| {
+                self.event_types
+                    .iter()
+                    .enumerate()
+                    .filter(|(bit, _)| event_types & (1 << bit) != 0)
+                    .map(|(_, event_type)| event_type_weights.weight(Some(event_type)))
+                    .reduce(f64::max)
+                    .unwrap_or(1.0)
+            })
+            .collect();
+    }
+
+    /// `None` if the index uses the idf of the index build.
+    pub fn item_weighting(&self) -> Option<ItemWeighting> {
+        self.item_weighting
+    }
+
+    /// Replaces the item counts and recomputes the item weights with the current weighting, if there is one.
+    /// Counts of items that are not in the index are ignored.
+    fn set_item_counts(&mut self, item_to_count: HashMap<u64, ItemCount>, qty_sessions: u64, qty_interactions: u64) {
+        let mut dense_item_to_count = vec![ItemCount::default(); self.item_ids.len()];
+        for (item_id, item_count) in item_to_count.into_iter() {
+            if let Ok(item) = self.item_ids.binary_search(&item_id) {
+                dense_item_to_count[item] = item_count;
+            }
+        }
+        self.item_counts = Some(ItemCounts {
+            item_to_count: dense_item_to_count,
+            qty_sessions,
+            qty_interactions,
+        });
+        if let Some(item_weighting) = self.item_weighting {
+            self.set_item_weighting(item_weighting);
+        }
+    }
+
+    /// Reads the category, the brand and the product group of the items from a csv file with the header
+    /// `item_id,category_id,brand_id,group_id`, for indexes whose item index doesn't contain them. Missing columns and
+    /// empty values leave the attributes unchanged, and items that are not part of the index are ignored.
+    /// Returns the number of items of the index in the file.
+    pub fn read_item_attributes(&mut self, path: &str) -> Result<usize, Box<dyn Error>> {
+        let mut reader = csv::Reader::from_path(path)?;
+        let headers = reader.headers()?.clone();
+        let column = |name: &str| headers.iter().position(|header| header.trim() == name);
+        let item_id_column = column("item_id").ok_or("The item attributes have no item_id column")?;
+        let category_column = column("category_id");
+        let brand_column = column("brand_id");
+        let group_column = column("group_id");
+
+        let mut qty_items = 0;
+        for record in reader.records() {
+            let record = record?;
+            let item_id = &record[item_id_column];
+            let item_id = match &self.item_dictionary {
+                Some(item_dictionary) => item_dictionary.to_internal(item_id),
+                None => Some(item_id.parse::<u64>()?),
+            };
+            let item = match item_id.and_then(|item_id| self.internal_item_id(&item_id)) {
+                Some(item) => item,
+                None => continue,
+            };
+            let value = |column: Option<usize>| -> Result<Option<u64>, Box<dyn Error>> {
+                match column.map(|column| record[column].trim()) {
+                    Some(value) if !value.is_empty() => Ok(Some(value.parse::<u64>()?)),
+                    _ => Ok(None),
+                }
+            };
+            let (category, brand, group) = (value(category_column)?, value(brand_column)?, value(group_column)?);
+            if let Some(attributes) = self.item_to_product_attributes[item as usize].as_mut() {
+                attributes.category = category.or(attributes.category);
+                attributes.brand = brand.or(attributes.brand);
+                attributes.group = group.or(attributes.group);
+                qty_items += 1;
+            }
+        }
+        self.update_canonical_items();
+        Ok(qty_items)
+    }
+
+    /// How many items of a product group are recommended, see `ProductGroups`.
+    pub fn set_product_groups(&mut self, product_groups: ProductGroups) {
+        self.product_groups = product_groups;
+    }
+
+    /// Assigns the items of every product group the most popular item of the group as canonical item: the item in
+    /// the most sessions, or with the lowest idf for indexes without item counts. Ties go to the smallest item id.
+    /// The item counts and the idf are computed from all training sessions and every shard keeps all items, so the
+    /// shards of a sharded index assign the same canonical items as an unsharded index, although their posting lists
+    /// only contain their own sessions.
+    fn update_canonical_items(&mut self) {
+        let popularity = |item: usize| match &self.item_counts {
+            Some(item_counts) => item_counts.item_to_count[item].qty_sessions as f64,
+            None if self.item_to_idf_score[item] <= 0.0 => f64::NEG_INFINITY,
+            None => -self.item_to_idf_score[item],
+        };
+        let mut group_to_canonical_item: HashMap<u64, (f64, usize)> = HashMap::new();
+        for (item, attributes) in self.item_to_product_attributes.iter().enumerate() {
+            if let Some(group) = attributes.as_ref().and_then(|attributes| attributes.group) {
+                let item_popularity = popularity(item);
+                let canonical_item = group_to_canonical_item
+                    .entry(group)
+                    .or_insert((item_popularity, item));
+                if item_popularity > canonical_item.0 {
+                    *canonical_item = (item_popularity, item);
+                }
+            }
+        }
+        let item_ids = &self.item_ids;
+        for attributes in self.item_to_product_attributes.iter_mut().flatten() {
+            attributes.canonical_item_id = attributes
+                .group
+                .map(|group| item_ids[group_to_canonical_item[&group].1]);
+        }
+    }
+
+    pub fn item_dictionary(&self) -> Option<&IdDictionary> {
+        self.item_dictionary.as_ref()
+    }
+
+    /// Persists the item dictionary in the directory of an offline index, so that `new` can load it.
+    pub fn write_item_dictionary(&self, base_path: &str) -> Result<(), Box<dyn Error>> {
+        match &self.item_dictionary {
+            Some(item_dictionary) => {
+                let path = Path::new(base_path).join(ITEM_DICTIONARY_FILE_NAME);
+                item_dictionary.write_to_file(&path.display().to_string())
+            }
+            None => Ok(()),
         }
     }
 }
 
 impl SimilarityComputationNew for OfflineIndex {
-    fn items_for_session(&self, session: &u32) -> &[u64] {
-        &self.session_to_items_sorted[*session as usize]
+    type SessionItems<'a> = Copied<slice::Iter<'a, InternalItemId>>;
+
+    fn items_for_session(&self, session: &u32) -> Self::SessionItems<'_> {
+        self.session_to_items_sorted[*session as usize].iter().copied()
     }
 
-    fn idf(&self, item: &u64) -> f64 {
-        self.item_to_idf_score[item]
+    fn idf(&self, item: &InternalItemId) -> f64 {
+        self.item_to_idf_score[*item as usize]
     }
 
-    fn find_neighbors(
+    fn find_weighted_neighbors(
         &self,
         evolving_session: &[u64],
+        item_weights: &[f64],
         k: usize,
         m: usize,
     ) -> BinaryHeap<SessionScore> {
-        // We use a d-ary heap for the (timestamp, session_id) tuple, a hashmap for the (session_id, score) tuples, and a hashmap for the unique items in the evolving session
-        let mut heap_timestamps = OctonaryHeap::<SessionTime>::with_capacity(m);
-        let mut session_similarities = HashMap::with_capacity(m);
-        let len_evolving_session = evolving_session.len();
-        let mut unique = evolving_session.iter().clone().collect_vec();
-        unique.sort_unstable();
-        unique.dedup();
-
-        let qty_unique_session_items = unique.len() as f64;
-
-        let mut hash_items = HashMap::with_capacity(len_evolving_session);
-
-        //  Loop over items in evolving session in reverse order
-        for (pos, item_id) in evolving_session.iter().rev().enumerate() {
-            // Duplicate items: only calculate similarity score for the item in the farthest position in the evolving session
-            match hash_items.insert(*item_id, pos) {
-                Some(_) => {}
-                None => {
-                    // Find similar sessions in training data
-                    if let Some(similar_sessions) = self.item_to_top_sessions_ordered.get(item_id) {
-                        let decay_factor =
-                            (len_evolving_session - pos) as f64 / qty_unique_session_items;
-                        // Loop over all similar sessions.
-                        'session_loop: for session_id in similar_sessions {
-                            match session_similarities.get_mut(session_id) {
-                                Some(similarity) => *similarity += decay_factor,
-                                None => {
-                                    let session_time_stamp =
-                                        self.session_to_max_time_stamp[*session_id as usize];
-                                    if session_similarities.len() < m {
-                                        session_similarities.insert(*session_id, decay_factor);
-                                        heap_timestamps.push(SessionTime::new(
-                                            *session_id,
-                                            session_time_stamp,
+        find_neighbors_in_posting_lists(
+            evolving_session,
+            item_weights,
+            k,
+            m,
+            &self.session_to_max_time_stamp,
+            self.max_time_stamp,
+            &self.weighting,
+            |item_id| self.internal_item_id(item_id).map(|item| self.posting_list(&item)),
+        )
+    }
+
+    fn find_attributes(&self, item: &InternalItemId) -> Option<&ProductAttributes> {
+        self.item_to_product_attributes[*item as usize].as_ref()
+    }
+
+    fn qty_items(&self) -> usize {
+        self.item_ids.len()
+    }
+
+    fn internal_item_id(&self, item_id: &u64) -> Option<InternalItemId> {
+        self.item_ids
+            .binary_search(item_id)
+            .ok()
+            .map(|item| item as InternalItemId)
+    }
+
+    fn external_item_id(&self, item: &InternalItemId) -> u64 {
+        self.item_ids[*item as usize]
+    }
+
+    fn weighting(&self) -> Weighting {
+        self.weighting
+    }
+
+    fn product_groups(&self) -> ProductGroups {
+        self.product_groups
+    }
+
+    fn session_item_weight(&self, session: &u32, position: usize) -> f64 {
+        event_type_weight(
+            &self.session_to_item_event_types,
+            &self.event_types_to_weight,
+            session,
+            position,
+        )
+    }
+}
+
+impl PostingLists for OfflineIndex {
+    type PostingList<'a> = Copied<slice::Iter<'a, u32>>;
+
+    fn posting_list(&self, item: &InternalItemId) -> Self::PostingList<'_> {
+        self.item_to_top_sessions_ordered[*item as usize].iter().copied()
+    }
+
+    fn session_to_max_time_stamp(&self) -> &[u32] {
+        &self.session_to_max_time_stamp
+    }
+
+    fn max_time_stamp(&self) -> u32 {
+        self.max_time_stamp
+    }
+}
+
+impl Recommender for OfflineIndex {
+    fn recommend_with_events(
+        &self,
+        evolving_session: &[u64],
+        session_events: &SessionEvents,
+        params: &RecommendParams,
+    ) -> Vec<ItemScore> {
+        vmisknn::predict_with_events(
+            self,
+            evolving_session,
+            session_events,
+            params.neighborhood_size_k,
+            params.m_most_recent_sessions,
+            params.how_many,
+            params.enable_business_logic,
+        )
+        .into_sorted_vec()
+    }
+
+    fn kind(&self) -> RecommenderKind {
+        RecommenderKind::VmisKnn
+    }
+}
+
+/// The weight of the event types of a session item, 1 for sessions without event types or without weights.
+pub(crate) fn event_type_weight(
+    session_to_item_event_types: &[Vec<u8>],
+    event_types_to_weight: &[f64],
+    session: &u32,
+    position: usize,
+) -> f64 {
+    if event_types_to_weight.is_empty() {
+        return 1.0;
+    }
+    match session_to_item_event_types.get(*session as usize) {
+        Some(event_types) if !event_types.is_empty() => event_types_to_weight[event_types[position] as usize],
+        _ => 1.0,
+    }
+}
+
+/// The neighbor search of VMIS-kNN over posting lists with the most recent sessions per item, ordered by
+/// descending time. The posting lists are provided by `similar_sessions_for` so that they can be stored
+/// compressed and decoded on the fly.
+#[allow(clippy::too_many_arguments)]
+pub(crate) fn find_neighbors_in_posting_lists<F, S>(
+    evolving_session: &[u64],
+    item_weights: &[f64],
+    k: usize,
+    m: usize,
+    session_to_max_time_stamp: &[u32],
+    max_time_stamp: u32,
+    weighting: &Weighting,
+    similar_sessions_for: F,
+) -> BinaryHeap<SessionScore>
+where
+    F: Fn(&u64) -> Option<S>,
+    S: Iterator<Item = u32>,
+{
+    // We use a d-ary heap for the (timestamp, session_id) tuple, a hashmap for the (session_id, score) tuples, and a hashmap for the unique items in the evolving session
+    let mut heap_timestamps = OctonaryHeap::<SessionTime>::with_capacity(m);
+    let mut session_similarities = HashMap::with_capacity(m);
+    let len_evolving_session = evolving_session.len();
+    let mut unique = evolving_session.iter().clone().collect_vec();
+    unique.sort_unstable();
+    unique.dedup();
+
+    let qty_unique_session_items = unique.len() as f64;
+
+    let mut hash_items = HashMap::with_capacity(len_evolving_session);
+    debug_assert_eq!(len_evolving_session, item_weights.len());
 
-                                        ));
+    //  Loop over items in evolving session in reverse order
+    for (pos, (item_id, item_weight)) in evolving_session.iter().zip(item_weights.iter()).rev().enumerate() {
+        // Duplicate items: only calculate similarity score for the item in the farthest position in the evolving session
+        match hash_items.insert(*item_id, pos) {
+            Some(_) => {}
+            None => {
+                // Find similar sessions in training data
+                if let Some(similar_sessions) = similar_sessions_for(item_id) {
+                    let decay_factor = item_weight * weighting.decay(pos + 1, len_evolving_session)
+                        / qty_unique_session_items;
+                    // Loop over all similar sessions.
+                    'session_loop: for session_id in similar_sessions {
+                        match session_similarities.get_mut(&session_id) {
+                            Some(similarity) => *similarity += decay_factor,
+                            None => {
+                                let session_time_stamp =
+                                    session_to_max_time_stamp[session_id as usize];
+                                if session_similarities.len() < m {
+                                    session_similarities.insert(session_id, decay_factor);
+                                    heap_timestamps.push(SessionTime::new(
+                                        session_id,
+                                        session_time_stamp,
+
+                                    ));
+                                } else {
+                                    let mut bottom = heap_timestamps.peek_mut().unwrap();
+                                    if session_time_stamp > bottom.time {
+                                        // println!("{:?} {:?}", session_time_stamp, bottom.time);
+                                        // Remove the the existing minimum time stamp.
+                                        session_similarities
+                                            .remove_entry(&bottom.session_id);
+                                        // Set new minimum timestamp
+                                        session_similarities
+                                            .insert(session_id, decay_factor);
+                                        *bottom = SessionTime::new(
+                                            session_id,
+                                            session_time_stamp,
+                                        );
                                     } else {
-                                        let mut bottom = heap_timestamps.peek_mut().unwrap();
-                                        if session_time_stamp > bottom.time {
-                                            // println!("{:?} {:?}", session_time_stamp, bottom.time);
-                                            // Remove the the existing minimum time stamp.
-                                            session_similarities
-                                                .remove_entry(&bottom.session_id);
-                                            // Set new minimum timestamp
-                                            session_similarities
-                                                .insert(*session_id, decay_factor);
-                                            *bottom = SessionTime::new(
-                                                *session_id,
-                                                session_time_stamp,
-                                            );
-                                        } else {
-                                            break 'session_loop;
-                                        }
+                                        break 'session_loop;
                                     }
                                 }
                             }
@@ -388,34 +1022,33 @@ impl SimilarityComputationNew for OfflineIndex {
                 }
             }
         }
-
-        // Return top-k
-        let mut closest_neighbors: BinaryHeap<SessionScore> = BinaryHeap::with_capacity(k);
-        for (session_id, score) in session_similarities.iter() {
-            if closest_neighbors.len() < k {
-                let scored_session = SessionScore::new(*session_id, *score);
-                closest_neighbors.push(scored_session);
-            } else {
-                let mut bottom = closest_neighbors.peek_mut().unwrap();
-                if score > &bottom.score {
-                    let scored_session = SessionScore::new(*session_id, *score);
-                    *bottom = scored_session;
-                } else if (score - bottom.score).abs() < f64::EPSILON
-                    && (self.session_to_max_time_stamp[*session_id as usize]
-                        > self.session_to_max_time_stamp[bottom.id as usize])
-                {
-                    let scored_session = SessionScore::new(*session_id, *score);
-                    *bottom = scored_session;
-                }
-            }
-        }
-        // Closest neigbours contain unique session_ids and corresponding top-k similarity scores
-        closest_neighbors
     }
 
-    fn find_attributes(&self, item_id: &u64) -> Option<&ProductAttributes> {
-        self.item_to_product_attributes.get(item_id)
+    // Return top-k. Ties prefer the more recent session and then the smaller session id, so that the neighbors
+    // don't depend on the iteration order of the hash map.
+    let mut closest_neighbors: Vec<SessionScore> = session_similarities
+        .iter()
+        .map(|(session_id, similarity)| {
+            let score = similarity
+                * weighting.recency_weight(session_to_max_time_stamp[*session_id as usize], max_time_stamp);
+            SessionScore::new(*session_id, score)
+        })
+        .collect();
+    let by_similarity = |left: &SessionScore, right: &SessionScore| {
+        right
+            .score
+            .total_cmp(&left.score)
+            .then(session_to_max_time_stamp[right.id as usize].cmp(&session_to_max_time_stamp[left.id as usize]))
+            .then(left.id.cmp(&right.id))
+    };
+    if closest_neighbors.len() > k {
+        if k > 0 {
+            closest_neighbors.select_nth_unstable_by(k - 1, by_similarity);
+        }
+        closest_neighbors.truncate(k);
     }
+    // Closest neigbours contain unique session_ids and corresponding top-k similarity scores
+    closest_neighbors.into_iter().collect()
 }
 
 pub(crate) fn prepare_hashmap(
@@ -511,6 +1144,10 @@ pub(crate) fn prepare_hashmap(
         let attributes = ProductAttributes {
             is_adult: false,
             is_for_sale: true,
+            category: None,
+            brand: None,
+            group: None,
+            canonical_item_id: None,
         };
         item_to_product_attributes.insert(*current_item, attributes);
     }
@@ -587,101 +1224,36 @@ fn binary_search_left(array: &[u64], key: u64) -> Result<usize, usize> {
 
 pub fn read_from_file(
     path: &str,
-) -> Result<(Vec<Vec<u64>>, Vec<Vec<usize>>, Vec<u32>, TrainingDataStats), Box<dyn Error>> {
-    // Creates a new csv `Reader` from a file
-    let mut reader = csv::ReaderBuilder::new()
-        .delimiter(b'\t')
-        .has_headers(true)
-        .from_path(path)?;
-
-    // Vector initialization
-    let mut session_id: Vec<usize> = Vec::with_capacity(100_000_000);
-    let mut item_id: Vec<usize> = Vec::with_capacity(100_000_000);
-    let mut time: Vec<usize> = Vec::with_capacity(100_000_000);
-
-    reader.deserialize().for_each(|result| {
-        if result.is_ok() {
-            let raw: (usize, usize, f64) = result.unwrap();
-            let (a_session_id, a_item_id, a_time): (usize, usize, usize) =
-                (raw.0, raw.1, raw.2.round() as usize);
-
-            session_id.push(a_session_id);
-            item_id.push(a_item_id);
-            time.push(a_time);
-        } else {
-            eprintln!("Unable to parse input!");
-        }
-    });
+    input_schema: &InputSchema,
+) -> Result<TrainingDataFromFile, Box<dyn Error>> {
+    let interactions = read_interactions(path, input_schema)?;
+    report_malformed_rows(path, &interactions);
+    let item_dictionary = interactions.item_dictionary;
+    let interactions = interactions.records;
+    if interactions.is_empty() {
+        return Err(format!("No valid training data found in {}", path).into());
+    }
 
-    // Sort by session id - the data is unsorted
-    let mut session_id_indices: Vec<usize> = (0..session_id.len()).into_iter().collect();
-    session_id_indices.sort_by_key(|&i| &session_id[i]);
-    let session_id_sorted: Vec<usize> = session_id_indices
-        .iter()
-        .map(|&i| session_id[i])
-        .collect();
-    let item_id_sorted: Vec<usize> = session_id_indices
-        .iter()
-        .map(|&i| item_id[i])
-        .collect();
-    let time_sorted: Vec<usize> = session_id_indices
-        .iter()
-        .map(|&i| time[i])
-        .collect();
+    let qty_records = interactions.len();
 
-    // Get unique session ids
-    session_id.sort_unstable();
+    let mut session_id: Vec<TrainingSessionId> =
+        interactions.par_iter().map(|interaction| interaction.session_id).collect();
+    session_id.par_sort_unstable();
     session_id.dedup();
-
-    let qty_records = time_sorted.len();
     let qty_unique_session_ids = session_id.len();
 
-    // Get unique item ids
-    // let mut unique_item_ids = item_id.clone();
-    item_id.sort_unstable();
+    let mut item_id: Vec<ItemId> = interactions.par_iter().map(|interaction| interaction.item_id).collect();
+    item_id.par_sort_unstable();
     item_id.dedup();
     let qty_unique_item_ids = item_id.len();
 
-    let min_time = time.par_iter().min().unwrap();
-    let min_time_date_time = NaiveDateTime::from_timestamp(*min_time as i64, 0);
-    let max_time = time.par_iter().max().unwrap();
-    let max_time_date_time = NaiveDateTime::from_timestamp(*max_time as i64, 0);
-
-    // Create historical sessions array (deduplicated), historical sessions id array and array with max timestamps.
-    //let mut i: usize = 0;
-    let mut historical_sessions: Vec<Vec<u64>> = Vec::with_capacity(session_id.len());
-    let mut historical_sessions_id: Vec<Vec<usize>> = Vec::with_capacity(session_id.len());
-    let mut historical_sessions_max_time_stamp: Vec<u32> =
-        Vec::with_capacity(session_id.len());
-    let mut history_session: Vec<u64> = Vec::with_capacity(1000);
-    let mut history_session_id: Vec<usize> = Vec::with_capacity(1000);
-    let mut max_time_stamp: usize = time_sorted[0];
-    // Push initial session and item id
-    history_session.push(item_id_sorted[0] as u64);
-    history_session_id.push(item_id_sorted[0]);
-    // Loop over length of data
-    for i in 1..session_id_sorted.len() {
-        if session_id_sorted[i] == session_id_sorted[i - 1] {
-            if !history_session.contains(&(item_id_sorted[i] as u64)) {
-                history_session.push(item_id_sorted[i] as u64);
-                history_session_id.push(session_id_sorted[i]);
-                if time_sorted[i] > max_time_stamp {
-                    max_time_stamp = time_sorted[i];
-                }
-            }
-        } else {
-            let mut history_session_sorted = history_session.clone();
-            history_session_sorted.sort_unstable();
-            historical_sessions.push(history_session_sorted);
-            historical_sessions_id.push(history_session_id.clone());
-            historical_sessions_max_time_stamp.push(max_time_stamp as u32);
-            history_session.clear();
-            history_session_id.clear();
-            history_session.push(item_id_sorted[i] as u64);
-            history_session_id.push(session_id_sorted[i]);
-            max_time_stamp = time_sorted[i];
-        }
-    }
+    let min_time = interactions.par_iter().map(|interaction| interaction.time).min().unwrap();
+    let min_time_date_time = NaiveDateTime::from_timestamp(min_time as i64, 0);
+    let max_time = interactions.par_iter().map(|interaction| interaction.time).max().unwrap();
+    let max_time_date_time = NaiveDateTime::from_timestamp(max_time as i64, 0);
+
+    // Create historical sessions array (deduplicated) and array with max timestamps.
+    let (historical_sessions, historical_sessions_max_time_stamp) = group_into_sessions(&interactions);
 
     let qty_events = historical_sessions
         .iter()
@@ -740,10 +1312,104 @@ pub fn read_from_file(
     };
 
     println!("qty_events_p99_5: {}", qty_events_p99_5);
-    Ok((
-        historical_sessions,
-        historical_sessions_id,
-        historical_sessions_max_time_stamp,
+
+    // Counted like the index: without the sessions that are too long, but with every interaction of an item.
+    let max_training_session_length = qty_events_p99_5 as usize;
+    let (mut item_to_count, qty_sessions, _) =
+        count_session_items(&historical_sessions, max_training_session_length);
+    item_to_count
+        .values_mut()
+        .for_each(|item_count| item_count.qty_interactions = 0);
+    let mut qty_interactions = 0_u64;
+    for interaction in interactions.iter() {
+        // The sessions are grouped in ascending order of their session ids.
+        let session_index = session_id.binary_search(&interaction.session_id).unwrap();
+        if historical_sessions[session_index].len() <= max_training_session_length {
+            item_to_count
+                .get_mut(&interaction.item_id)
+                .unwrap()
+                .qty_interactions += 1;
+            qty_interactions += 1;
+        }
+    }
+
+    let event_types = if interactions.iter().any(|interaction| interaction.event_type.is_some()) {
+        Some(collect_event_types(&interactions, &session_id, &historical_sessions))
+    } else {
+        None
+    };
+
+    Ok(TrainingDataFromFile {
+        sessions: historical_sessions,
+        max_time_stamps: historical_sessions_max_time_stamp,
         training_data_stats,
-    ))
+        item_dictionary,
+        item_counts: (item_to_count, qty_sessions, qty_interactions),
+        event_types,
+    })
+}
+
+/// The `MAX_EVENT_TYPES` most frequent event types and the bitmask of the event types of every session item.
+/// `session_ids` are the ascending session ids of the `historical_sessions`.
+fn collect_event_types(
+    interactions: &[Interaction],
+    session_ids: &[TrainingSessionId],
+    historical_sessions: &[Vec<u64>],
+) -> SessionEventTypes {
+    let mut event_type_to_qty: HashMap<&str, usize> = HashMap::new();
+    for event_type in interactions.iter().filter_map(|interaction| interaction.event_type.as_deref()) {
+        *event_type_to_qty.entry(event_type).or_default() += 1;
+    }
+    let mut event_types: Vec<String> = event_type_to_qty
+        .into_iter()
+        .sorted_by(|(left, left_qty), (right, right_qty)| right_qty.cmp(left_qty).then(left.cmp(right)))
+        .map(|(event_type, _)| event_type.to_string())
+        .collect();
+    if event_types.len() > MAX_EVENT_TYPES {
+        println!(
+            "Only the {} most frequent event types are used, ignoring: {}",
+            MAX_EVENT_TYPES,
+            event_types[MAX_EVENT_TYPES..].join(",")
+        );
+        event_types.truncate(MAX_EVENT_TYPES);
+    }
+
+    let mut session_to_item_event_types: Vec<Vec<u8>> = historical_sessions
+        .iter()
+        .map(|session| vec![0; session.len()])
+        .collect();
+    for interaction in interactions.iter() {
+        let bit = interaction
+            .event_type
+            .as_ref()
+            .and_then(|event_type| event_types.iter().position(|known| known == event_type));
+        if let Some(bit) = bit {
+            let session_index = session_ids.binary_search(&interaction.session_id).unwrap();
+            let position = historical_sessions[session_index]
+                .binary_search(&interaction.item_id)
+                .unwrap();
+            session_to_item_event_types[session_index][position] |= 1 << bit;
+        }
+    }
+    (event_types, session_to_item_event_types)
+}
+
+/// Counts the sessions and the interactions of every item in the sessions that are not too long for the index.
+/// The sessions are deduplicated, so every session counts a single interaction with each of its items.
+fn count_session_items(historical_sessions: &[Vec<u64>], max_training_session_length: usize) -> RawItemCounts {
+    let mut item_to_count: HashMap<u64, ItemCount> = HashMap::new();
+    let mut qty_sessions = 0_u64;
+    let mut qty_interactions = 0_u64;
+    for session in historical_sessions.iter() {
+        if session.len() <= max_training_session_length {
+            for item_id in session.iter() {
+                let item_count = item_to_count.entry(*item_id).or_default();
+                item_count.qty_sessions += 1;
+                item_count.qty_interactions += 1;
+            }
+            qty_sessions += 1;
+            qty_interactions += session.len() as u64;
+        }
+    }
+    (item_to_count, qty_sessions, qty_interactions)
 }
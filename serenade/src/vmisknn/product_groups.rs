@@ -0,0 +1,171 @@
+use std::collections::BinaryHeap;
+
+use hashbrown::HashMap;
+
+use crate::vmisknn::offline_index::ProductAttributes;
+use crate::vmisknn::similarity_indexed::SimilarityComputationNew;
+use crate::vmisknn::weighting::SessionEvents;
+use crate::vmisknn::{passes_business_rules, DenseScoreAccumulator, InternalItemId, ItemScore};
+
+/// How the recommendations treat the items of a product group, e.g. the color and size variants of a product, by the
+/// `group` of their `ProductAttributes`. Items without a group are never limited.
+#[derive(Clone, Copy, Debug, Default, PartialEq, Eq)]
+pub struct ProductGroups {
+    /// The maximum number of recommended items of a group, 0 doesn't limit them.
+    pub max_items_per_group: usize,
+    /// Whether every group is recommended once, as its canonical item with the score of its best item.
+    pub collapse: bool,
+}
+
+impl ProductGroups {
+    pub fn is_enabled(&self) -> bool {
+        self.max_items_per_group > 0 || self.collapse
+    }
+
+    fn max_items_per_group(&self) -> usize {
+        if self.collapse {
+            1
+        } else {
+            self.max_items_per_group
+        }
+    }
+}
+
+/// Like the selection of `select_top_items`, with at most `max_items_per_group` items of a group. All scored items
+/// are ranked, so that the items of the groups that are full are replaced by the next best items. A collapsed group is
+/// represented by its canonical item, unless that item can't be recommended, e.g. because it is part of the session.
+pub(super) fn select_top_items_per_group<I: SimilarityComputationNew>(
+    index: &I,
+    item_scores: &mut DenseScoreAccumulator,
+    current_item_attribs: Option<&ProductAttributes>,
+    most_recent_item: Option<InternalItemId>,
+    session_events: &SessionEvents,
+    how_many: usize,
+    enable_business_logic: bool,
+) -> BinaryHeap<ItemScore> {
+    let product_groups = index.product_groups();
+    let passes = |item: &InternalItemId| {
+        !enable_business_logic || passes_business_rules(current_item_attribs, index.find_attributes(item))
+    };
+
+    let mut candidates: Vec<(ItemScore, InternalItemId)> = Vec::new();
+    item_scores.drain(|reco_item, reco_item_score| {
+        if passes(&reco_item) {
+            candidates.push((ItemScore::new(index.external_item_id(&reco_item), reco_item_score), reco_item));
+        }
+    });
+    // The best item first, see the `Ord` of `ItemScore`.
+    candidates.sort_unstable_by(|(left, _), (right, _)| left.cmp(right));
+
+    let can_represent = |canonical_item_id: &u64| {
+        index.internal_item_id(canonical_item_id).is_some_and(|canonical_item| {
+            Some(canonical_item) != most_recent_item
+                && !session_events.excluded_items.contains(canonical_item_id)
+                && passes(&canonical_item)
+        })
+    };
+
+    let mut group_to_qty_items: HashMap<u64, usize> = HashMap::new();
+    let mut top_items: BinaryHeap<ItemScore> = BinaryHeap::with_capacity(how_many);
+    for (scored_item, reco_item) in candidates.into_iter() {
+        if top_items.len() >= how_many {
+            break;
+        }
+        let attributes = index.find_attributes(&reco_item);
+        let group = match attributes.and_then(|attributes| attributes.group) {
+            Some(group) => group,
+            None => {
+                top_items.push(scored_item);
+                continue;
+            }
+        };
+        let qty_items = group_to_qty_items.entry(group).or_default();
+        if *qty_items >= product_groups.max_items_per_group() {
+            continue;
+        }
+        *qty_items += 1;
+        let canonical_item_id = attributes.and_then(|attributes| attributes.canonical_item_id);
+        match canonical_item_id {
+            Some(canonical_item_id) if product_groups.collapse && can_represent(&canonical_item_id) => {
+                top_items.push(ItemScore::new(canonical_item_id, scored_item.score))
+            }
+            _ => top_items.push(scored_item),
+        }
+    }
+    top_items
+}
+
+#[cfg(test)]
+mod product_groups_test {
+    use crate::vmisknn;
+    use crate::vmisknn::offline_index::OfflineIndex;
+
+    use super::*;
+
+    /// Items 2, 3 and 4 are variants of one product, item 4 occurs in the most sessions.
+    fn create_index() -> OfflineIndex {
+        let historical_sessions = vec![vec![1, 2, 4], vec![1, 3, 5], vec![1, 4, 5], vec![4, 6], vec![1, 2, 3]];
+        let max_time_stamps = vec![10, 20, 30, 40, 50];
+        let mut index = OfflineIndex::new_for_test(historical_sessions, max_time_stamps, 10);
+        index.read_item_attributes_for_test("item_id,group_id\n2,7\n3,7\n4,7\n");
+        index
+    }
+
+    fn recommended_items(index: &OfflineIndex, evolving_session: &[u64]) -> Vec<u64> {
+        vmisknn::predict(index, evolving_session, 10, 10, 10, false)
+            .into_sorted_vec()
+            .iter()
+            .map(|scored| scored.id)
+            .collect()
+    }
+
+    fn count_in_group(recommendations: &[u64]) -> usize {
+        recommendations.iter().filter(|item_id| [2, 3, 4].contains(item_id)).count()
+    }
+
+    #[test]
+    fn should_limit_the_items_per_group() {
+        let mut index = create_index();
+        let recommendations = recommended_items(&index, &[1]);
+        assert_eq!(3, count_in_group(&recommendations));
+
+        index.set_product_groups(ProductGroups {
+            max_items_per_group: 2,
+            collapse: false,
+        });
+        let limited = recommended_items(&index, &[1]);
+        assert_eq!(2, count_in_group(&limited));
+        let expected: Vec<u64> = recommendations
+            .iter()
+            .copied()
+            .filter(|item_id| ![2, 3, 4].contains(item_id) || limited.contains(item_id))
+            .collect();
+        assert_eq!(expected, limited);
+    }
+
+    #[test]
+    fn should_collapse_a_group_to_its_canonical_item() {
+        let mut index = create_index();
+        let best_of_group = vmisknn::predict(&index, &[1], 10, 10, 10, false)
+            .into_sorted_vec()
+            .into_iter()
+            .find(|scored| [2, 3, 4].contains(&scored.id))
+            .unwrap();
+        index.set_product_groups(ProductGroups {
+            max_items_per_group: 0,
+            collapse: true,
+        });
+
+        // The group is recommended once, as the most popular item 4, with the score of its best item.
+        let collapsed = vmisknn::predict(&index, &[1], 10, 10, 10, false).into_sorted_vec();
+        let in_group: Vec<&ItemScore> = collapsed.iter().filter(|scored| [2, 3, 4].contains(&scored.id)).collect();
+        assert_eq!(1, in_group.len());
+        assert_eq!(4, in_group[0].id);
+        assert!((best_of_group.score - in_group[0].score).abs() < 1e-9);
+
+        // Item 4 is part of the session, so its best variant represents the group.
+        let without_canonical = recommended_items(&index, &[1, 4]);
+        assert_eq!(1, count_in_group(&without_canonical));
+        assert!(!without_canonical.contains(&4));
+    }
+}
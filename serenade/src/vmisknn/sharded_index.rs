@@ -0,0 +1,484 @@
+use std::cmp::Ordering;
+use std::collections::BinaryHeap;
+use std::iter::Copied;
+use std::slice;
+
+use hashbrown::HashMap;
+use itertools::Itertools;
+use serde::{Deserialize, Serialize};
+
+use crate::recommender::{RecommendParams, Recommender, RecommenderKind};
+use crate::vmisknn;
+use crate::vmisknn::offline_index::{OfflineIndex, ProductAttributes};
+use crate::vmisknn::product_groups::ProductGroups;
+use crate::vmisknn::similarity_indexed::SimilarityComputationNew;
+use crate::vmisknn::weighting::{SessionEvents, Weighting};
+use crate::vmisknn::{InternalItemId, ItemScore, SessionScore};
+
+/// The sessions of a shard: every session whose index modulo `qty_shards` equals `shard_index`.
+#[derive(Clone, Debug)]
+pub struct ShardAssignment {
+    pub shard_index: usize,
+    pub qty_shards: usize,
+}
+
+impl ShardAssignment {
+    pub fn new(shard_index: usize, qty_shards: usize) -> Self {
+        assert!(
+            shard_index < qty_shards,
+            "shard_index {} must be smaller than qty_shards {}",
+            shard_index,
+            qty_shards
+        );
+        ShardAssignment {
+            shard_index,
+            qty_shards,
+        }
+    }
+
+    pub fn contains(&self, session_id: u32) -> bool {
+        session_id as usize % self.qty_shards == self.shard_index
+    }
+}
+
+/// The request of the coordinator to each shard.
+#[derive(Debug, Serialize, Deserialize)]
+pub struct ShardNeighborsRequest {
+    pub session_items: Vec<u64>,
+    /// The weights of the session items by their event types, empty if all items have weight 1.
+    #[serde(default)]
+    pub item_weights: Vec<f64>,
+    pub m: usize,
+}
+
+/// A candidate neighbor session of a shard with its partial similarity to the evolving session.
+#[derive(Debug, Serialize, Deserialize)]
+pub struct NeighborSession {
+    pub session_id: u32,
+    pub time: u32,
+    pub score: f64,
+    pub item_ids: Vec<u64>,
+    /// The weights of the items by their event types, empty if all items have weight 1.
+    #[serde(default)]
+    pub item_weights: Vec<f64>,
+}
+
+/// The item data that the coordinator needs to score the items of the neighbor sessions.
+#[derive(Debug, Serialize, Deserialize)]
+pub struct NeighborItem {
+    pub item_id: u64,
+    pub idf: f64,
+    pub attributes: Option<ProductAttributes>,
+}
+
+#[derive(Debug, Default, Serialize, Deserialize)]
+pub struct ShardNeighbors {
+    pub sessions: Vec<NeighborSession>,
+    pub items: Vec<NeighborItem>,
+}
+
+/// The scatter step, executed by every shard: the `m` most recent sessions of the shard that share items with the
+/// evolving session. They are not reduced to the top-`k` yet, because that requires the sessions of all shards.
+pub fn find_shard_neighbors(
+    index: &OfflineIndex,
+    evolving_session: &[u64],
+    item_weights: &[f64],
+    m: usize,
+) -> ShardNeighbors {
+    let candidates = if item_weights.is_empty() {
+        index.find_neighbors(evolving_session, m, m)
+    } else {
+        index.find_weighted_neighbors(evolving_session, item_weights, m, m)
+    };
+    let has_event_type_weights = !index.event_types_to_weight.is_empty();
+
+    let mut items: Vec<InternalItemId> = evolving_session
+        .iter()
+        .filter_map(|item_id| index.internal_item_id(item_id))
+        .collect();
+    let sessions = candidates
+        .into_iter()
+        .map(|scored_session| {
+            let session_items = &index.session_to_items_sorted[scored_session.id as usize];
+            items.extend(session_items.iter());
+            NeighborSession {
+                session_id: scored_session.id,
+                time: index.session_to_max_time_stamp[scored_session.id as usize],
+                score: scored_session.score,
+                item_ids: session_items
+                    .iter()
+                    .map(|item| index.external_item_id(item))
+                    .collect(),
+                item_weights: if has_event_type_weights {
+                    (0..session_items.len())
+                        .map(|position| index.session_item_weight(&scored_session.id, position))
+                        .collect()
+                } else {
+                    Vec::new()
+                },
+            }
+        })
+        .collect();
+
+    // The coordinator can only collapse a group to its canonical item if it knows the item, also when no neighbor
+    // session contains it.
+    let canonical_items: Vec<InternalItemId> = items
+        .iter()
+        .filter_map(|item| index.find_attributes(item).and_then(|attributes| attributes.canonical_item_id))
+        .filter_map(|canonical_item_id| index.internal_item_id(&canonical_item_id))
+        .collect();
+    items.extend(canonical_items);
+    items.sort_unstable();
+    items.dedup();
+    let items = items
+        .into_iter()
+        .map(|item| {
+            NeighborItem {
+                item_id: index.external_item_id(&item),
+                idf: index.idf(&item),
+                attributes: index.find_attributes(&item).cloned(),
+            }
+        })
+        .collect();
+
+    ShardNeighbors { sessions, items }
+}
+
+/// The gather step, executed by the coordinator: the neighbors of all shards reduced to the `m` most recent and
+/// then to the `k` most similar sessions. It is a small index with only these sessions and their items, so that
+/// `vmisknn::predict` scores the items exactly like for a single index.
+pub struct MergedNeighborhood {
+    item_ids: Vec<u64>,
+    item_to_idf_score: Vec<f64>,
+    item_to_product_attributes: Vec<Option<ProductAttributes>>,
+    session_to_items_sorted: Vec<Vec<InternalItemId>>,
+    session_to_item_weights: Vec<Vec<f64>>,
+    session_scores: Vec<f64>,
+    weighting: Weighting,
+    product_groups: ProductGroups,
+}
+
+impl MergedNeighborhood {
+    /// The `weighting` has to match the weighting of the shards.
+    pub fn new(shard_neighbors: Vec<ShardNeighbors>, k: usize, m: usize, weighting: Weighting) -> Self {
+        let mut item_data: HashMap<u64, (f64, Option<ProductAttributes>)> = HashMap::new();
+        let mut candidates = Vec::new();
+        for neighbors in shard_neighbors.into_iter() {
+            for item in neighbors.items.into_iter() {
+                item_data.insert(item.item_id, (item.idf, item.attributes));
+            }
+            candidates.extend(neighbors.sessions);
+        }
+
+        // Session indices are global, so sessions of different shards never collide.
+        candidates.sort_unstable_by(|left, right| {
+            right
+                .time
+                .cmp(&left.time)
+                .then(left.session_id.cmp(&right.session_id))
+        });
+        candidates.truncate(m);
+        candidates.sort_by(|left, right| {
+            right
+                .score
+                .partial_cmp(&left.score)
+                .unwrap_or(Ordering::Equal)
+                .then(right.time.cmp(&left.time))
+                .then(left.session_id.cmp(&right.session_id))
+        });
+        candidates.truncate(k);
+
+        let item_ids: Vec<u64> = item_data.keys().copied().sorted().collect();
+        let to_internal =
+            |item_id: &u64| item_ids.binary_search(item_id).unwrap() as InternalItemId;
+        let session_to_items_sorted = candidates
+            .iter()
+            .map(|session| session.item_ids.iter().map(to_internal).collect())
+            .collect();
+        let session_scores = candidates.iter().map(|session| session.score).collect();
+        let session_to_item_weights = candidates
+            .iter_mut()
+            .map(|session| std::mem::take(&mut session.item_weights))
+            .collect();
+        let (item_to_idf_score, item_to_product_attributes) = item_ids
+            .iter()
+            .map(|item_id| {
+                item_data.remove(item_id).unwrap()
+            })
+            .unzip();
+
+        MergedNeighborhood {
+            item_ids,
+            item_to_idf_score,
+            item_to_product_attributes,
+            session_to_items_sorted,
+            session_to_item_weights,
+            session_scores,
+            weighting,
+            product_groups: ProductGroups::default(),
+        }
+    }
+
+    /// The `ProductGroups` of the coordinator. The canonical items of the groups are assigned by the shards, which
+    /// all pick them from the item counts of all training sessions, see `OfflineIndex::update_canonical_items`.
+    pub fn set_product_groups(&mut self, product_groups: ProductGroups) {
+        self.product_groups = product_groups;
+    }
+}
+
+impl SimilarityComputationNew for MergedNeighborhood {
+    type SessionItems<'a> = Copied<slice::Iter<'a, InternalItemId>>;
+
+    fn items_for_session(&self, session: &u32) -> Self::SessionItems<'_> {
+        self.session_to_items_sorted[*session as usize].iter().copied()
+    }
+
+    fn idf(&self, item: &InternalItemId) -> f64 {
+        self.item_to_idf_score[*item as usize]
+    }
+
+    /// The neighbors have already been selected when the shards were merged.
+    fn find_weighted_neighbors(
+        &self,
+        _evolving_session: &[u64],
+        _item_weights: &[f64],
+        _k: usize,
+        _m: usize,
+    ) -> BinaryHeap<SessionScore> {
+        self.session_scores
+            .iter()
+            .enumerate()
+            .map(|(session, score)| SessionScore::new(session as u32, *score))
+            .collect()
+    }
+
+    fn find_attributes(&self, item: &InternalItemId) -> Option<&ProductAttributes> {
+        self.item_to_product_attributes[*item as usize].as_ref()
+    }
+
+    fn qty_items(&self) -> usize {
+        self.item_ids.len()
+    }
+
+    fn internal_item_id(&self, item_id: &u64) -> Option<InternalItemId> {
+        self.item_ids
+            .binary_search(item_id)
+            .ok()
+            .map(|item| item as InternalItemId)
+    }
+
+    fn external_item_id(&self, item: &InternalItemId) -> u64 {
+        self.item_ids[*item as usize]
+    }
+
+    fn weighting(&self) -> Weighting {
+        self.weighting
+    }
+
+    fn product_groups(&self) -> ProductGroups {
+        self.product_groups
+    }
+
+    fn session_item_weight(&self, session: &u32, position: usize) -> f64 {
+        self.session_to_item_weights[*session as usize]
+            .get(position)
+            .copied()
+            .unwrap_or(1.0)
+    }
+}
+
+/// Recommends the items of the merged neighbors. The neighborhood only holds the neighbors of the evolving session
+/// that the shards were asked for, so it only recommends for that session.
+impl Recommender for MergedNeighborhood {
+    fn recommend_with_events(
+        &self,
+        evolving_session: &[u64],
+        session_events: &SessionEvents,
+        params: &RecommendParams,
+    ) -> Vec<ItemScore> {
+        vmisknn::predict_with_events(
+            self,
+            evolving_session,
+            session_events,
+            params.neighborhood_size_k,
+            params.m_most_recent_sessions,
+            params.how_many,
+            params.enable_business_logic,
+        )
+        .into_sorted_vec()
+    }
+
+    fn kind(&self) -> RecommenderKind {
+        RecommenderKind::VmisKnn
+    }
+}
+
+#[cfg(test)]
+mod sharded_index_test {
+
+    use super::*;
+
+    fn create_index() -> OfflineIndex {
+        let historical_sessions = vec![
+            vec![1, 2, 300],
+            vec![2, 3],
+            vec![1, 3, 70_000],
+            vec![2, 300, 70_000],
+            vec![1, 2, 3, 300],
+            vec![3, 4],
+            vec![1, 4, 70_000],
+        ];
+        OfflineIndex::new_for_test(historical_sessions, vec![10, 40, 20, 50, 30, 60, 70], 10)
+    }
+
+    #[test]
+    fn should_retain_only_the_sessions_of_a_shard() {
+        let mut index = create_index();
+        let shard = ShardAssignment::new(1, 3);
+        index.retain_shard(&shard);
+
+        for session_id in 0..7 {
+            assert_eq!(shard.contains(session_id), index.items_for_session(&session_id).next().is_some());
+        }
+        for session_ids in index.item_to_top_sessions_ordered.iter() {
+            assert!(session_ids.iter().all(|session_id| shard.contains(*session_id)));
+        }
+    }
+
+    #[test]
+    fn should_predict_the_same_as_a_single_index() {
+        let index = create_index();
+        let qty_shards = 3;
+        let shards: Vec<OfflineIndex> = (0..qty_shards)
+            .map(|shard_index| {
+                let mut shard = create_index();
+                shard.retain_shard(&ShardAssignment::new(shard_index, qty_shards));
+                shard
+            })
+            .collect();
+
+        // Without truncation, because a single index breaks ties in similarity arbitrarily.
+        let (k, m) = (10, 10);
+        for evolving_session in [vec![1], vec![2, 3], vec![300, 1, 70_000], vec![4, 42]].iter() {
+            let shard_neighbors = shards
+                .iter()
+                .map(|shard| find_shard_neighbors(shard, evolving_session, &[], m))
+                .collect();
+            let neighborhood = MergedNeighborhood::new(shard_neighbors, k, m, index.weighting());
+
+            let expected = vmisknn::predict(&index, evolving_session, k, m, 10, true).into_sorted_vec();
+            let actual = vmisknn::predict(&neighborhood, evolving_session, k, m, 10, true).into_sorted_vec();
+            let params = RecommendParams {
+                neighborhood_size_k: k,
+                m_most_recent_sessions: m,
+                how_many: 10,
+                enable_business_logic: true,
+            };
+            assert_eq!(actual, neighborhood.recommend(evolving_session, &params));
+            let expected_items = expected.iter().map(|scored| scored.id).sorted().collect_vec();
+            let actual_items = actual.iter().map(|scored| scored.id).sorted().collect_vec();
+            assert_eq!(expected_items, actual_items);
+            let expected_score: f64 = expected.iter().map(|scored| scored.score).sum();
+            let actual_score: f64 = actual.iter().map(|scored| scored.score).sum();
+            assert!((expected_score - actual_score).abs() < 1e-9);
+        }
+    }
+
+    #[test]
+    fn should_collapse_the_groups_like_a_single_index() {
+        // Items 300 and 70_000 occur in three sessions each, so item 300 is the canonical item of their group.
+        let group_attributes = "item_id,group_id\n300,7\n70000,7\n";
+        let product_groups = ProductGroups {
+            max_items_per_group: 0,
+            collapse: true,
+        };
+        let mut index = create_index();
+        index.read_item_attributes_for_test(group_attributes);
+        index.set_product_groups(product_groups);
+        let qty_shards = 3;
+        let shards: Vec<OfflineIndex> = (0..qty_shards)
+            .map(|shard_index| {
+                let mut shard = create_index();
+                shard.retain_shard(&ShardAssignment::new(shard_index, qty_shards));
+                shard.read_item_attributes_for_test(group_attributes);
+                shard
+            })
+            .collect();
+        for shard in shards.iter() {
+            let item = shard.internal_item_id(&70_000).unwrap();
+            assert_eq!(Some(300), shard.find_attributes(&item).unwrap().canonical_item_id);
+        }
+
+        // No neighbor session of [4] contains item 300, which still represents the group.
+        let (k, m) = (10, 10);
+        let evolving_session = [4];
+        let shard_neighbors = shards
+            .iter()
+            .map(|shard| find_shard_neighbors(shard, &evolving_session, &[], m))
+            .collect();
+        let mut neighborhood = MergedNeighborhood::new(shard_neighbors, k, m, index.weighting());
+        neighborhood.set_product_groups(product_groups);
+
+        let expected = vmisknn::predict(&index, &evolving_session, k, m, 10, true).into_sorted_vec();
+        let actual = vmisknn::predict(&neighborhood, &evolving_session, k, m, 10, true).into_sorted_vec();
+        let expected_items = expected.iter().map(|scored| scored.id).sorted().collect_vec();
+        let actual_items = actual.iter().map(|scored| scored.id).sorted().collect_vec();
+        assert!(expected_items.contains(&300) && !expected_items.contains(&70_000));
+        assert_eq!(expected_items, actual_items);
+    }
+
+    fn neighbor_session(session_id: u32, time: u32, score: f64) -> NeighborSession {
+        NeighborSession {
+            session_id,
+            time,
+            score,
+            item_ids: vec![1, session_id as u64 + 100],
+            item_weights: Vec::new(),
+        }
+    }
+
+    #[test]
+    fn should_merge_the_most_recent_and_then_the_most_similar_sessions() {
+        let items = |session_ids: &[u64]| {
+            session_ids
+                .iter()
+                .map(|session_id| NeighborItem {
+                    item_id: session_id + 100,
+                    idf: 1.0,
+                    attributes: None,
+                })
+                .chain(std::iter::once(NeighborItem {
+                    item_id: 1,
+                    idf: 0.0,
+                    attributes: None,
+                }))
+                .collect()
+        };
+        let shard_neighbors = vec![
+            ShardNeighbors {
+                sessions: vec![neighbor_session(0, 10, 3.0), neighbor_session(2, 40, 1.0)],
+                items: items(&[0, 2]),
+            },
+            ShardNeighbors {
+                sessions: vec![neighbor_session(1, 30, 2.0), neighbor_session(3, 20, 0.5)],
+                items: items(&[1, 3]),
+            },
+        ];
+
+        // session 0 is the oldest, so it is dropped although it is the most similar
+        let neighborhood = MergedNeighborhood::new(shard_neighbors, 2, 3, Weighting::default());
+
+        let neighbors = neighborhood.find_neighbors(&[1], 2, 3).into_sorted_vec();
+        let session_items: Vec<Vec<u64>> = neighbors
+            .iter()
+            .map(|scored| {
+                neighborhood
+                    .items_for_session(&scored.id)
+                    .map(|item| neighborhood.external_item_id(&item))
+                    .collect()
+            })
+            .collect();
+        assert_eq!(vec![vec![1, 101], vec![1, 102]], session_items);
+        assert_eq!(0.0, neighborhood.idf(&neighborhood.internal_item_id(&1).unwrap()));
+    }
+}
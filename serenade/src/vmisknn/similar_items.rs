@@ -0,0 +1,130 @@
+use std::collections::BinaryHeap;
+use std::time::Instant;
+
+use hashbrown::HashMap;
+use rayon::prelude::*;
+
+use crate::vmisknn::similarity_indexed::{PostingLists, SimilarityComputationNew};
+use crate::vmisknn::weighting::SessionEvents;
+use crate::vmisknn::{select_top_items, InternalItemId, ItemScore, ITEM_SCORES};
+
+/// The items that co-occur with `item_id` in its `m` most recent training sessions, independent of any evolving
+/// session. Every co-occurrence adds the idf of the other item, weighted like the item scores of `predict` by the
+/// event types of the interaction and by the recency of the session. The item itself is not recommended and the
+/// business rules are applied relative to it.
+pub fn similar_items<I: SimilarityComputationNew + PostingLists>(
+    index: &I,
+    item_id: &u64,
+    m: usize,
+    how_many: usize,
+    enable_business_logic: bool,
+) -> BinaryHeap<ItemScore> {
+    let item = match index.internal_item_id(item_id) {
+        Some(item) => item,
+        None => return BinaryHeap::new(),
+    };
+    let weighting = index.weighting();
+
+    ITEM_SCORES.with(|item_scores| {
+        let mut item_scores = item_scores.borrow_mut();
+        item_scores.prepare(index.qty_items());
+
+        // The posting lists are ordered from the most recent session.
+        for session_id in index.posting_list(&item).take(m) {
+            let recency_weight = weighting.recency_weight(
+                index.session_to_max_time_stamp()[session_id as usize],
+                index.max_time_stamp(),
+            );
+            for (position, other_item) in index.items_for_session(&session_id).enumerate() {
+                let event_type_weight = index.session_item_weight(&session_id, position);
+                item_scores.add(other_item, recency_weight * event_type_weight * index.idf(&other_item));
+            }
+        }
+
+        select_top_items(
+            index,
+            &mut item_scores,
+            Some(item),
+            &SessionEvents::default(),
+            how_many,
+            enable_business_logic,
+        )
+    })
+}
+
+/// The similar items of every item of the index, computed in parallel so that they can be served with a lookup.
+pub fn precompute_similar_items<I: SimilarityComputationNew + PostingLists + Sync>(
+    index: &I,
+    m: usize,
+    how_many: usize,
+    enable_business_logic: bool,
+) -> HashMap<u64, Vec<ItemScore>> {
+    let start_time = Instant::now();
+    let item_to_similar_items: HashMap<u64, Vec<ItemScore>> = (0..index.qty_items() as InternalItemId)
+        .into_par_iter()
+        .filter(|item| index.posting_list(item).next().is_some())
+        .map(|item| {
+            let item_id = index.external_item_id(&item);
+            let similar = similar_items(index, &item_id, m, how_many, enable_business_logic).into_sorted_vec();
+            (item_id, similar)
+        })
+        .collect();
+    println!(
+        "similar items: {} items in {} secs",
+        item_to_similar_items.len(),
+        start_time.elapsed().as_secs()
+    );
+    item_to_similar_items
+}
+
+#[cfg(test)]
+mod similar_items_test {
+    use crate::vmisknn::offline_index::OfflineIndex;
+
+    use super::*;
+
+    fn create_index() -> OfflineIndex {
+        let historical_sessions = vec![vec![1, 2, 3], vec![1, 2], vec![1, 3, 4], vec![2, 4], vec![1, 2, 5]];
+        let max_time_stamps = vec![10, 20, 30, 40, 50];
+        OfflineIndex::new_for_test(historical_sessions, max_time_stamps, 10)
+    }
+
+    fn similar_item_ids(index: &OfflineIndex, item_id: u64, m: usize) -> Vec<u64> {
+        similar_items(index, &item_id, m, 10, false)
+            .into_sorted_vec()
+            .iter()
+            .map(|scored| scored.id)
+            .collect()
+    }
+
+    #[test]
+    fn should_rank_the_co_occurring_items() {
+        let index = create_index();
+        let similar = similar_items(&index, &1, 10, 10, false).into_sorted_vec();
+        // Item 1 co-occurs with item 2 in three sessions, with item 3 in two and with items 4 and 5 in one session.
+        let idf = |item_id: u64| index.idf(&index.internal_item_id(&item_id).unwrap());
+        let mut expected: Vec<ItemScore> = vec![(2, 3.0), (3, 2.0), (4, 1.0), (5, 1.0)]
+            .into_iter()
+            .map(|(item_id, qty_sessions)| ItemScore::new(item_id, qty_sessions * idf(item_id)))
+            .collect();
+        expected.sort();
+        assert_eq!(expected, similar);
+
+        // Only the most recent session of item 1, [1, 2, 5].
+        assert_eq!(vec![5, 2], similar_item_ids(&index, 1, 1));
+        assert!(similar_item_ids(&index, 6, 10).is_empty());
+    }
+
+    #[test]
+    fn should_precompute_the_similar_items_of_every_item() {
+        let index = create_index();
+        let item_to_similar_items = precompute_similar_items(&index, 10, 2, false);
+        assert_eq!(5, item_to_similar_items.len());
+        for item_id in 1..=5 {
+            assert_eq!(
+                similar_items(&index, &item_id, 10, 2, false).into_sorted_vec(),
+                item_to_similar_items[&item_id]
+            );
+        }
+    }
+}
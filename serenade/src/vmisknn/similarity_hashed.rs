@@ -1,14 +1,18 @@
 extern crate hashbrown;
 
+use std::borrow::Cow;
+use std::cmp::Ordering;
 use std::collections::BinaryHeap;
 
-use hashbrown::hash_map::DefaultHashBuilder as RandomState;
-use hashbrown::HashSet;
+use hashbrown::HashMap;
 
-use crate::vmisknn::SessionScore;
+use crate::vmisknn::weighting::{SessionEvents, Weighting};
+use crate::vmisknn::{ItemScore, SessionScore};
 
+/// The indexes of the runtime comparisons, which are keyed by the external item ids of the training data.
 pub trait SimilarityComputationHash {
-    fn items_for_session(&self, session: &u32) -> &HashSet<u64, RandomState>;
+    /// The items of a training session in ascending order.
+    fn items_for_session(&self, session: &u32) -> Cow<'_, [u64]>;
 
     fn idf(&self, item_id: &u64) -> f64;
 
@@ -24,6 +28,71 @@ pub(crate) fn idf(num_sessions_total: usize, num_session_with_item: usize) -> f6
     (num_sessions_total as f64 / num_session_with_item as f64).ln()
 }
 
+/// The idf of every item of the deduplicated training sessions, relative to all their items like the idf of the
+/// `OfflineIndex`.
+pub(crate) fn idfs_of_sessions(historical_sessions: &[Vec<u64>]) -> HashMap<u64, f64> {
+    let mut item_to_qty_sessions: HashMap<u64, usize> = HashMap::new();
+    for session in historical_sessions.iter() {
+        for item_id in session.iter() {
+            *item_to_qty_sessions.entry(*item_id).or_default() += 1;
+        }
+    }
+    let qty_items_total: usize = historical_sessions.iter().map(|session| session.len()).sum();
+    item_to_qty_sessions
+        .into_iter()
+        .map(|(item_id, qty_sessions)| (item_id, idf(qty_items_total, qty_sessions)))
+        .collect()
+}
+
+/// Scores the items of the neighbors like `vmisknn::predict_with_events` with the default weighting, for the
+/// indexes without the dense item ids of `SimilarityComputationNew`. The event types only exclude items, because
+/// these indexes don't weight the evolving session items.
+pub fn predict<I: SimilarityComputationHash>(
+    index: &I,
+    evolving_session: &[u64],
+    session_events: &SessionEvents,
+    k: usize,
+    m: usize,
+    how_many: usize,
+) -> Vec<ItemScore> {
+    let neighbors = index.find_neighbors(evolving_session, k, m);
+    let weighting = Weighting::default();
+
+    let mut item_scores: HashMap<u64, f64> = HashMap::new();
+    for scored_session in neighbors.into_sorted_vec() {
+        let training_item_ids = index.items_for_session(&scored_session.id);
+        let first_match_pos = evolving_session
+            .iter()
+            .rev()
+            .position(|item_id| training_item_ids.binary_search(item_id).is_ok())
+            .unwrap()
+            + 1;
+        let session_weight = weighting.match_weight(first_match_pos);
+        for item_id in training_item_ids.iter() {
+            *item_scores.entry(*item_id).or_default() += session_weight * index.idf(item_id) * scored_session.score;
+        }
+    }
+
+    if let Some(most_recent_item) = evolving_session.last() {
+        item_scores.remove(most_recent_item);
+    }
+    for excluded_item in session_events.excluded_items.iter() {
+        item_scores.remove(excluded_item);
+    }
+
+    let mut recommendations: Vec<ItemScore> = item_scores
+        .into_iter()
+        .map(|(item_id, score)| ItemScore::new(item_id, score))
+        .collect();
+    recommendations.sort_unstable_by(|a, b| {
+        b.score
+            .partial_cmp(&a.score)
+            .unwrap_or(Ordering::Equal)
+            .then(a.id.cmp(&b.id))
+    });
+    recommendations.truncate(how_many);
+    recommendations
+}
 
 #[cfg(test)]
 mod sessiontime_test {
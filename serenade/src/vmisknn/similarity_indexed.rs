@@ -1,14 +1,24 @@
 extern crate hashbrown;
 
 use crate::vmisknn::offline_index::ProductAttributes;
-use crate::vmisknn::SessionScore;
+use crate::vmisknn::product_groups::ProductGroups;
+use crate::vmisknn::weighting::Weighting;
+use crate::vmisknn::{InternalItemId, SessionScore};
 use std::collections::BinaryHeap;
 
 
+/// An index that refers to items with dense internal ids in `0..qty_items()` and only translates the external
+/// item ids of the evolving session and of the recommendations.
 pub trait SimilarityComputationNew {
-    fn items_for_session(&self, session_idx: &u32) -> &[u64];
+    /// The iterator over the items of a training session, which decodes them if the index stores them compressed.
+    type SessionItems<'a>: Iterator<Item = InternalItemId>
+    where
+        Self: 'a;
 
-    fn idf(&self, item_id: &u64) -> f64;
+    /// The items of a training session in ascending order.
+    fn items_for_session(&self, session_idx: &u32) -> Self::SessionItems<'_>;
+
+    fn idf(&self, item_idx: &InternalItemId) -> f64;
 
     /// find neighboring sessions for the given evolving_session.
     /// param m select the 'm' most recent historical sessions
@@ -18,7 +28,57 @@ pub trait SimilarityComputationNew {
         evolving_session: &[u64],
         k: usize,
         m: usize,
+    ) -> BinaryHeap<SessionScore> {
+        let item_weights = vec![1.0; evolving_session.len()];
+        self.find_weighted_neighbors(evolving_session, &item_weights, k, m)
+    }
+
+    /// Like `find_neighbors`, with the contribution of each evolving session item multiplied by its weight in
+    /// `item_weights`, for example the weight of its event type.
+    fn find_weighted_neighbors(
+        &self,
+        evolving_session: &[u64],
+        item_weights: &[f64],
+        k: usize,
+        m: usize,
     ) -> BinaryHeap<SessionScore>;
 
-    fn find_attributes(&self, item_id: &u64) -> Option<&ProductAttributes>;
+    /// The weight of the item at `position` in the items of a training session, by the event types of its
+    /// interactions. Indexes without event types weight all items equally.
+    fn session_item_weight(&self, _session_idx: &u32, _position: usize) -> f64 {
+        1.0
+    }
+
+    fn find_attributes(&self, item_idx: &InternalItemId) -> Option<&ProductAttributes>;
+
+    fn qty_items(&self) -> usize;
+
+    /// The internal id of an external item id, `None` for items that are not in the index.
+    fn internal_item_id(&self, item_id: &u64) -> Option<InternalItemId>;
+
+    fn external_item_id(&self, item_idx: &InternalItemId) -> u64;
+
+    /// The position weighting of the evolving session items, for the neighbor similarity and the item scores.
+    fn weighting(&self) -> Weighting;
+
+    /// How many items of a product group are recommended. Indexes without product groups don't limit them.
+    fn product_groups(&self) -> ProductGroups {
+        ProductGroups::default()
+    }
+}
+
+/// An index with the posting lists of the `m` most recent training sessions per item, for the scorings that walk
+/// them directly instead of searching the neighbors of an evolving session.
+pub trait PostingLists {
+    type PostingList<'a>: Iterator<Item = u32>
+    where
+        Self: 'a;
+
+    /// The training sessions of an item, the most recent session first.
+    fn posting_list(&self, item_idx: &InternalItemId) -> Self::PostingList<'_>;
+
+    fn session_to_max_time_stamp(&self) -> &[u32];
+
+    /// The most recent session, the reference time of the recency weighting.
+    fn max_time_stamp(&self) -> u32;
 }
@@ -0,0 +1,255 @@
+use std::collections::HashMap;
+use std::error::Error;
+use std::fs::File;
+use std::io::{BufReader, BufWriter};
+use std::time::Instant;
+
+use rayon::prelude::*;
+use serde::{Deserialize, Serialize};
+
+use crate::recommender::RecommendParams;
+use crate::vmisknn;
+use crate::vmisknn::offline_index::OfflineIndex;
+use crate::vmisknn::similarity_indexed::SimilarityComputationNew;
+use crate::vmisknn::{InternalItemId, ItemScore};
+
+/// The file of the table in the index directory, next to `itemindex/` and `sessionindex/`.
+pub const SINGLE_ITEM_TABLE_FILE_NAME: &str = "singleitemtable.bin";
+
+/// The recommendations of every item of an index for the sessions that only consist of that item, which only
+/// depend on the item and the index. Built with `predict` and the parameters of the server, so that one-item
+/// sessions are served with a lookup.
+#[derive(Debug, PartialEq, Serialize, Deserialize)]
+pub struct SingleItemTable {
+    params: RecommendParams,
+    /// Identifies the index and its weightings that the table was built for.
+    index_fingerprint: u128,
+    item_to_recommendations: HashMap<u64, Vec<(u64, f64)>>,
+}
+
+impl SingleItemTable {
+    /// Predicts the recommendations of all items in parallel.
+    pub fn build(index: &OfflineIndex, params: &RecommendParams) -> Self {
+        let start_time = Instant::now();
+        let item_to_recommendations: HashMap<u64, Vec<(u64, f64)>> = (0..index.qty_items() as InternalItemId)
+            .into_par_iter()
+            .filter(|item| !index.item_to_top_sessions_ordered[*item as usize].is_empty())
+            .map(|item| {
+                let item_id = index.external_item_id(&item);
+                let recommendations = vmisknn::predict(
+                    index,
+                    &[item_id],
+                    params.neighborhood_size_k,
+                    params.m_most_recent_sessions,
+                    params.how_many,
+                    params.enable_business_logic,
+                )
+                .into_sorted_vec()
+                .into_iter()
+                .map(|scored| (scored.id, scored.score))
+                .collect();
+                (item_id, recommendations)
+            })
+            .collect();
+        println!(
+            "single item table: {} items in {} secs",
+            item_to_recommendations.len(),
+            start_time.elapsed().as_secs()
+        );
+        SingleItemTable {
+            params: *params,
+            index_fingerprint: index_fingerprint(index, params.enable_business_logic),
+            item_to_recommendations,
+        }
+    }
+
+    /// Reads the table from `path` if it was built for this index and these parameters, otherwise builds it and
+    /// writes it to `path`.
+    pub fn read_or_build(index: &OfflineIndex, params: &RecommendParams, path: &str) -> Self {
+        match SingleItemTable::read_from_file(path) {
+            Ok(table) if table.is_built_for(index, params) => {
+                println!("read single item table of {} items from {}", table.len(), path);
+                return table;
+            }
+            Ok(_) => println!("rebuilding single item table {}, the index or the parameters changed", path),
+            Err(_) => println!("building single item table {}", path),
+        }
+        let table = SingleItemTable::build(index, params);
+        if let Err(error) = table.write_to_file(path) {
+            println!("could not write single item table {}: {}", path, error);
+        }
+        table
+    }
+
+    /// Whether the table was built for this index with its current weightings and these parameters, otherwise its
+    /// recommendations differ from `predict`.
+    pub fn is_built_for(&self, index: &OfflineIndex, params: &RecommendParams) -> bool {
+        self.index_fingerprint == index_fingerprint(index, params.enable_business_logic) && self.params == *params
+    }
+
+    /// The recommendations of the session that only consists of `item_id`, the best item first. Their order is the
+    /// order of `predict`, the scores are those of an unweighted session.
+    pub fn recommend(&self, item_id: &u64) -> Option<Vec<ItemScore>> {
+        self.item_to_recommendations.get(item_id).map(|recommendations| {
+            recommendations
+                .iter()
+                .map(|(id, score)| ItemScore::new(*id, *score))
+                .collect()
+        })
+    }
+
+    pub fn len(&self) -> usize {
+        self.item_to_recommendations.len()
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.item_to_recommendations.is_empty()
+    }
+
+    pub fn write_to_file(&self, path: &str) -> Result<(), Box<dyn Error>> {
+        bincode::serialize_into(BufWriter::new(File::create(path)?), self)?;
+        Ok(())
+    }
+
+    pub fn read_from_file(path: &str) -> Result<Self, Box<dyn Error>> {
+        Ok(bincode::deserialize_from(BufReader::new(File::open(path)?))?)
+    }
+}
+
+/// An md5 digest of everything that the recommendations of an index depend on: the items, the posting lists, the
+/// sessions, the weightings, the product groups and, for the business rules, the item attributes. Unlike
+/// `DefaultHasher`, md5 of the little endian bytes doesn't change between Rust releases or platforms, so tables
+/// written by an older build are still recognized.
+fn index_fingerprint(index: &OfflineIndex, enable_business_logic: bool) -> u128 {
+    let mut fingerprint = Fingerprint(md5::Context::new());
+    fingerprint.add_u64s(&index.item_ids);
+    for session_ids in index.item_to_top_sessions_ordered.iter() {
+        fingerprint.add_u32s(session_ids);
+    }
+    for session_items in index.session_to_items_sorted.iter() {
+        fingerprint.add_u32s(session_items);
+    }
+    for event_types in index.session_to_item_event_types.iter() {
+        fingerprint.add_bytes(event_types);
+    }
+    fingerprint.add_u32s(&index.session_to_max_time_stamp);
+    fingerprint.add_u32s(&[index.max_time_stamp]);
+    fingerprint.add_bytes(format!("{:?}", index.weighting).as_bytes());
+    fingerprint.add_f64s(&index.item_to_idf_score);
+    fingerprint.add_f64s(&index.event_types_to_weight);
+    fingerprint.add_bytes(format!("{:?}", index.product_groups).as_bytes());
+    fingerprint.add_bytes(&[enable_business_logic as u8]);
+    for attributes in index.item_to_product_attributes.iter() {
+        match attributes {
+            Some(attributes) => {
+                fingerprint.add_bytes(&[attributes.is_adult as u8, attributes.is_for_sale as u8]);
+                fingerprint.add_option(attributes.category);
+                fingerprint.add_option(attributes.brand);
+                fingerprint.add_option(attributes.group);
+                fingerprint.add_option(attributes.canonical_item_id);
+            }
+            None => fingerprint.add_bytes(&[]),
+        }
+    }
+    u128::from_le_bytes(fingerprint.0.compute().0)
+}
+
+/// Feeds values to an md5 context, each slice prefixed with its length so that the boundaries of the posting lists
+/// and the sessions are part of the digest.
+struct Fingerprint(md5::Context);
+
+impl Fingerprint {
+    fn add_bytes(&mut self, bytes: &[u8]) {
+        self.0.consume((bytes.len() as u64).to_le_bytes());
+        self.0.consume(bytes);
+    }
+
+    fn add_u32s(&mut self, values: &[u32]) {
+        let bytes: Vec<u8> = values.iter().flat_map(|value| value.to_le_bytes()).collect();
+        self.add_bytes(&bytes);
+    }
+
+    fn add_u64s(&mut self, values: &[u64]) {
+        let bytes: Vec<u8> = values.iter().flat_map(|value| value.to_le_bytes()).collect();
+        self.add_bytes(&bytes);
+    }
+
+    fn add_f64s(&mut self, values: &[f64]) {
+        let bytes: Vec<u8> = values.iter().flat_map(|value| value.to_bits().to_le_bytes()).collect();
+        self.add_bytes(&bytes);
+    }
+
+    fn add_option(&mut self, value: Option<u64>) {
+        match value {
+            Some(value) => self.add_u64s(&[value]),
+            None => self.add_bytes(&[]),
+        }
+    }
+}
+
+#[cfg(test)]
+mod single_item_table_test {
+    use crate::vmisknn::weighting::ItemWeighting;
+
+    use super::*;
+
+    fn create_index() -> OfflineIndex {
+        let historical_sessions = vec![vec![1, 2, 3], vec![2, 3], vec![1, 3, 4], vec![2, 4], vec![5]];
+        let max_time_stamps = vec![10, 20, 30, 40, 50];
+        OfflineIndex::new_for_test(historical_sessions, max_time_stamps, 10)
+    }
+
+    fn params() -> RecommendParams {
+        RecommendParams {
+            neighborhood_size_k: 10,
+            m_most_recent_sessions: 10,
+            how_many: 2,
+            enable_business_logic: false,
+        }
+    }
+
+    #[test]
+    fn should_recommend_like_predict() {
+        let index = create_index();
+        let table = SingleItemTable::build(&index, &params());
+        assert_eq!(5, table.len());
+        assert!(table.is_built_for(&index, &params()));
+        assert!(!table.is_built_for(&index, &RecommendParams { how_many: 3, ..params() }));
+        let business_logic_params = RecommendParams {
+            enable_business_logic: true,
+            ..params()
+        };
+        assert!(!table.is_built_for(&index, &business_logic_params));
+        let mut reweighted_index = create_index();
+        reweighted_index.set_item_weighting(ItemWeighting::None);
+        assert!(!table.is_built_for(&reweighted_index, &params()));
+        let mut resessioned_index = create_index();
+        resessioned_index.session_to_items_sorted[0].pop();
+        assert!(!table.is_built_for(&resessioned_index, &params()));
+        let mut adult_index = create_index();
+        adult_index.item_to_product_attributes[0].as_mut().unwrap().is_adult = true;
+        assert!(!table.is_built_for(&adult_index, &params()));
+        let mut regrouped_index = create_index();
+        regrouped_index.read_item_attributes_for_test("item_id,group_id\n1,7\n2,7\n");
+        assert!(!table.is_built_for(&regrouped_index, &params()));
+
+        for item_id in 1..=5 {
+            let expected = vmisknn::predict(&index, &[item_id], 10, 10, 2, false).into_sorted_vec();
+            assert_eq!(Some(expected), table.recommend(&item_id));
+        }
+        assert_eq!(None, table.recommend(&6));
+    }
+
+    #[test]
+    fn should_write_and_read_the_table() {
+        let table = SingleItemTable::build(&create_index(), &params());
+        let path = std::env::temp_dir()
+            .join(format!("serenade_single_item_table_{}.bin", std::process::id()))
+            .display()
+            .to_string();
+        table.write_to_file(&path).unwrap();
+        let read_table = SingleItemTable::read_from_file(&path).unwrap();
+        std::fs::remove_file(&path).unwrap();
+        assert_eq!(table, read_table);
+    }
+}
@@ -0,0 +1,333 @@
+use std::cmp::Ordering;
+use std::collections::BinaryHeap;
+use std::sync::Arc;
+
+use hashbrown::{HashMap, HashSet};
+
+use crate::io::{read_training_sessions_ordered, InputSchema};
+use crate::recommender::{RecommendParams, Recommender, RecommenderKind};
+use crate::vmisknn::offline_index::{OfflineIndex, ProductAttributes};
+use crate::vmisknn::similarity_indexed::SimilarityComputationNew;
+use crate::vmisknn::weighting::{SessionEvents, Weighting};
+use crate::vmisknn::{select_top_items, InternalItemId, ItemScore, SessionScore, ITEM_SCORES};
+
+/// The decay parameters of STAN, the sequence and time aware neighborhood (Garg et al., 2019).
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub struct StanParameters {
+    /// λ1: the decay of the evolving session items by their distance to the most recent item.
+    pub lambda_spw: f64,
+    /// λ2: the decay of the neighbor sessions by their age relative to the most recent training session, in seconds.
+    pub lambda_snh_secs: f64,
+    /// λ3: the decay of the items of a neighbor session by their distance to the item that it shares with the
+    /// evolving session.
+    pub lambda_inh: f64,
+}
+
+impl Default for StanParameters {
+    fn default() -> Self {
+        StanParameters {
+            lambda_spw: 1.0,
+            lambda_snh_secs: 604_800.0,
+            lambda_inh: 1.0,
+        }
+    }
+}
+
+/// An `OfflineIndex` with the position of every item in the time order of its training session, which STAN needs
+/// in addition to the deduplicated and sorted session items.
+pub struct StanIndex {
+    index: Arc<OfflineIndex>,
+    /// Aligned with `session_to_items_sorted`, the position of the last interaction with the item among the
+    /// distinct items of the session.
+    session_to_item_positions: Vec<Vec<u32>>,
+    parameters: StanParameters,
+}
+
+impl StanIndex {
+    /// `ordered_sessions` are the items of the training sessions of the index ordered by time, in the order of the
+    /// sessions of the index.
+    pub fn new(index: Arc<OfflineIndex>, ordered_sessions: &[Vec<u64>], parameters: StanParameters) -> Self {
+        assert_eq!(
+            index.session_to_items_sorted.len(),
+            ordered_sessions.len(),
+            "The ordered sessions don't match the sessions of the index"
+        );
+        let session_to_item_positions = index
+            .session_to_items_sorted
+            .iter()
+            .zip(ordered_sessions.iter())
+            .map(|(items, ordered_items)| {
+                let mut item_to_last_interaction: HashMap<u64, usize> = HashMap::new();
+                for (interaction, item_id) in ordered_items.iter().enumerate() {
+                    item_to_last_interaction.insert(*item_id, interaction);
+                }
+                let mut distinct_items: Vec<(usize, u64)> = item_to_last_interaction
+                    .into_iter()
+                    .map(|(item_id, interaction)| (interaction, item_id))
+                    .collect();
+                distinct_items.sort_unstable();
+                let item_to_position: HashMap<u64, u32> = distinct_items
+                    .into_iter()
+                    .enumerate()
+                    .map(|(position, (_, item_id))| (item_id, position as u32))
+                    .collect();
+                items
+                    .iter()
+                    .map(|item| item_to_position[&index.external_item_id(item)])
+                    .collect()
+            })
+            .collect();
+        StanIndex {
+            index,
+            session_to_item_positions,
+            parameters,
+        }
+    }
+
+    /// Shares the `index` that was built from the csv training data, e.g. the index of the server.
+    pub fn new_from_csv(
+        path_to_training: &str,
+        input_schema: &InputSchema,
+        index: Arc<OfflineIndex>,
+        parameters: StanParameters,
+    ) -> Self {
+        // The ordered sessions are read with the same session order and item dictionary as the index.
+        let ordered_sessions =
+            read_training_sessions_ordered(path_to_training, input_schema, index.item_dictionary());
+        StanIndex::new(index, &ordered_sessions, parameters)
+    }
+
+    pub fn index(&self) -> &OfflineIndex {
+        &self.index
+    }
+
+    pub fn parameters(&self) -> StanParameters {
+        self.parameters
+    }
+
+    /// The position of an item in the time order of a training session, `None` if the session lacks the item.
+    fn item_position(&self, session: &u32, item: &InternalItemId) -> Option<u32> {
+        self.index.session_to_items_sorted[*session as usize]
+            .binary_search(item)
+            .ok()
+            .map(|index| self.session_to_item_positions[*session as usize][index])
+    }
+}
+
+impl SimilarityComputationNew for StanIndex {
+    type SessionItems<'a> = <OfflineIndex as SimilarityComputationNew>::SessionItems<'a>;
+
+    fn items_for_session(&self, session: &u32) -> Self::SessionItems<'_> {
+        self.index.items_for_session(session)
+    }
+
+    fn idf(&self, item: &InternalItemId) -> f64 {
+        self.index.idf(item)
+    }
+
+    /// The similarity of STAN: the evolving session items decay by their distance to the most recent item (λ1),
+    /// normalized by the lengths of both sessions, and the neighbor sessions decay by their age (λ2).
+    /// The `m` most recent sessions that share an item with the evolving session are candidates.
+    fn find_weighted_neighbors(
+        &self,
+        evolving_session: &[u64],
+        item_weights: &[f64],
+        k: usize,
+        m: usize,
+    ) -> BinaryHeap<SessionScore> {
+        let mut session_similarities: HashMap<u32, f64> = HashMap::new();
+        let mut seen_items = HashSet::with_capacity(evolving_session.len());
+        // Only the most recent occurrence of a duplicate item counts.
+        for (distance, (item_id, item_weight)) in evolving_session.iter().zip(item_weights.iter()).rev().enumerate() {
+            if !seen_items.insert(*item_id) {
+                continue;
+            }
+            if let Some(item) = self.index.internal_item_id(item_id) {
+                let position_weight = item_weight * (-(distance as f64) / self.parameters.lambda_spw).exp();
+                for session_id in self.index.item_to_top_sessions_ordered[item as usize].iter() {
+                    *session_similarities.entry(*session_id).or_default() += position_weight;
+                }
+            }
+        }
+        let qty_unique_session_items = seen_items.len() as f64;
+
+        let session_to_max_time_stamp = &self.index.session_to_max_time_stamp;
+        let mut candidates: Vec<(u32, f64)> = session_similarities.into_iter().collect();
+        candidates.sort_unstable_by(|(left, _), (right, _)| {
+            session_to_max_time_stamp[*right as usize]
+                .cmp(&session_to_max_time_stamp[*left as usize])
+                .then(left.cmp(right))
+        });
+        candidates.truncate(m);
+
+        let mut neighbors: Vec<SessionScore> = candidates
+            .into_iter()
+            .map(|(session_id, similarity)| {
+                let qty_session_items = self.index.session_to_items_sorted[session_id as usize].len() as f64;
+                let age = self.index.max_time_stamp - session_to_max_time_stamp[session_id as usize];
+                let recency_weight = (-(age as f64) / self.parameters.lambda_snh_secs).exp();
+                let score = similarity / (qty_unique_session_items * qty_session_items).sqrt() * recency_weight;
+                SessionScore::new(session_id, score)
+            })
+            .collect();
+        // The most recent neighbors and then the smaller session ids win ties, like in VMIS-kNN.
+        neighbors.sort_by(|left, right| right.score.partial_cmp(&left.score).unwrap_or(Ordering::Equal));
+        neighbors.truncate(k);
+        neighbors.into_iter().collect()
+    }
+
+    fn find_attributes(&self, item: &InternalItemId) -> Option<&ProductAttributes> {
+        self.index.find_attributes(item)
+    }
+
+    fn qty_items(&self) -> usize {
+        self.index.qty_items()
+    }
+
+    fn internal_item_id(&self, item_id: &u64) -> Option<InternalItemId> {
+        self.index.internal_item_id(item_id)
+    }
+
+    fn external_item_id(&self, item: &InternalItemId) -> u64 {
+        self.index.external_item_id(item)
+    }
+
+    fn weighting(&self) -> Weighting {
+        self.index.weighting()
+    }
+
+    fn session_item_weight(&self, session: &u32, position: usize) -> f64 {
+        self.index.session_item_weight(session, position)
+    }
+}
+
+/// Recommends the items of the STAN neighbors: the item score of a neighbor is its similarity, decayed by the
+/// distance of the item to the most recent item of the evolving session that the neighbor contains (λ3).
+pub fn predict(
+    index: &StanIndex,
+    evolving_session: &[u64],
+    session_events: &SessionEvents,
+    k: usize,
+    m: usize,
+    how_many: usize,
+    enable_business_logic: bool,
+) -> BinaryHeap<ItemScore> {
+    let neighbors = index.find_weighted_neighbors(evolving_session, &session_events.item_weights, k, m);
+
+    let evolving_session_internal: Vec<Option<InternalItemId>> = evolving_session
+        .iter()
+        .map(|item_id| index.internal_item_id(item_id))
+        .collect();
+
+    ITEM_SCORES.with(|item_scores| {
+        let mut item_scores = item_scores.borrow_mut();
+        item_scores.prepare(index.qty_items());
+
+        for scored_session in neighbors.into_sorted_vec() {
+            let shared_item_position = evolving_session_internal
+                .iter()
+                .rev()
+                .find_map(|item| item.and_then(|item| index.item_position(&scored_session.id, &item)))
+                .unwrap();
+            let training_item_ids = index.items_for_session(&scored_session.id);
+            let item_positions = &index.session_to_item_positions[scored_session.id as usize];
+            for (position, (item, item_position)) in training_item_ids.zip(item_positions.iter()).enumerate() {
+                let distance = (*item_position as f64 - shared_item_position as f64).abs();
+                let item_weight = (-distance / index.parameters.lambda_inh).exp();
+                let event_type_weight = index.session_item_weight(&scored_session.id, position);
+                item_scores.add(item, event_type_weight * item_weight * scored_session.score);
+            }
+        }
+
+        let most_recent_item = *evolving_session_internal.last().unwrap();
+        select_top_items(
+            index,
+            &mut item_scores,
+            most_recent_item,
+            session_events,
+            how_many,
+            enable_business_logic,
+        )
+    })
+}
+
+impl Recommender for StanIndex {
+    fn recommend_with_events(
+        &self,
+        evolving_session: &[u64],
+        session_events: &SessionEvents,
+        params: &RecommendParams,
+    ) -> Vec<ItemScore> {
+        predict(
+            self,
+            evolving_session,
+            session_events,
+            params.neighborhood_size_k,
+            params.m_most_recent_sessions,
+            params.how_many,
+            params.enable_business_logic,
+        )
+        .into_sorted_vec()
+    }
+
+    fn kind(&self) -> RecommenderKind {
+        RecommenderKind::Stan
+    }
+}
+
+#[cfg(test)]
+mod stan_index_test {
+    use super::*;
+
+    fn create_index(parameters: StanParameters) -> StanIndex {
+        // Sessions with their items ordered by time.
+        let ordered_sessions = vec![vec![3, 1, 2], vec![1, 4, 5, 6], vec![2, 1, 7], vec![1, 8]];
+        let sorted_sessions = ordered_sessions
+            .iter()
+            .map(|session| {
+                let mut items = session.clone();
+                items.sort_unstable();
+                items
+            })
+            .collect();
+        let index = OfflineIndex::new_for_test(sorted_sessions, vec![100, 200, 300, 400], 10);
+        StanIndex::new(Arc::new(index), &ordered_sessions, parameters)
+    }
+
+    #[test]
+    fn should_store_the_time_order_of_the_session_items() {
+        let index = create_index(StanParameters::default());
+        let item = |item_id| index.internal_item_id(&item_id).unwrap();
+        assert_eq!(Some(0), index.item_position(&0, &item(3)));
+        assert_eq!(Some(2), index.item_position(&0, &item(2)));
+        assert_eq!(Some(3), index.item_position(&1, &item(6)));
+        assert_eq!(None, index.item_position(&1, &item(2)));
+    }
+
+    #[test]
+    fn should_prefer_recent_neighbors_and_items_close_to_the_shared_item() {
+        let parameters = StanParameters {
+            lambda_spw: 1.0,
+            lambda_snh_secs: 100.0,
+            lambda_inh: 1.0,
+        };
+        let index = create_index(parameters);
+
+        let neighbors = index.find_neighbors(&[1], 10, 10).into_sorted_vec();
+        let neighbor_ids: Vec<u32> = neighbors.iter().map(|scored| scored.id).collect();
+        // Every session contains item 1, the shorter and more recent sessions are more similar.
+        assert_eq!(vec![3, 2, 1, 0], neighbor_ids);
+        let expected_score = 1.0 / 2_f64.sqrt();
+        assert!((neighbors[0].score - expected_score).abs() < 1e-9);
+
+        let recommended: Vec<u64> = predict(&index, &[1], &SessionEvents::unweighted(&[1]), 10, 10, 10, false)
+            .into_sorted_vec()
+            .iter()
+            .map(|scored| scored.id)
+            .collect();
+        // Item 6 is three positions away from item 1 in an old session.
+        assert_eq!(8, recommended[0]);
+        assert_eq!(6, *recommended.last().unwrap());
+        assert!(!recommended.contains(&1));
+    }
+}
@@ -1,28 +1,29 @@
+use std::borrow::Cow;
 use std::collections::BinaryHeap;
 
-use hashbrown::hash_map::DefaultHashBuilder;
 use hashbrown::HashMap;
-use hashbrown::HashSet;
 
-use crate::vmisknn::{SessionScore, SessionTime};
+use crate::io::{read_training_sessions, InputSchema};
+use crate::vmisknn::{ItemScore, SessionScore, SessionTime};
 use crate::vmisknn::vmisknn_index::prepare_hashmap;
-use crate::vmisknn::similarity_hashed::SimilarityComputationHash;
-use crate::vmisknn::vmisknn_index::read_from_file;
+use crate::recommender::{RecommendParams, Recommender, RecommenderKind};
+use crate::vmisknn::similarity_hashed;
+use crate::vmisknn::similarity_hashed::{idfs_of_sessions, SimilarityComputationHash};
+use crate::vmisknn::weighting::SessionEvents;
 
 pub struct VMISSkNNIndexNoOpt {
     sessions_for_item: HashMap<u64, Vec<u32>>,
     historical_sessions_max_time_stamp: Vec<u32>,
+    /// The items of every training session in ascending order.
+    session_items: Vec<Vec<u64>>,
+    item_idfs: HashMap<u64, f64>,
 }
 
 impl VMISSkNNIndexNoOpt {
-    pub fn new(path_train: &str, n_most_recent_sessions: usize) -> Self {
+    pub fn new(path_train: &str, input_schema: &InputSchema, n_most_recent_sessions: usize) -> Self {
         //println!("Reading inputs for tree index from {}...", path_train);
-        let data_train = read_from_file(path_train);
-        let (
-            historical_sessions_train,
-            _historical_sessions_id_train,
-            historical_sessions_max_time_stamp,
-        ) = data_train.unwrap();
+        let data_train = read_training_sessions(path_train, input_schema);
+        let (historical_sessions_train, historical_sessions_max_time_stamp) = data_train.unwrap();
 
         //println!("Creating index...");
         let historical_sessions = prepare_hashmap(
@@ -34,17 +35,19 @@ impl VMISSkNNIndexNoOpt {
         VMISSkNNIndexNoOpt {
             sessions_for_item: historical_sessions,
             historical_sessions_max_time_stamp,
+            item_idfs: idfs_of_sessions(&historical_sessions_train),
+            session_items: historical_sessions_train,
         }
     }
 }
 
 impl SimilarityComputationHash for VMISSkNNIndexNoOpt {
-    fn items_for_session(&self, _session: &u32) -> &HashSet<u64, DefaultHashBuilder> {
-        unimplemented!()
+    fn items_for_session(&self, session: &u32) -> Cow<'_, [u64]> {
+        Cow::Borrowed(&self.session_items[*session as usize])
     }
 
-    fn idf(&self, _item_id: &u64) -> f64 {
-        unimplemented!()
+    fn idf(&self, item_id: &u64) -> f64 {
+        self.item_idfs[item_id]
     }
 
     fn find_neighbors(
@@ -113,3 +116,25 @@ impl SimilarityComputationHash for VMISSkNNIndexNoOpt {
         closest_neighbors
     }
 }
+
+impl Recommender for VMISSkNNIndexNoOpt {
+    fn recommend_with_events(
+        &self,
+        evolving_session: &[u64],
+        session_events: &SessionEvents,
+        params: &RecommendParams,
+    ) -> Vec<ItemScore> {
+        similarity_hashed::predict(
+            self,
+            evolving_session,
+            session_events,
+            params.neighborhood_size_k,
+            params.m_most_recent_sessions,
+            params.how_many,
+        )
+    }
+
+    fn kind(&self) -> RecommenderKind {
+        RecommenderKind::VmisKnnNoOpt
+    }
+}
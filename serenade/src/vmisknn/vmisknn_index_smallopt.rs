@@ -1,28 +1,29 @@
+use std::borrow::Cow;
 use std::collections::BinaryHeap;
-use std::error::Error;
 
 use dary_heap::OctonaryHeap;
-use hashbrown::hash_map::DefaultHashBuilder;
 use hashbrown::HashMap;
-use hashbrown::HashSet;
 
-use crate::vmisknn::{SessionScore, SessionTime};
-use crate::vmisknn::similarity_hashed::SimilarityComputationHash;
+use crate::io::{read_training_sessions, InputSchema};
+use crate::vmisknn::{ItemScore, SessionScore, SessionTime};
+use crate::recommender::{RecommendParams, Recommender, RecommenderKind};
+use crate::vmisknn::similarity_hashed;
+use crate::vmisknn::similarity_hashed::{idfs_of_sessions, SimilarityComputationHash};
+use crate::vmisknn::weighting::SessionEvents;
 
 pub struct VMISSkNNIndexSmallOpt {
     sessions_for_item: HashMap<u64, Vec<u32>>,
     historical_sessions_max_time_stamp: Vec<u32>,
+    /// The items of every training session in ascending order.
+    session_items: Vec<Vec<u64>>,
+    item_idfs: HashMap<u64, f64>,
 }
 
 impl VMISSkNNIndexSmallOpt {
-    pub fn new(path_train: &str, n_most_recent_sessions: usize) -> Self {
+    pub fn new(path_train: &str, input_schema: &InputSchema, n_most_recent_sessions: usize) -> Self {
         //println!("Reading inputs for tree index from {}...", path_train);
-        let data_train = read_from_file(path_train);
-        let (
-            historical_sessions_train,
-            _historical_sessions_id_train,
-            historical_sessions_max_time_stamp,
-        ) = data_train.unwrap();
+        let data_train = read_training_sessions(path_train, input_schema);
+        let (historical_sessions_train, historical_sessions_max_time_stamp) = data_train.unwrap();
 
         //println!("Creating index...");
         let historical_sessions = prepare_hashmap(
@@ -34,101 +35,12 @@ impl VMISSkNNIndexSmallOpt {
         VMISSkNNIndexSmallOpt {
             sessions_for_item: historical_sessions,
             historical_sessions_max_time_stamp,
+            item_idfs: idfs_of_sessions(&historical_sessions_train),
+            session_items: historical_sessions_train,
         }
     }
 }
 
-pub fn read_from_file(
-    path: &str,
-) -> Result<(Vec<Vec<u64>>, Vec<Vec<usize>>, Vec<u32>), Box<dyn Error>> {
-    // Creates a new csv `Reader` from a file
-    let mut reader = csv::ReaderBuilder::new()
-        .delimiter(b'\t')
-        .has_headers(true)
-        .from_path(path)?;
-
-    // Vector initialization
-    let mut session_id: Vec<usize> = Vec::new();
-    let mut item_id: Vec<usize> = Vec::new();
-    let mut time: Vec<usize> = Vec::new();
-
-    reader.deserialize().for_each(|result| {
-        if result.is_ok() {
-            let raw: (usize, usize, f64) = result.unwrap();
-            let (a_session_id, a_item_id, a_time): (usize, usize, usize) =
-                (raw.0, raw.1, raw.2.round() as usize);
-
-            session_id.push(a_session_id);
-            item_id.push(a_item_id);
-            time.push(a_time);
-        } else {
-            eprintln!("Unable to parse input!");
-        }
-    });
-
-    // Sort by session id - the data is unsorted
-    let mut session_id_indices: Vec<usize> = (0..session_id.len()).into_iter().collect();
-    session_id_indices.sort_by_key(|&i| &session_id[i]);
-    let session_id_sorted: Vec<usize> = session_id_indices
-        .iter()
-        .map(|&i| session_id[i])
-        .collect();
-    let item_id_sorted: Vec<usize> = session_id_indices
-        .iter()
-        .map(|&i| item_id[i])
-        .collect();
-    let time_sorted: Vec<usize> = session_id_indices
-        .iter()
-        .map(|&i| time[i])
-        .collect();
-
-    // Get unique session ids
-    session_id.sort_unstable();
-    session_id.dedup();
-
-    // Create historical sessions array (deduplicated), historical sessions id array and array with max timestamps.
-    //let mut i: usize = 0;
-    let mut historical_sessions: Vec<Vec<u64>> = Vec::with_capacity(session_id.len());
-    let mut historical_sessions_id: Vec<Vec<usize>> = Vec::with_capacity(session_id.len());
-    let mut historical_sessions_max_time_stamp: Vec<u32> =
-        Vec::with_capacity(session_id.len());
-    let mut history_session: Vec<u64> = Vec::with_capacity(1000);
-    let mut history_session_id: Vec<usize> = Vec::with_capacity(1000);
-    let mut max_time_stamp: usize = time_sorted[0];
-    // Push initial session and item id
-    history_session.push(item_id_sorted[0] as u64);
-    history_session_id.push(item_id_sorted[0]);
-    // Loop over length of data
-    for i in 1..session_id_sorted.len() {
-        if session_id_sorted[i] == session_id_sorted[i - 1] {
-            if !history_session.contains(&(item_id_sorted[i] as u64)) {
-                history_session.push(item_id_sorted[i] as u64);
-                history_session_id.push(session_id_sorted[i]);
-                if time_sorted[i] > max_time_stamp {
-                    max_time_stamp = time_sorted[i];
-                }
-            }
-        } else {
-            let mut history_session_sorted = history_session.clone();
-            history_session_sorted.sort_unstable();
-            historical_sessions.push(history_session_sorted);
-            historical_sessions_id.push(history_session_id.clone());
-            historical_sessions_max_time_stamp.push(max_time_stamp as u32);
-            history_session.clear();
-            history_session_id.clear();
-            history_session.push(item_id_sorted[i] as u64);
-            history_session_id.push(session_id_sorted[i]);
-            max_time_stamp = time_sorted[i];
-        }
-    }
-
-    Ok((
-        historical_sessions,
-        historical_sessions_id,
-        historical_sessions_max_time_stamp,
-    ))
-}
-
 // Custom binary search because this is stable unlike the rust default (i.e. this always returns right-most index in case of duplicate entries instead of a random match)
 fn binary_search_right(array: &[u64], key: u64) -> Result<usize, usize> {
     let mut top: usize = array.len();
@@ -260,12 +172,12 @@ fn prepare_hashmap(
 }
 
 impl SimilarityComputationHash for VMISSkNNIndexSmallOpt {
-    fn items_for_session(&self, _session: &u32) -> &HashSet<u64, DefaultHashBuilder> {
-        unimplemented!()
+    fn items_for_session(&self, session: &u32) -> Cow<'_, [u64]> {
+        Cow::Borrowed(&self.session_items[*session as usize])
     }
 
-    fn idf(&self, _item_id: &u64) -> f64 {
-        unimplemented!()
+    fn idf(&self, item_id: &u64) -> f64 {
+        self.item_idfs[item_id]
     }
 
     fn find_neighbors(
@@ -334,3 +246,25 @@ impl SimilarityComputationHash for VMISSkNNIndexSmallOpt {
         closest_neighbors
     }
 }
+
+impl Recommender for VMISSkNNIndexSmallOpt {
+    fn recommend_with_events(
+        &self,
+        evolving_session: &[u64],
+        session_events: &SessionEvents,
+        params: &RecommendParams,
+    ) -> Vec<ItemScore> {
+        similarity_hashed::predict(
+            self,
+            evolving_session,
+            session_events,
+            params.neighborhood_size_k,
+            params.m_most_recent_sessions,
+            params.how_many,
+        )
+    }
+
+    fn kind(&self) -> RecommenderKind {
+        RecommenderKind::VmisKnnSmallOpt
+    }
+}
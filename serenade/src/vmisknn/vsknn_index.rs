@@ -1,5 +1,6 @@
 extern crate hashbrown;
 
+use std::borrow::Cow;
 use std::collections::BinaryHeap;
 
 use hashbrown::{HashMap, HashSet};
@@ -8,7 +9,10 @@ use hashbrown::hash_map::DefaultHashBuilder as RandomState;
 use itertools::Itertools;
 
 use crate::io::{ItemId, Time, TrainingSessionId};
-use crate::vmisknn::{SessionScore, SessionTime};
+use crate::recommender::{RecommendParams, Recommender, RecommenderKind};
+use crate::vmisknn::similarity_hashed;
+use crate::vmisknn::weighting::SessionEvents;
+use crate::vmisknn::{ItemScore, SessionScore, SessionTime};
 use crate::vmisknn::similarity_hashed::{idf, SimilarityComputationHash};
 
 pub struct VSkNNIndex {
@@ -19,8 +23,8 @@ pub struct VSkNNIndex {
 }
 
 impl SimilarityComputationHash for VSkNNIndex {
-    fn items_for_session(&self, session: &TrainingSessionId) -> &HashSet<ItemId, RandomState> {
-        &self.session_index[session]
+    fn items_for_session(&self, session: &TrainingSessionId) -> Cow<'_, [ItemId]> {
+        Cow::Owned(self.session_index[session].iter().copied().sorted().collect())
     }
 
     fn idf(&self, item: &ItemId) -> f64 {
@@ -61,7 +65,7 @@ impl SimilarityComputationHash for VSkNNIndex {
         for neighbor_session in most_recent_neighbors.into_iter() {
             let mut similarity = 0_f64;
 
-            let other_session_items = self.items_for_session(&neighbor_session.id);
+            let other_session_items = &self.session_index[&neighbor_session.id];
 
             //            let start_time = Instant::now();
             // Decayed dot product
@@ -194,3 +198,25 @@ impl VSkNNIndex {
         self.session_max_order[session]
     }
 }
+
+impl Recommender for VSkNNIndex {
+    fn recommend_with_events(
+        &self,
+        evolving_session: &[u64],
+        session_events: &SessionEvents,
+        params: &RecommendParams,
+    ) -> Vec<ItemScore> {
+        similarity_hashed::predict(
+            self,
+            evolving_session,
+            session_events,
+            params.neighborhood_size_k,
+            params.m_most_recent_sessions,
+            params.how_many,
+        )
+    }
+
+    fn kind(&self) -> RecommenderKind {
+        RecommenderKind::VsKnn
+    }
+}
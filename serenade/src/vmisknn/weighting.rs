@@ -0,0 +1,529 @@
+use std::error::Error;
+use std::fmt;
+use std::str::FromStr;
+
+use hashbrown::HashMap;
+
+/// Weights the items of the evolving session by their position, where position 1 is the most recent item.
+pub trait PositionWeighting: Send + Sync {
+    /// The weight of an evolving session item in the similarity to the sessions that contain it.
+    /// Only the ratios between positions matter, because the similarities of all sessions are scaled equally.
+    fn decay(&self, position: usize, session_length: usize) -> f64;
+
+    /// The weight of a neighbor session by the position of the most recent evolving session item that it contains.
+    fn match_weight(&self, position: usize) -> f64;
+}
+
+pub struct LinearWeighting;
+
+impl PositionWeighting for LinearWeighting {
+    fn decay(&self, position: usize, session_length: usize) -> f64 {
+        (session_length + 1 - position) as f64
+    }
+
+    fn match_weight(&self, position: usize) -> f64 {
+        (1.0 - 0.1 * position as f64).max(0.0)
+    }
+}
+
+pub struct LogarithmicWeighting;
+
+impl PositionWeighting for LogarithmicWeighting {
+    fn decay(&self, position: usize, _session_length: usize) -> f64 {
+        1.0 / (position as f64 + 1.0).log2()
+    }
+
+    fn match_weight(&self, position: usize) -> f64 {
+        1.0 / (position as f64 + 1.0).log2()
+    }
+}
+
+pub struct HarmonicWeighting;
+
+impl PositionWeighting for HarmonicWeighting {
+    fn decay(&self, position: usize, _session_length: usize) -> f64 {
+        1.0 / position as f64
+    }
+
+    fn match_weight(&self, position: usize) -> f64 {
+        1.0 / position as f64
+    }
+}
+
+pub struct QuadraticWeighting;
+
+impl PositionWeighting for QuadraticWeighting {
+    fn decay(&self, position: usize, session_length: usize) -> f64 {
+        ((session_length + 1 - position) as f64).powi(2)
+    }
+
+    fn match_weight(&self, position: usize) -> f64 {
+        LinearWeighting.match_weight(position).powi(2)
+    }
+}
+
+/// Halves the weight with every position.
+pub struct ExponentialWeighting;
+
+impl PositionWeighting for ExponentialWeighting {
+    fn decay(&self, position: usize, _session_length: usize) -> f64 {
+        0.5_f64.powi(position as i32 - 1)
+    }
+
+    fn match_weight(&self, position: usize) -> f64 {
+        0.5_f64.powi(position as i32 - 1)
+    }
+}
+
+/// Weights all positions equally, for sets of items without an order such as baskets.
+pub struct ConstantWeighting;
+
+impl PositionWeighting for ConstantWeighting {
+    fn decay(&self, _position: usize, _session_length: usize) -> f64 {
+        1.0
+    }
+
+    fn match_weight(&self, _position: usize) -> f64 {
+        1.0
+    }
+}
+
+#[derive(Debug)]
+pub struct WeightingError(String);
+
+impl fmt::Display for WeightingError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "{}", self.0)
+    }
+}
+
+impl Error for WeightingError {}
+
+/// The name of a `PositionWeighting`, as used in the config and in the hyperparameter search.
+#[derive(Clone, Copy, Debug, Default, PartialEq)]
+pub enum WeightingFunction {
+    #[default]
+    Linear,
+    Logarithmic,
+    Harmonic,
+    Quadratic,
+    Exponential,
+    Constant,
+}
+
+impl WeightingFunction {
+    /// The functions that prefer the recent positions, `Constant` ignores the order of the items.
+    pub const ALL: [WeightingFunction; 5] = [
+        WeightingFunction::Linear,
+        WeightingFunction::Logarithmic,
+        WeightingFunction::Harmonic,
+        WeightingFunction::Quadratic,
+        WeightingFunction::Exponential,
+    ];
+
+    pub fn position_weighting(self) -> &'static dyn PositionWeighting {
+        match self {
+            WeightingFunction::Linear => &LinearWeighting,
+            WeightingFunction::Logarithmic => &LogarithmicWeighting,
+            WeightingFunction::Harmonic => &HarmonicWeighting,
+            WeightingFunction::Quadratic => &QuadraticWeighting,
+            WeightingFunction::Exponential => &ExponentialWeighting,
+            WeightingFunction::Constant => &ConstantWeighting,
+        }
+    }
+}
+
+impl FromStr for WeightingFunction {
+    type Err = WeightingError;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        match s {
+            "linear" => Ok(WeightingFunction::Linear),
+            "logarithmic" => Ok(WeightingFunction::Logarithmic),
+            "harmonic" => Ok(WeightingFunction::Harmonic),
+            "quadratic" => Ok(WeightingFunction::Quadratic),
+            "exponential" => Ok(WeightingFunction::Exponential),
+            "constant" => Ok(WeightingFunction::Constant),
+            _ => Err(WeightingError(format!("Invalid weighting function: {}", s))),
+        }
+    }
+}
+
+impl fmt::Display for WeightingFunction {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        let name = match self {
+            WeightingFunction::Linear => "linear",
+            WeightingFunction::Logarithmic => "logarithmic",
+            WeightingFunction::Harmonic => "harmonic",
+            WeightingFunction::Quadratic => "quadratic",
+            WeightingFunction::Exponential => "exponential",
+            WeightingFunction::Constant => "constant",
+        };
+        write!(f, "{}", name)
+    }
+}
+
+/// The weighting functions of an index: `position_decay` for the neighbor similarity and `match_weight` for the
+/// item scores. The defaults are the linear functions of VMIS-kNN.
+/// With a positive `recency_half_life_secs`, the similarity of a neighbor session halves with every half-life that it is
+/// older than the most recent session of the index.
+#[derive(Clone, Copy, Debug, Default, PartialEq)]
+pub struct Weighting {
+    pub position_decay: WeightingFunction,
+    pub match_weight: WeightingFunction,
+    pub recency_half_life_secs: Option<u32>,
+}
+
+impl Weighting {
+    pub fn decay(&self, position: usize, session_length: usize) -> f64 {
+        self.position_decay
+            .position_weighting()
+            .decay(position, session_length)
+    }
+
+    pub fn match_weight(&self, position: usize) -> f64 {
+        self.match_weight.position_weighting().match_weight(position)
+    }
+
+    pub fn recency_weight(&self, session_time: u32, max_time_stamp: u32) -> f64 {
+        match self.recency_half_life_secs {
+            Some(half_life_secs) => {
+                let age_secs = max_time_stamp.saturating_sub(session_time);
+                0.5_f64.powf(age_secs as f64 / half_life_secs as f64)
+            }
+            None => 1.0,
+        }
+    }
+}
+
+/// How the items of the neighbor sessions are weighted in the item scores, computed from the `ItemCounts`.
+#[derive(Clone, Copy, Debug, Default, PartialEq)]
+pub enum ItemWeighting {
+    /// `ln(qty_sessions / qty_sessions_with_item)`
+    IdfSessions,
+    /// `ln(qty_interactions / qty_interactions_with_item)`. Counted over deduplicated sessions, as by
+    /// `OfflineIndex::new_from_sessions`, this is the idf of the index build.
+    #[default]
+    IdfInteractions,
+    /// `ln(qty_sessions / qty_interactions_with_item)`, the `idf` of the avro indexes of the Spark index build.
+    IdfSpark,
+    /// The BM25 idf over sessions, multiplied with the saturated amount of interactions per session with the item.
+    Bm25,
+    /// Every item has weight 1.
+    None,
+}
+
+/// The `k1` parameter of BM25, which limits the influence of repeated interactions with an item.
+const BM25_K1: f64 = 1.2;
+
+impl ItemWeighting {
+    pub const ALL: [ItemWeighting; 5] = [
+        ItemWeighting::IdfSessions,
+        ItemWeighting::IdfInteractions,
+        ItemWeighting::IdfSpark,
+        ItemWeighting::Bm25,
+        ItemWeighting::None,
+    ];
+
+    fn weight(self, item_count: &ItemCount, item_counts: &ItemCounts) -> f64 {
+        if item_count.qty_sessions == 0 {
+            return 0.0;
+        }
+        let qty_sessions_with_item = item_count.qty_sessions as f64;
+        match self {
+            ItemWeighting::IdfSessions => (item_counts.qty_sessions as f64 / qty_sessions_with_item).ln(),
+            ItemWeighting::IdfInteractions => {
+                (item_counts.qty_interactions as f64 / item_count.qty_interactions as f64).ln()
+            }
+            ItemWeighting::IdfSpark => (item_counts.qty_sessions as f64 / item_count.qty_interactions as f64).ln(),
+            ItemWeighting::Bm25 => {
+                let qty_sessions = item_counts.qty_sessions as f64;
+                let idf = (1.0 + (qty_sessions - qty_sessions_with_item + 0.5) / (qty_sessions_with_item + 0.5)).ln();
+                let interactions_per_session = item_count.qty_interactions as f64 / qty_sessions_with_item;
+                idf * interactions_per_session * (BM25_K1 + 1.0) / (interactions_per_session + BM25_K1)
+            }
+            ItemWeighting::None => 1.0,
+        }
+    }
+}
+
+impl FromStr for ItemWeighting {
+    type Err = WeightingError;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        match s {
+            "idf_sessions" => Ok(ItemWeighting::IdfSessions),
+            "idf_interactions" => Ok(ItemWeighting::IdfInteractions),
+            "idf_spark" => Ok(ItemWeighting::IdfSpark),
+            "bm25" => Ok(ItemWeighting::Bm25),
+            "none" => Ok(ItemWeighting::None),
+            _ => Err(WeightingError(format!("Invalid item weighting: {}", s))),
+        }
+    }
+}
+
+impl fmt::Display for ItemWeighting {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        let name = match self {
+            ItemWeighting::IdfSessions => "idf_sessions",
+            ItemWeighting::IdfInteractions => "idf_interactions",
+            ItemWeighting::IdfSpark => "idf_spark",
+            ItemWeighting::Bm25 => "bm25",
+            ItemWeighting::None => "none",
+        };
+        write!(f, "{}", name)
+    }
+}
+
+/// The occurrences of an item in the training sessions.
+#[derive(Clone, Copy, Debug, Default, PartialEq)]
+pub struct ItemCount {
+    pub qty_sessions: u32,
+    pub qty_interactions: u32,
+}
+
+/// The raw counts that the item weights are computed from, so that an index can switch its `ItemWeighting`.
+#[derive(Clone, Debug, Default)]
+pub struct ItemCounts {
+    /// Indexed like the item data of the index, zero for items without counts.
+    pub(crate) item_to_count: Vec<ItemCount>,
+    pub(crate) qty_sessions: u64,
+    pub(crate) qty_interactions: u64,
+}
+
+impl ItemCounts {
+    /// The weight of every item, zero for the items without counts.
+    pub fn item_weights(&self, item_weighting: ItemWeighting) -> Vec<f64> {
+        self.item_to_count
+            .iter()
+            .map(|item_count| item_weighting.weight(item_count, self))
+            .collect()
+    }
+}
+
+/// The weights of the event types of interactions, such as views, carts and purchases.
+/// Interactions without an event type or with an unknown event type have weight 1.
+#[derive(Clone, Debug, Default, PartialEq)]
+pub struct EventTypeWeights {
+    weights: HashMap<String, f64>,
+    excluded_event_types: Vec<String>,
+}
+
+impl EventTypeWeights {
+    /// Items of the evolving session with one of the `excluded_event_types` are not recommended.
+    pub fn new(weights: HashMap<String, f64>, excluded_event_types: Vec<String>) -> Self {
+        EventTypeWeights {
+            weights,
+            excluded_event_types,
+        }
+    }
+
+    pub fn weight(&self, event_type: Option<&str>) -> f64 {
+        event_type
+            .and_then(|event_type| self.weights.get(event_type))
+            .copied()
+            .unwrap_or(1.0)
+    }
+
+    pub fn is_excluded(&self, event_type: Option<&str>) -> bool {
+        event_type.is_some_and(|event_type| self.excluded_event_types.iter().any(|excluded| excluded == event_type))
+    }
+
+    /// Whether an item with `event_type` should replace the `previous` event type of the same item, e.g. a purchase
+    /// after a view. Excluded event types always win, so that purchased items stay excluded.
+    pub fn is_stronger(&self, event_type: Option<&str>, previous: Option<&str>) -> bool {
+        let rank = |event_type| (self.is_excluded(event_type), self.weight(event_type));
+        rank(event_type) > rank(previous)
+    }
+
+    /// The weights and the excluded items of an evolving session, with an event type for each item.
+    pub fn session_events(&self, evolving_session: &[u64], event_types: &[Option<String>]) -> SessionEvents {
+        let item_weights = event_types
+            .iter()
+            .map(|event_type| self.weight(event_type.as_deref()))
+            .collect();
+        let excluded_items = evolving_session
+            .iter()
+            .zip(event_types.iter())
+            .filter(|(_, event_type)| self.is_excluded(event_type.as_deref()))
+            .map(|(item_id, _)| *item_id)
+            .collect();
+        SessionEvents {
+            item_weights,
+            excluded_items,
+        }
+    }
+}
+
+/// Parses weights in the format `view:1,cart:3,purchase:5`.
+pub fn parse_event_type_weights(weights: &str) -> Result<HashMap<String, f64>, WeightingError> {
+    weights
+        .split(',')
+        .map(|weight| weight.trim())
+        .filter(|weight| !weight.is_empty())
+        .map(|weight| {
+            let invalid = || WeightingError(format!("Invalid event type weight: {}", weight));
+            let (event_type, value) = weight.split_once(':').ok_or_else(invalid)?;
+            let value: f64 = value.trim().parse().map_err(|_| invalid())?;
+            if !value.is_finite() || value < 0.0 {
+                return Err(invalid());
+            }
+            Ok((event_type.trim().to_string(), value))
+        })
+        .collect()
+}
+
+/// The event types of an evolving session: a weight for each of its items and the items that are not recommended.
+#[derive(Clone, Debug, Default, PartialEq)]
+pub struct SessionEvents {
+    pub item_weights: Vec<f64>,
+    pub excluded_items: Vec<u64>,
+}
+
+impl SessionEvents {
+    /// Every item has weight 1 and nothing is excluded.
+    pub fn unweighted(evolving_session: &[u64]) -> Self {
+        SessionEvents {
+            item_weights: vec![1.0; evolving_session.len()],
+            excluded_items: Vec::new(),
+        }
+    }
+}
+
+/// Extends the evolving session with the recent items of the user from past sessions, which precede the session
+/// items so that the position weighting prefers the session. Their weight is `user_history_weight` and items that
+/// are already part of the session are skipped.
+pub fn blend_user_history(
+    evolving_session: &[u64],
+    session_events: &SessionEvents,
+    user_items: &[u64],
+    user_history_weight: f64,
+) -> (Vec<u64>, SessionEvents) {
+    let mut blended_session: Vec<u64> = user_items
+        .iter()
+        .filter(|item_id| !evolving_session.contains(item_id))
+        .copied()
+        .collect();
+    let mut item_weights = vec![user_history_weight; blended_session.len()];
+    blended_session.extend_from_slice(evolving_session);
+    item_weights.extend_from_slice(&session_events.item_weights);
+    let blended_events = SessionEvents {
+        item_weights,
+        excluded_items: session_events.excluded_items.clone(),
+    };
+    (blended_session, blended_events)
+}
+
+#[cfg(test)]
+mod weighting_test {
+    use super::*;
+
+    #[test]
+    fn should_keep_linear_match_weight_non_negative() {
+        let weighting = Weighting::default();
+        assert!((weighting.match_weight(1) - 0.9).abs() < 1e-9);
+        assert!((weighting.match_weight(9) - 0.1).abs() < 1e-9);
+        for position in 10..200 {
+            assert_eq!(0.0, weighting.match_weight(position));
+        }
+    }
+
+    #[test]
+    fn should_prefer_recent_positions() {
+        for function in WeightingFunction::ALL.iter() {
+            let weighting = Weighting {
+                position_decay: *function,
+                match_weight: *function,
+                ..Weighting::default()
+            };
+            for position in 1..8 {
+                assert!(weighting.decay(position, 8) > weighting.decay(position + 1, 8), "{}", function);
+                assert!(weighting.match_weight(position) > weighting.match_weight(position + 1), "{}", function);
+            }
+            assert_eq!(*function, function.to_string().parse().unwrap());
+        }
+    }
+
+    #[test]
+    fn should_halve_the_recency_weight_per_half_life() {
+        assert_eq!(1.0, Weighting::default().recency_weight(0, 1_000_000));
+        let weighting = Weighting {
+            recency_half_life_secs: Some(3600),
+            ..Weighting::default()
+        };
+        assert_eq!(1.0, weighting.recency_weight(1_000_000, 1_000_000));
+        assert!((weighting.recency_weight(1_000_000 - 3600, 1_000_000) - 0.5).abs() < 1e-9);
+        assert!((weighting.recency_weight(1_000_000 - 7200, 1_000_000) - 0.25).abs() < 1e-9);
+    }
+
+    #[test]
+    fn should_compute_item_weights_from_counts() {
+        let item_counts = ItemCounts {
+            item_to_count: vec![
+                ItemCount { qty_sessions: 10, qty_interactions: 40 },
+                ItemCount { qty_sessions: 10, qty_interactions: 10 },
+                ItemCount::default(),
+            ],
+            qty_sessions: 100,
+            qty_interactions: 400,
+        };
+
+        let idf_sessions = item_counts.item_weights(ItemWeighting::IdfSessions);
+        assert!((idf_sessions[0] - 10.0_f64.ln()).abs() < 1e-9);
+        assert_eq!(idf_sessions[0], idf_sessions[1]);
+        let idf_interactions = item_counts.item_weights(ItemWeighting::IdfInteractions);
+        assert!((idf_interactions[0] - 10.0_f64.ln()).abs() < 1e-9);
+        assert!((idf_interactions[1] - 40.0_f64.ln()).abs() < 1e-9);
+        let idf_spark = item_counts.item_weights(ItemWeighting::IdfSpark);
+        assert!((idf_spark[0] - 2.5_f64.ln()).abs() < 1e-9);
+        assert!((idf_spark[1] - 10.0_f64.ln()).abs() < 1e-9);
+        // repeated interactions count, but saturate
+        let bm25 = item_counts.item_weights(ItemWeighting::Bm25);
+        assert!(bm25[0] > bm25[1]);
+        assert!(bm25[0] < 4.0 * bm25[1]);
+        assert_eq!(vec![1.0, 1.0], item_counts.item_weights(ItemWeighting::None)[..2].to_vec());
+
+        for item_weighting in ItemWeighting::ALL.iter() {
+            assert_eq!(0.0, item_counts.item_weights(*item_weighting)[2]);
+            assert_eq!(*item_weighting, item_weighting.to_string().parse().unwrap());
+        }
+    }
+
+    #[test]
+    fn should_weight_and_exclude_event_types() {
+        let weights = parse_event_type_weights("view:1, cart:3,purchase:5").unwrap();
+        let event_type_weights = EventTypeWeights::new(weights, vec!["purchase".to_string()]);
+        assert_eq!(3.0, event_type_weights.weight(Some("cart")));
+        assert_eq!(1.0, event_type_weights.weight(Some("wishlist")));
+        assert_eq!(1.0, event_type_weights.weight(None));
+        assert!(event_type_weights.is_stronger(Some("purchase"), Some("cart")));
+        assert!(!event_type_weights.is_stronger(Some("view"), Some("purchase")));
+
+        let events = event_type_weights.session_events(
+            &[10, 11, 12],
+            &[Some("view".to_string()), Some("purchase".to_string()), None],
+        );
+        assert_eq!(vec![1.0, 5.0, 1.0], events.item_weights);
+        assert_eq!(vec![11], events.excluded_items);
+
+        assert!(parse_event_type_weights("view=1").is_err());
+        assert!(parse_event_type_weights("view:-1").is_err());
+        assert!(parse_event_type_weights("").unwrap().is_empty());
+    }
+
+    #[test]
+    fn should_blend_the_user_history_before_the_session() {
+        let session_events = SessionEvents {
+            item_weights: vec![1.0, 3.0],
+            excluded_items: vec![5],
+        };
+        let (blended_session, blended_events) = blend_user_history(&[4, 5], &session_events, &[1, 5, 2], 0.5);
+        assert_eq!(vec![1, 2, 4, 5], blended_session);
+        assert_eq!(vec![0.5, 0.5, 1.0, 3.0], blended_events.item_weights);
+        assert_eq!(vec![5], blended_events.excluded_items);
+
+        let (unchanged_session, unchanged_events) = blend_user_history(&[4, 5], &session_events, &[], 0.5);
+        assert_eq!(vec![4, 5], unchanged_session);
+        assert_eq!(session_events, unchanged_events);
+    }
+}